@@ -1,12 +1,24 @@
 //! Implementations of the internal representation of accounts
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 
 use num_rational::Rational64;
-use serde::{Deserialize, Serialize};
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Zero};
+use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::date::{Date, ParseDateError};
+use crate::decimal::allocate_rounding;
+use crate::money::{
+    minor_units, round_money, Amount, Money, Numeric, DEFAULT_DECIMALS,
+};
 use crate::rational::ParseRationalError;
-use crate::rational::{rational_from_str, rational_to_string};
+use crate::rational::{rational_from_str, rational_to_exact_string};
+use crate::settlement::{self, settle};
+use crate::source::YamlIndex;
+use crate::split::{
+    resolve_splits, split_from_str, split_to_exact_string, split_to_string,
+    Split, SplitError,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseError {
@@ -15,12 +27,54 @@ pub enum ParseError {
     UnknownUser(String),
     RationalParsingFailed(ParseRationalError),
     UserAlreadyPresent(String),
+    /// A user is declared several times, at these positions of the users
+    DuplicateUser(String, Vec<usize>),
     UserHasData(String),
-    InvalidPurchase(usize),
-    InvalidUserId(usize),
+    InvalidPurchase(PurchaseId),
+    DuplicatePurchaseId(PurchaseId),
+    /// Every purchase id was already given
+    NoPurchaseIdLeft,
+    InvalidTransfer(usize),
+    InvalidUserId(UserId),
+    InvalidPayers(String),
+    PayersMismatch(String),
+    SplitExceedsAmount(String),
+    SharesSumToZero(String),
+    ItemsMismatch(String),
+    InvalidItem(usize),
+    InvalidAdjustment(usize),
+    InvalidDate(ParseDateError),
+    InvalidCurrency(String),
+    UnknownRate(String),
+    Overflow(String),
+    AmountTooPrecise(String),
     JsonError(String),
     YamlError(String),
+    TomlError(String),
+    CsvError(String),
     InvalidState(String),
+    /// In strict mode, a field that does not exist, by its path
+    UnknownField(String),
+    /// In strict mode, shares given to a user who does not exist
+    UnknownBeneficiary(String),
+    /// In strict mode, a user has negative shares
+    NegativeShares(String),
+    /// In strict mode, a user transfers money to themselves
+    SelfTransfer(String),
+    /// In strict mode, a transfer of this amount, which is not positive
+    NonPositiveTransfer(String),
+    /// Accounts with these different base currencies cannot be combined
+    BaseCurrencyMismatch(Option<String>, Option<String>),
+    /// An error found when parsing a purchase
+    InPurchase(PurchaseLocation, Box<ParseError>),
+    /// An error found when parsing the transfer at this position, starting
+    /// from 0
+    InTransfer(usize, Box<ParseError>),
+    /// An error, with where it occurred in the source
+    At(SourceLocation, Box<ParseError>),
+    /// Several errors found at once, such as all the unknown fields of the
+    /// accounts in strict mode
+    Several(Vec<ParseError>),
 }
 
 impl std::fmt::Display for ParseError {
@@ -41,290 +95,2255 @@ impl std::fmt::Display for ParseError {
             ParseError::UserAlreadyPresent(user) => {
                 write!(f, "Cannot insert user {} twice.", user)
             }
+            ParseError::DuplicateUser(user, positions) => {
+                let positions: Vec<String> = positions
+                    .iter()
+                    .map(|position| (position + 1).to_string())
+                    .collect();
+                write!(
+                    f,
+                    "User {} is declared several times, at positions {} of \
+                     the users.",
+                    user,
+                    positions.join(", "),
+                )
+            }
             ParseError::UserHasData(user) => write!(
                 f,
                 "Cannot remove user {}, he has paid a transaction or shares.",
                 user,
             ),
-            ParseError::InvalidPurchase(index) => {
-                write!(f, "Transaction {} does not exist.", index,)
+            ParseError::InvalidPurchase(id) => {
+                write!(f, "Transaction {} does not exist.", id,)
+            }
+            ParseError::DuplicatePurchaseId(id) => {
+                write!(f, "Several transactions have the id {}.", id,)
+            }
+            ParseError::NoPurchaseIdLeft => {
+                write!(f, "No id is left for a new transaction.")
+            }
+            ParseError::InvalidTransfer(index) => {
+                write!(f, "Transfer {} does not exist.", index,)
             }
             ParseError::InvalidUserId(index) => {
                 write!(f, "User id {} does not exist.", index,)
             }
+            ParseError::InvalidPayers(descr) => write!(
+                f,
+                "Purchase {} should have either a payer or a map of payers.",
+                descr,
+            ),
+            ParseError::PayersMismatch(descr) => write!(
+                f,
+                "The amounts paid for {} do not add up to its amount.",
+                descr,
+            ),
+            ParseError::SplitExceedsAmount(descr) => write!(
+                f,
+                "Fixed amounts and percentages of {} exceed its amount.",
+                descr,
+            ),
+            ParseError::SharesSumToZero(descr) => write!(
+                f,
+                "The shares of {} sum to zero, its cost cannot be split.",
+                descr,
+            ),
+            ParseError::ItemsMismatch(descr) => write!(
+                f,
+                "The items and adjustments of {} do not add up to its amount.",
+                descr,
+            ),
+            ParseError::InvalidItem(index) => {
+                write!(f, "Item {} does not exist.", index,)
+            }
+            ParseError::InvalidAdjustment(index) => {
+                write!(f, "Adjustment {} does not exist.", index,)
+            }
+            ParseError::InvalidDate(err) => write!(f, "{}", err),
+            ParseError::InvalidCurrency(currency) => write!(
+                f,
+                "{} is not a currency code, such as EUR or USD.",
+                currency,
+            ),
+            ParseError::UnknownRate(currency) => write!(
+                f,
+                "No exchange rate from {} to the base currency.",
+                currency,
+            ),
+            ParseError::Overflow(descr) => write!(
+                f,
+                "Computations on {} overflow, its amounts or shares are too \
+                 precise.",
+                descr,
+            ),
+            ParseError::AmountTooPrecise(descr) => write!(
+                f,
+                "The amount of {} has more decimals than its currency allows.",
+                descr,
+            ),
             ParseError::JsonError(err) => {
                 write!(f, "Json decode error: {}", err)
             }
             ParseError::YamlError(err) => {
                 write!(f, "Yaml decode error: {}", err)
             }
+            ParseError::TomlError(err) => {
+                write!(f, "Toml error: {}", err)
+            }
+            ParseError::CsvError(err) => {
+                write!(f, "Csv error: {}", err)
+            }
             ParseError::InvalidState(err) => {
                 write!(f, "App has reached and invalid state: {}", err)
             }
+            ParseError::UnknownField(path) => {
+                write!(f, "Unknown field: {}", path)
+            }
+            ParseError::UnknownBeneficiary(user) => {
+                write!(f, "Shares of unknown user: {}", user)
+            }
+            ParseError::NegativeShares(user) => {
+                write!(f, "User {} has negative shares.", user)
+            }
+            ParseError::SelfTransfer(user) => {
+                write!(f, "User {} transfers money to themselves.", user)
+            }
+            ParseError::NonPositiveTransfer(amount) => write!(
+                f,
+                "The amount of a transfer should be positive, not {}.",
+                amount,
+            ),
+            ParseError::BaseCurrencyMismatch(first, second) => {
+                let name = |currency: &Option<String>| {
+                    currency.clone().unwrap_or_else(|| "no currency".into())
+                };
+                write!(
+                    f,
+                    "Cannot combine accounts in {} with accounts in {}.",
+                    name(first),
+                    name(second),
+                )
+            }
+            ParseError::InPurchase(location, err) => {
+                write!(f, "In {}: {}", location, err)
+            }
+            ParseError::InTransfer(index, err) => {
+                write!(f, "In transfer {}: {}", index + 1, err)
+            }
+            ParseError::At(location, err) => {
+                write!(f, "{}: {}", location, err)
+            }
+            ParseError::Several(errors) => {
+                write!(f, "{} errors:", errors.len())?;
+                for err in errors {
+                    write!(f, "\n{}", err)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-impl From<serde_json::Error> for ParseError {
-    fn from(err: serde_json::Error) -> Self {
-        ParseError::JsonError(format!("{}", err))
+/// A purchase of a file, by its position and description
+#[derive(Debug, Clone, PartialEq)]
+pub struct PurchaseLocation {
+    /// The position of the purchase in the file, starting from 0
+    pub index: usize,
+    pub descr: String,
+}
+
+impl std::fmt::Display for PurchaseLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "purchase {} ({})", self.index + 1, self.descr)
     }
 }
 
-impl From<serde_yaml::Error> for ParseError {
-    fn from(err: serde_yaml::Error) -> Self {
-        ParseError::YamlError(format!("{}", err))
+/// How strictly accounts are checked when parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Unknown fields and shares of unknown users are ignored, as are
+    /// purchases whose shares sum to zero when computing balances.
+    Lenient,
+    /// Unknown fields, shares of unknown users, negative shares, purchases
+    /// whose shares sum to zero, and transfers to the payer or of amounts
+    /// which are not positive are errors.
+    Strict,
+}
+
+impl ParseError {
+    /// Attach the file the error occurred in
+    pub fn in_file(self, file: &str) -> Self {
+        match self {
+            ParseError::At(location, err) => ParseError::At(
+                SourceLocation {
+                    file: Some(file.to_string()),
+                    ..location
+                },
+                err,
+            ),
+            err => ParseError::At(
+                SourceLocation {
+                    file: Some(file.to_string()),
+                    position: None,
+                },
+                Box::new(err),
+            ),
+        }
+    }
+
+    /// The error itself, without where it occurred
+    pub fn root_cause(&self) -> &ParseError {
+        match self {
+            ParseError::InPurchase(_, err)
+            | ParseError::InTransfer(_, err)
+            | ParseError::At(_, err) => err.root_cause(),
+            err => err,
+        }
+    }
+
+    /// The file where the error occurred, if known
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            ParseError::At(location, err) => {
+                location.file.as_deref().or_else(|| err.file())
+            }
+            _ => None,
+        }
+    }
+
+    /// The line and column where the error occurred, if known
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            ParseError::At(location, err) => {
+                location.position.or_else(|| err.position())
+            }
+            _ => None,
+        }
+    }
+
+    /// Attach a position to an error of a deserializer
+    pub(crate) fn at_position(self, line: usize, column: usize) -> Self {
+        let location = SourceLocation {
+            file: None,
+            position: Some((line, column)),
+        };
+        ParseError::At(location, Box::new(self))
     }
 }
 
-impl Error for ParseError {}
+/// Where an error occurred in a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    pub file: Option<String>,
+    /// The line and column, both starting from 1
+    pub position: Option<(usize, usize)>,
+}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Purchase {
-    descr: String,
-    who: String,
-    amount: String,
-    benef_to_shares: BTreeMap<String, String>,
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.file, self.position) {
+            (Some(file), Some((line, column))) => {
+                write!(f, "{}:{}:{}", file, line, column)
+            }
+            (Some(file), None) => write!(f, "{}", file),
+            (None, Some((line, column))) => {
+                write!(f, "line {}, column {}", line, column)
+            }
+            (None, None) => write!(f, "unknown location"),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct SerializedAccounts {
-    users: Vec<String>,
-    purchases: Vec<Purchase>,
+impl From<serde_json::Error> for ParseError {
+    fn from(err: serde_json::Error) -> Self {
+        let (line, column) = (err.line(), err.column());
+        let err = ParseError::JsonError(format!("{}", err));
+        // Errors which are not about the syntax or the data are at line 0
+        if line > 0 {
+            err.at_position(line, column)
+        } else {
+            err
+        }
+    }
 }
 
-impl SerializedAccounts {
-    pub fn parse(self) -> Result<ParsedAccounts, ParseError> {
-        let mut users = self.users;
-        users.sort();
-        users.dedup();
-        let mut purchases = Vec::with_capacity(self.purchases.len());
-        for purchase in self.purchases {
-            let user_id = users
-                .binary_search(&purchase.who)
-                .or(Err(ParseError::UnknownUser(purchase.who)))?;
-            let amount = rational_from_str(&purchase.amount)
-                .map_err(|e| ParseError::RationalParsingFailed(e))?;
-            let mut benef_to_shares = vec![Rational64::new(0, 1); users.len()];
-            for (benef_id, benef) in users.iter().enumerate() {
-                if let Some(shares) = purchase.benef_to_shares.get(benef) {
-                    benef_to_shares[benef_id] = rational_from_str(shares)
-                        .map_err(|e| ParseError::RationalParsingFailed(e))?;
+impl From<toml::de::Error> for ParseError {
+    fn from(err: toml::de::Error) -> Self {
+        let mut message = format!("{}", err);
+        match err.line_col() {
+            Some((line, column)) => {
+                // The position is attached to the error instead, from 1
+                // rather than 0
+                let suffix =
+                    format!(" at line {} column {}", line + 1, column + 1);
+                if message.ends_with(&suffix) {
+                    message.truncate(message.len() - suffix.len());
                 }
+                ParseError::TomlError(message).at_position(line + 1, column + 1)
             }
-            purchases.push(ParsedPurchase {
-                descr: purchase.descr,
-                who_paid: user_id,
-                amount,
-                benef_to_shares,
-            });
+            None => ParseError::TomlError(message),
         }
-        Ok(ParsedAccounts { users, purchases })
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct ParsedPurchase {
-    pub descr: String,
-    pub who_paid: usize,
-    pub amount: Rational64,
-    benef_to_shares: Vec<Rational64>,
+impl From<toml::ser::Error> for ParseError {
+    fn from(err: toml::ser::Error) -> Self {
+        ParseError::TomlError(format!("{}", err))
+    }
 }
 
-impl ParsedPurchase {
-    pub fn benef_to_shares<'a>(
-        &'a self,
-        accounts: &'a ParsedAccounts,
-    ) -> impl Iterator<Item = (&'a str, Rational64)> {
-        self.benef_to_shares
-            .iter()
-            .enumerate()
-            .map(move |(uid, share)| (&accounts.users[uid][..], *share))
+impl From<serde_yaml::Error> for ParseError {
+    fn from(err: serde_yaml::Error) -> Self {
+        let location = err.location();
+        let err = ParseError::YamlError(format!("{}", err));
+        match location {
+            Some(location) => {
+                err.at_position(location.line(), location.column())
+            }
+            None => err,
+        }
     }
+}
+
+impl Error for ParseError {}
 
-    pub fn shares(&self) -> &[Rational64] {
-        &self.benef_to_shares[..]
+/// A stable identifier of a user. Unlike the position of the user in the
+/// accounts, it does not change when other users are added, removed or
+/// renamed.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct UserId(usize);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    pub fn set_share(
-        &mut self,
-        uid: usize,
-        share: Rational64,
-    ) -> Result<(), ParseError> {
-        if uid >= self.benef_to_shares.len() {
-            return Err(ParseError::InvalidUserId(uid));
-        }
-        self.benef_to_shares[uid] = share;
-        Ok(())
+/// A user of the accounts. Transactions refer to it by its id, so that it
+/// can be renamed without updating them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct User {
+    pub id: UserId,
+    pub name: String,
+}
+
+/// A stable identifier of a purchase, saved with it. Unlike the position of
+/// the purchase in the accounts, it does not change when other purchases are
+/// added or removed.
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Clone,
+    Copy,
+    Default,
+    Serialize,
+    Deserialize,
+)]
+#[serde(transparent)]
+pub struct PurchaseId(u64);
+
+impl std::fmt::Display for PurchaseId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Default)]
-pub struct ParsedAccounts {
-    users: Vec<String>,
-    purchases: Vec<ParsedPurchase>,
+impl std::str::FromStr for PurchaseId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        Ok(PurchaseId(id.trim().trim_start_matches('#').parse()?))
+    }
 }
 
-impl ParsedAccounts {
-    /// Deserialize from json data
-    pub fn from_json(json: &str) -> Result<Self, ParseError> {
-        SerializedAccounts::parse(serde_json::from_str(json)?)
+/// The users of the accounts in the order they were declared, indexed by
+/// name
+#[derive(Debug, Clone, Default)]
+struct Users {
+    users: Vec<User>,
+    ids: BTreeMap<String, UserId>,
+    /// The id of the next added user. It only ever goes up, so that the id
+    /// of a removed user is never given to another one.
+    next_id: usize,
+}
+
+/// Users are equal if they have the same names and ids, whichever users
+/// were removed before
+impl PartialEq for Users {
+    fn eq(&self, other: &Self) -> bool {
+        self.users == other.users
     }
+}
 
-    pub fn from_yaml_reader<R: std::io::Read>(
-        yaml: R,
-    ) -> Result<Self, ParseError> {
-        SerializedAccounts::parse(serde_yaml::from_reader(yaml)?)
+impl Users {
+    /// Users with the given names, whose ids are their positions
+    fn from_names(names: Vec<String>) -> Result<Self, ParseError> {
+        let mut positions: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (position, name) in names.iter().enumerate() {
+            positions.entry(name).or_default().push(position);
+        }
+        // Report the first duplicate of the file
+        if let Some((name, positions)) = positions
+            .into_iter()
+            .filter(|(_, positions)| positions.len() > 1)
+            .min_by_key(|(_, positions)| positions[1])
+        {
+            return Err(ParseError::DuplicateUser(name.to_string(), positions));
+        }
+        let mut users = Users::default();
+        for name in names {
+            users.push(name);
+        }
+        Ok(users)
     }
 
-    /// Return the existing users
-    pub fn users(&self) -> &[String] {
-        &self.users[..]
+    fn iter(&self) -> std::slice::Iter<'_, User> {
+        self.users.iter()
     }
 
-    pub fn purchases(&self) -> &[ParsedPurchase] {
-        &self.purchases[..]
+    /// Find the id of a user by name
+    fn find(&self, name: &str) -> Result<UserId, ParseError> {
+        self.ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| ParseError::UnknownUser(name.to_string()))
     }
 
-    /// Get a mutable reference to the selected transaction.
-    ///
-    /// For borrow-checking reasons, we also return the view on our
-    /// users.
-    pub fn purchase_mut(
-        &mut self,
-        purchase_idx: usize,
-    ) -> Result<(&mut ParsedPurchase, &[String]), ParseError> {
-        if purchase_idx >= self.purchases.len() {
-            return Err(ParseError::InvalidPurchase(purchase_idx));
-        }
-        Ok((&mut self.purchases[purchase_idx], &self.users))
+    /// Find the position of a user by id
+    fn position(&self, uid: UserId) -> Result<usize, ParseError> {
+        self.users
+            .iter()
+            .position(|user| user.id == uid)
+            .ok_or(ParseError::InvalidUserId(uid))
     }
 
-    /// Compute the balance for each user
-    pub fn user_balances(&self) -> Vec<Rational64> {
-        let zero = Rational64::new(0, 1);
-        let mut balances = vec![zero; self.users.len()];
-        for purchase in &self.purchases {
-            let total_shares: Rational64 =
-                purchase.benef_to_shares.iter().sum();
-            if total_shares == zero {
-                eprintln!(
-                    "Warning, transaction {:?} is ignored: shares sum to zero",
-                    purchase,
-                );
-                continue;
-            }
-            for (user_id, share) in purchase.benef_to_shares.iter().enumerate()
-            {
-                balances[user_id] -= purchase.amount * share / total_shares;
-            }
-            balances[purchase.who_paid] += purchase.amount;
-        }
-        balances
+    /// Add a user after the others, whose name must not be present yet
+    fn push(&mut self, name: String) -> UserId {
+        let id = UserId(self.next_id);
+        self.next_id += 1;
+        self.ids.insert(name.clone(), id);
+        self.users.push(User { id, name });
+        id
     }
 
-    pub fn print_balances(&self, nb_max_decimals: u8) {
-        let balances = self.user_balances();
-        for (user, balance) in self.users.iter().zip(&balances) {
-            println!(
-                "{} has a balance of: {}",
-                user,
-                rational_to_string(*balance, nb_max_decimals),
-            );
-        }
+    fn remove(&mut self, uid: UserId) -> Result<(), ParseError> {
+        let user = self.users.remove(self.position(uid)?);
+        self.ids.remove(&user.name);
+        Ok(())
     }
 
-    /// Add a new user to the accounts. Its shares in all existing transactions
-    /// will be zero.
-    pub fn add_user(&mut self, user: String) -> Result<(), ParseError> {
-        if user.len() == 0 {
-            return Err(ParseError::EmptyUser);
-        }
-        let loc = self.users.binary_search(&user);
-        let index = match loc {
-            Ok(_) => return Err(ParseError::UserAlreadyPresent(user)),
-            Err(index) => index,
-        };
-        self.users.insert(index, user);
-        let zero = Rational64::new(0, 1);
-        for purchase in self.purchases.iter_mut() {
-            purchase.benef_to_shares.insert(index, zero);
-            if purchase.who_paid >= index {
-                purchase.who_paid += 1;
-            }
-        }
+    /// Rename a user, whose new name must not be present yet
+    fn rename(&mut self, uid: UserId, name: String) -> Result<(), ParseError> {
+        let position = self.position(uid)?;
+        self.ids.remove(&self.users[position].name);
+        self.ids.insert(name.clone(), uid);
+        self.users[position].name = name;
         Ok(())
     }
+}
 
-    pub fn remove_user(&mut self, user: String) -> Result<(), ParseError> {
-        let loc = self.users.binary_search(&user);
-        let index = match loc {
-            Err(_) => return Err(ParseError::UnknownUser(user)),
-            Ok(index) => index,
-        };
-        for purchase in self.purchases.iter() {
-            if purchase.who_paid == index
-                || purchase.benef_to_shares[index] > 0.into()
-            {
-                return Err(ParseError::UserHasData(user));
-            }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Purchase {
+    /// Purchases without id, eg written by hand, are given one when the
+    /// accounts are parsed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<PurchaseId>,
+    descr: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    who: Option<String>,
+    /// Currency of the amounts of the purchase, the base currency if none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<String>,
+    /// Value of one unit of the currency in the base currency, overriding
+    /// the rates of the accounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    payers: BTreeMap<String, String>,
+    amount: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    benef_to_shares: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    items: Vec<Item>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    adjustments: Vec<Adjustment>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Item {
+    descr: String,
+    amount: String,
+    benef_to_shares: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Adjustment {
+    kind: AdjustmentKind,
+    amount: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Transfer {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    descr: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    from: String,
+    to: String,
+    amount: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SerializedAccounts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base_currency: Option<String>,
+    /// Value of one unit of each currency in the base currency
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    rates: BTreeMap<String, String>,
+    users: Vec<String>,
+    purchases: Vec<Purchase>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    transfers: Vec<Transfer>,
+}
+
+impl SerializedAccounts {
+    pub fn parse(self) -> Result<ParsedAccounts, ParseError> {
+        self.parse_with(ParseMode::Lenient)
+    }
+
+    /// Deserialize accounts, failing on unknown fields in strict mode
+    fn deserialize_with<'de, D>(
+        deserializer: D,
+        mode: ParseMode,
+    ) -> Result<Self, ParseError>
+    where
+        D: Deserializer<'de>,
+        ParseError: From<D::Error>,
+    {
+        if mode == ParseMode::Lenient {
+            return Ok(Self::deserialize(deserializer)?);
         }
-        self.users.remove(index);
-        for purchase in self.purchases.iter_mut() {
-            purchase.benef_to_shares.remove(index);
-            if purchase.who_paid >= index {
-                purchase.who_paid -= 1;
-            }
+        let mut unknown_fields = Vec::new();
+        let accounts: Self =
+            serde_ignored::deserialize(deserializer, |path| {
+                unknown_fields.push((purchase_index(&path), path.to_string()))
+            })?;
+        // All the unknown fields are reported, so that they can be fixed in
+        // one go
+        let mut errors: Vec<ParseError> = unknown_fields
+            .into_iter()
+            .map(|(index, path)| {
+                let err = ParseError::UnknownField(path);
+                match index {
+                    Some(index) => ParseError::InPurchase(
+                        PurchaseLocation {
+                            index,
+                            descr: accounts.purchases[index].descr.clone(),
+                        },
+                        Box::new(err),
+                    ),
+                    None => err,
+                }
+            })
+            .collect();
+        match errors.len() {
+            0 => Ok(accounts),
+            1 => Err(errors.remove(0)),
+            _ => Err(ParseError::Several(errors)),
         }
-        Ok(())
     }
 
-    /// Add a purchase to the accounts, with empty shares. The purchase shares
-    /// shall be filled-in later.
-    ///
-    /// On success, returns the index of the added purchase.
-    pub fn add_purchase(
+    /// The accounts without their purchases and transfers
+    pub(crate) fn without_transactions(&self) -> Self {
+        SerializedAccounts {
+            base_currency: self.base_currency.clone(),
+            rates: self.rates.clone(),
+            users: self.users.clone(),
+            purchases: Vec::new(),
+            transfers: Vec::new(),
+        }
+    }
+
+    /// Add a purchase paid by `who` alone, in the base currency
+    pub(crate) fn push_purchase(
         &mut self,
         descr: String,
-        who_paid: String,
-        amount: Rational64,
-    ) -> Result<usize, ParseError> {
-        if who_paid.len() == 0 {
-            return Err(ParseError::EmptyUser);
-        }
-        if descr.len() == 0 {
-            return Err(ParseError::EmptyDescr);
-        }
-        let loc = self.users.binary_search(&who_paid);
-        let who_paid = match loc {
-            Err(_) => return Err(ParseError::UnknownUser(who_paid)),
-            Ok(index) => index,
-        };
-        self.purchases.push(ParsedPurchase {
+        who: String,
+        amount: String,
+        benef_to_shares: BTreeMap<String, String>,
+    ) {
+        self.purchases.push(Purchase {
+            id: None,
             descr,
-            who_paid,
+            date: None,
+            who: Some(who),
+            currency: None,
+            rate: None,
+            category: None,
+            tags: Vec::new(),
+            payers: BTreeMap::new(),
             amount,
-            benef_to_shares: vec![0.into(); self.users.len()],
+            benef_to_shares,
+            items: Vec::new(),
+            adjustments: Vec::new(),
         });
-        Ok(self.purchases.len() - 1)
     }
 
-    /// Modify the shares for the requested user in a selected purchase
-    pub fn set_purchase_user_share(
-        &mut self,
-        purchase_idx: usize,
-        user: String,
-        share: Rational64,
-    ) -> Result<(), ParseError> {
-        let loc = self.users.binary_search(&user);
-        let user = match loc {
-            Err(_) => return Err(ParseError::UnknownUser(user)),
-            Ok(index) => index,
+    pub fn parse_with(
+        self,
+        mode: ParseMode,
+    ) -> Result<ParsedAccounts, ParseError> {
+        let users = Users::from_names(self.users)?;
+        let base_currency =
+            self.base_currency.map(parse_currency).transpose()?;
+        let mut purchase_ids = BTreeSet::new();
+        for id in self.purchases.iter().filter_map(|purchase| purchase.id) {
+            if !purchase_ids.insert(id) {
+                return Err(ParseError::DuplicatePurchaseId(id));
+            }
+        }
+        let mut last_purchase_id =
+            purchase_ids.iter().max().copied().unwrap_or_default();
+        let mut purchases = Vec::with_capacity(self.purchases.len());
+        for (index, purchase) in self.purchases.into_iter().enumerate() {
+            let location = PurchaseLocation {
+                index,
+                descr: purchase.descr.clone(),
+            };
+            let id = match purchase.id {
+                Some(id) => Ok(id),
+                None => next_purchase_id([&last_purchase_id]),
+            };
+            let purchase = id
+                .and_then(|id| {
+                    parse_purchase(purchase, id, &users, &base_currency, mode)
+                })
+                .map_err(|err| {
+                    ParseError::InPurchase(location, Box::new(err))
+                })?;
+            last_purchase_id = last_purchase_id.max(purchase.id);
+            purchases.push(purchase);
+        }
+        let mut transfers = Vec::with_capacity(self.transfers.len());
+        for (index, transfer) in self.transfers.into_iter().enumerate() {
+            let transfer = parse_transfer(transfer, &users, mode)
+                .map_err(|err| ParseError::InTransfer(index, Box::new(err)))?;
+            transfers.push(transfer);
+        }
+        let mut rates = BTreeMap::new();
+        for (currency, rate) in self.rates {
+            let rate = rational_from_str(&rate)
+                .map_err(ParseError::RationalParsingFailed)?;
+            rates.insert(parse_currency(currency)?, rate);
+        }
+        let accounts = ParsedAccounts {
+            users,
+            purchases,
+            last_purchase_id,
+            transfers,
+            base_currency,
+            rates,
         };
-        if purchase_idx >= self.purchases.len() {
-            return Err(ParseError::InvalidPurchase(purchase_idx));
+        for (index, purchase) in accounts.purchases.iter().enumerate() {
+            accounts.purchase_rate(purchase).map_err(|err| {
+                let location = PurchaseLocation {
+                    index,
+                    descr: purchase.descr.clone(),
+                };
+                ParseError::InPurchase(location, Box::new(err))
+            })?;
         }
-        self.purchases[purchase_idx].benef_to_shares[user] = share;
+        accounts.user_balances()?;
+        Ok(accounts)
+    }
+}
+
+/// Parse a purchase of the file, whose id is `id`
+fn parse_purchase(
+    purchase: Purchase,
+    id: PurchaseId,
+    users: &Users,
+    base_currency: &Option<String>,
+    mode: ParseMode,
+) -> Result<ParsedPurchase, ParseError> {
+    if mode == ParseMode::Strict {
+        check_splits(users, &purchase.benef_to_shares)?;
+        for item in &purchase.items {
+            check_splits(users, &item.benef_to_shares)?;
+        }
+    }
+    let amount = rational_from_str(&purchase.amount)
+        .map_err(ParseError::RationalParsingFailed)?;
+    let mut payers = BTreeMap::new();
+    match (purchase.who, purchase.payers.len()) {
+        (Some(who), 0) => {
+            set_paid(&mut payers, users.find(&who)?, amount);
+        }
+        (None, nb_payers) if nb_payers > 0 => {
+            for (who, paid) in purchase.payers {
+                let paid = rational_from_str(&paid)
+                    .map_err(ParseError::RationalParsingFailed)?;
+                set_paid(&mut payers, users.find(&who)?, paid);
+            }
+        }
+        _ => return Err(ParseError::InvalidPayers(purchase.descr)),
+    }
+    let benef_to_shares = parse_splits(users, &purchase.benef_to_shares)?;
+    let mut items = Vec::with_capacity(purchase.items.len());
+    for item in purchase.items {
+        items.push(ParsedItem {
+            benef_to_shares: parse_splits(users, &item.benef_to_shares)?,
+            descr: item.descr,
+            amount: rational_from_str(&item.amount)
+                .map_err(ParseError::RationalParsingFailed)?,
+        });
+    }
+    let mut adjustments = Vec::with_capacity(purchase.adjustments.len());
+    for adjustment in purchase.adjustments {
+        adjustments.push(ParsedAdjustment {
+            kind: adjustment.kind,
+            amount: rational_from_str(&adjustment.amount)
+                .map_err(ParseError::RationalParsingFailed)?,
+        });
+    }
+    let rate = match purchase.rate {
+        Some(rate) => Some(
+            rational_from_str(&rate)
+                .map_err(ParseError::RationalParsingFailed)?,
+        ),
+        None => None,
+    };
+    // Purchases without currency are in the base currency
+    let currency = match purchase.currency {
+        Some(currency) => Some(parse_currency(currency)?),
+        None => base_currency.clone(),
+    };
+    let purchase = ParsedPurchase {
+        id,
+        date: parse_date(purchase.date)?,
+        rate,
+        category: purchase.category,
+        tags: purchase.tags,
+        descr: purchase.descr,
+        payers,
+        amount: Money::new(amount, currency),
+        benef_to_shares,
+        items,
+        adjustments,
+    };
+    purchase.check_amount()?;
+    purchase.check_payers()?;
+    match purchase.per_user_costs() {
+        Err(ParseError::SplitExceedsAmount(descr)) => {
+            return Err(ParseError::SplitExceedsAmount(descr));
+        }
+        Err(ParseError::ItemsMismatch(descr)) => {
+            return Err(ParseError::ItemsMismatch(descr));
+        }
+        Err(ParseError::Overflow(descr)) => {
+            return Err(ParseError::Overflow(descr));
+        }
+        Err(err @ ParseError::SharesSumToZero(_))
+            if mode == ParseMode::Strict =>
+        {
+            return Err(err);
+        }
+        // Purchases whose shares sum to zero are not an error in
+        // lenient mode, they are ignored when computing balances.
+        _ => (),
+    }
+    Ok(purchase)
+}
+
+/// Parse a transfer of the file
+fn parse_transfer(
+    transfer: Transfer,
+    users: &Users,
+    mode: ParseMode,
+) -> Result<ParsedTransfer, ParseError> {
+    let from = users.find(&transfer.from)?;
+    let to = users.find(&transfer.to)?;
+    let amount = rational_from_str(&transfer.amount)
+        .map_err(ParseError::RationalParsingFailed)?;
+    if mode == ParseMode::Strict {
+        if from == to {
+            return Err(ParseError::SelfTransfer(transfer.from));
+        }
+        if amount <= 0.into() {
+            return Err(ParseError::NonPositiveTransfer(transfer.amount));
+        }
+    }
+    Ok(ParsedTransfer {
+        from,
+        to,
+        amount,
+        date: parse_date(transfer.date)?,
+        descr: transfer.descr,
+    })
+}
+
+/// The id following the greatest of `ids`, ids start at 1
+fn next_purchase_id<'a>(
+    ids: impl IntoIterator<Item = &'a PurchaseId>,
+) -> Result<PurchaseId, ParseError> {
+    let last = ids.into_iter().map(|id| id.0).max().unwrap_or(0);
+    last.checked_add(1)
+        .map(PurchaseId)
+        .ok_or(ParseError::NoPurchaseIdLeft)
+}
+
+/// Check that a currency is an ISO 4217 code, ie three uppercase letters
+fn parse_currency(currency: String) -> Result<String, ParseError> {
+    if currency.len() == 3 && currency.bytes().all(|c| c.is_ascii_uppercase()) {
+        Ok(currency)
+    } else {
+        Err(ParseError::InvalidCurrency(currency))
+    }
+}
+
+fn parse_date(date: Option<String>) -> Result<Option<Date>, ParseError> {
+    date.map(|date| date.parse().map_err(ParseError::InvalidDate))
+        .transpose()
+}
+
+/// Parse a map from user names to splits into a map from user ids to
+/// splits.
+fn parse_splits(
+    users: &Users,
+    benef_to_shares: &BTreeMap<String, String>,
+) -> Result<BTreeMap<UserId, Split>, ParseError> {
+    let mut splits = BTreeMap::new();
+    for benef in users.iter() {
+        if let Some(shares) = benef_to_shares.get(&benef.name) {
+            let split = split_from_str(shares)
+                .map_err(ParseError::RationalParsingFailed)?;
+            set_split(&mut splits, benef.id, split);
+        }
+    }
+    Ok(splits)
+}
+
+/// Check that the splits of a serialized purchase or item only concern
+/// existing users, and that shares are not negative
+fn check_splits(
+    users: &Users,
+    benef_to_shares: &BTreeMap<String, String>,
+) -> Result<(), ParseError> {
+    for (benef, shares) in benef_to_shares {
+        users
+            .find(benef)
+            .map_err(|_| ParseError::UnknownBeneficiary(benef.clone()))?;
+        let split = split_from_str(shares)
+            .map_err(ParseError::RationalParsingFailed)?;
+        if let Split::Shares(shares) = split {
+            if shares < 0.into() {
+                return Err(ParseError::NegativeShares(benef.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The position of the purchase a field of the serialized accounts belongs
+/// to, if any
+fn purchase_index(path: &serde_ignored::Path) -> Option<usize> {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => None,
+        Path::Seq {
+            parent:
+                Path::Map {
+                    parent: Path::Root,
+                    key,
+                },
+            index,
+        } if key == "purchases" => Some(*index),
+        Path::Seq { parent, .. }
+        | Path::Map { parent, .. }
+        | Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => purchase_index(parent),
+    }
+}
+
+/// Set the split of a user. Users owing nothing are not stored.
+fn set_split(splits: &mut BTreeMap<UserId, Split>, uid: UserId, split: Split) {
+    if split.is_zero() {
+        splits.remove(&uid);
+    } else {
+        splits.insert(uid, split);
+    }
+}
+
+/// Set the amount paid by a user. Users who paid nothing are not stored,
+/// unless they are the only payer, so that a purchase whose amount is zero
+/// still knows who paid for it.
+fn set_paid(
+    payers: &mut BTreeMap<UserId, Rational64>,
+    uid: UserId,
+    paid: Rational64,
+) {
+    if paid.is_zero() {
+        payers.remove(&uid);
+        if payers.is_empty() {
+            payers.insert(uid, paid);
+        }
+    } else {
+        payers.retain(|_, paid| !paid.is_zero());
+        payers.insert(uid, paid);
+    }
+}
+
+/// Kinds of adjustments of an itemized purchase
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdjustmentKind {
+    Tax,
+    Tip,
+    Discount,
+}
+
+impl std::fmt::Display for AdjustmentKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdjustmentKind::Tax => write!(f, "tax"),
+            AdjustmentKind::Tip => write!(f, "tip"),
+            AdjustmentKind::Discount => write!(f, "discount"),
+        }
+    }
+}
+
+/// A line of an itemized purchase, with its own beneficiaries
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedItem {
+    pub descr: String,
+    pub amount: Rational64,
+    benef_to_shares: BTreeMap<UserId, Split>,
+}
+
+impl ParsedItem {
+    /// The splits of the beneficiaries of the item
+    pub fn splits(&self) -> &BTreeMap<UserId, Split> {
+        &self.benef_to_shares
+    }
+
+    pub fn set_split(&mut self, uid: UserId, split: Split) {
+        set_split(&mut self.benef_to_shares, uid, split);
+    }
+}
+
+/// A tax, tip or discount of an itemized purchase. It is spread on the
+/// beneficiaries pro rata of what they owe for the items.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedAdjustment {
+    pub kind: AdjustmentKind,
+    /// The amount of the adjustment. Discounts are deducted.
+    pub amount: Rational64,
+}
+
+impl ParsedAdjustment {
+    /// The amount added to the purchase by this adjustment
+    pub fn signed_amount(&self) -> Rational64 {
+        match self.kind {
+            AdjustmentKind::Tax | AdjustmentKind::Tip => self.amount,
+            AdjustmentKind::Discount => -self.amount,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedPurchase {
+    id: PurchaseId,
+    pub descr: String,
+    /// The amount paid by each user who paid
+    payers: BTreeMap<UserId, Rational64>,
+    /// The amount, in the currency of the purchase. Its other amounts are
+    /// in the same currency.
+    pub amount: Money,
+    benef_to_shares: BTreeMap<UserId, Split>,
+    /// If not empty, the purchase is itemized and its cost is split
+    /// according to its items and adjustments rather than its own splits.
+    items: Vec<ParsedItem>,
+    adjustments: Vec<ParsedAdjustment>,
+    pub date: Option<Date>,
+    /// Exchange rate to the base currency overriding the accounts' rates
+    pub rate: Option<Rational64>,
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl ParsedPurchase {
+    /// The stable id of the purchase
+    pub fn id(&self) -> PurchaseId {
+        self.id
+    }
+
+    /// The currency of the amounts of the purchase
+    pub fn currency(&self) -> Option<&str> {
+        self.amount.currency()
+    }
+
+    /// The amount paid by each user who paid for this purchase
+    pub fn payers(&self) -> &BTreeMap<UserId, Rational64> {
+        &self.payers
+    }
+
+    /// The amount paid by a user
+    pub fn paid_by(&self, uid: UserId) -> Rational64 {
+        self.payers.get(&uid).copied().unwrap_or_else(|| 0.into())
+    }
+
+    /// If only one user paid for this purchase, return its user id.
+    pub fn single_payer(&self) -> Option<UserId> {
+        let mut payers = self.payers.keys();
+        match (payers.next(), payers.next()) {
+            (Some(uid), None) => Some(*uid),
+            _ => None,
+        }
+    }
+
+    /// Set the amount paid by a user. The amount of the purchase is updated
+    /// to the total paid by all users.
+    pub fn set_payer_amount(&mut self, uid: UserId, paid: Rational64) {
+        set_paid(&mut self.payers, uid, paid);
+        self.amount.set_value(self.payers.values().sum());
+    }
+
+    /// Make the selected user the only one having paid for this purchase.
+    pub fn set_single_payer(&mut self, uid: UserId) {
+        self.payers.clear();
+        set_paid(&mut self.payers, uid, self.amount.value());
+    }
+
+    /// Change the amount of the purchase. If only one user paid for
+    /// the purchase, its payment is updated accordingly, otherwise the
+    /// payments should be updated to match the new amount.
+    pub fn set_amount(&mut self, amount: Rational64) {
+        if let Some(uid) = self.single_payer() {
+            set_paid(&mut self.payers, uid, amount);
+        }
+        self.amount.set_value(amount);
+    }
+
+    /// Check that the amounts paid by users add up to the amount of
+    /// the purchase.
+    pub fn check_payers(&self) -> Result<(), ParseError> {
+        let total_paid = self
+            .payers
+            .values()
+            .try_fold(Amount::zero(), |total, paid| {
+                total.checked_add(&Amount::from_rational(*paid))
+            })
+            .ok_or_else(|| ParseError::Overflow(self.descr.clone()))?;
+        if total_paid != Amount::from_rational(self.amount.value()) {
+            return Err(ParseError::PayersMismatch(self.descr.clone()));
+        }
+        Ok(())
+    }
+
+    /// Check that the amount is a whole number of minor units of its
+    /// currency
+    pub fn check_amount(&self) -> Result<(), ParseError> {
+        if !self.amount.is_whole() {
+            return Err(ParseError::AmountTooPrecise(self.descr.clone()));
+        }
+        Ok(())
+    }
+
+    /// The name and the split of every user of the accounts, in the order
+    /// of `ParsedAccounts::users`
+    pub fn benef_to_shares<'a>(
+        &'a self,
+        accounts: &'a ParsedAccounts,
+    ) -> impl Iterator<Item = (&'a str, Split)> {
+        accounts.users.iter().map(move |user| {
+            let split = self.splits().get(&user.id).copied();
+            (&user.name[..], split.unwrap_or_default())
+        })
+    }
+
+    /// The splits of the beneficiaries of the purchase
+    pub fn splits(&self) -> &BTreeMap<UserId, Split> {
+        &self.benef_to_shares
+    }
+
+    /// Set the number of shares of the user in this purchase
+    pub fn set_share(&mut self, uid: UserId, share: Rational64) {
+        self.set_split(uid, Split::Shares(share))
+    }
+
+    pub fn set_split(&mut self, uid: UserId, split: Split) {
+        set_split(&mut self.benef_to_shares, uid, split);
+    }
+
+    pub fn is_itemized(&self) -> bool {
+        !self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[ParsedItem] {
+        &self.items[..]
+    }
+
+    pub fn adjustments(&self) -> &[ParsedAdjustment] {
+        &self.adjustments[..]
+    }
+
+    /// Add an item, with empty shares, to this purchase. Its amount is not
+    /// updated, see `set_amount_from_lines`.
+    ///
+    /// Returns the index of the added item.
+    pub fn add_item(&mut self, descr: String, amount: Rational64) -> usize {
+        self.items.push(ParsedItem {
+            descr,
+            amount,
+            benef_to_shares: BTreeMap::new(),
+        });
+        self.items.len() - 1
+    }
+
+    pub fn item_mut(
+        &mut self,
+        item_idx: usize,
+    ) -> Result<&mut ParsedItem, ParseError> {
+        self.items
+            .get_mut(item_idx)
+            .ok_or(ParseError::InvalidItem(item_idx))
+    }
+
+    pub fn remove_item(&mut self, item_idx: usize) -> Result<(), ParseError> {
+        if item_idx >= self.items.len() {
+            return Err(ParseError::InvalidItem(item_idx));
+        }
+        self.items.remove(item_idx);
+        Ok(())
+    }
+
+    /// Add an adjustment to this purchase. Its amount is not updated, see
+    /// `set_amount_from_lines`.
+    ///
+    /// Returns the index of the added adjustment.
+    pub fn add_adjustment(
+        &mut self,
+        kind: AdjustmentKind,
+        amount: Rational64,
+    ) -> usize {
+        self.adjustments.push(ParsedAdjustment { kind, amount });
+        self.adjustments.len() - 1
+    }
+
+    pub fn adjustment_mut(
+        &mut self,
+        adjustment_idx: usize,
+    ) -> Result<&mut ParsedAdjustment, ParseError> {
+        self.adjustments
+            .get_mut(adjustment_idx)
+            .ok_or(ParseError::InvalidAdjustment(adjustment_idx))
+    }
+
+    pub fn remove_adjustment(
+        &mut self,
+        adjustment_idx: usize,
+    ) -> Result<(), ParseError> {
+        if adjustment_idx >= self.adjustments.len() {
+            return Err(ParseError::InvalidAdjustment(adjustment_idx));
+        }
+        self.adjustments.remove(adjustment_idx);
+        Ok(())
+    }
+
+    /// The total of the items and adjustments of this purchase
+    pub fn lines_total(&self) -> Result<Amount, ParseError> {
+        self.items
+            .iter()
+            .map(|item| item.amount)
+            .chain(self.adjustments.iter().map(ParsedAdjustment::signed_amount))
+            .try_fold(Amount::zero(), |total, amount| {
+                total.checked_add(&Amount::from_rational(amount))
+            })
+            .ok_or_else(|| ParseError::Overflow(self.descr.clone()))
+    }
+
+    /// Set the amount of an itemized purchase to the total of its items
+    /// and adjustments.
+    pub fn set_amount_from_lines(&mut self) -> Result<(), ParseError> {
+        let total = self
+            .lines_total()?
+            .to_rational()
+            .ok_or_else(|| ParseError::Overflow(self.descr.clone()))?;
+        self.set_amount(total);
+        Ok(())
+    }
+
+    /// Round what each user owes for this purchase to the requested number
+    /// of decimals, such that the rounded costs add up to the rounded
+    /// amount.
+    pub fn rounded_per_user_costs(
+        &self,
+        nb_max_decimals: u8,
+    ) -> Result<BTreeMap<UserId, Rational64>, ParseError> {
+        let costs = self.per_user_costs()?;
+        let values: Vec<Amount> = costs.values().cloned().collect();
+        let rounded = allocate_rounding(&values, nb_max_decimals)
+            .ok_or_else(|| ParseError::Overflow(self.descr.clone()))?;
+        Ok(costs.keys().copied().zip(rounded).collect())
+    }
+
+    /// Compute how much each beneficiary of this purchase owes.
+    ///
+    /// For itemized purchases, this is what each user owes for each item,
+    /// plus the adjustments spread pro rata of the items.
+    pub fn per_user_costs(
+        &self,
+    ) -> Result<BTreeMap<UserId, Amount>, ParseError> {
+        let overflow = || ParseError::Overflow(self.descr.clone());
+        let split_error = |err| match err {
+            SplitError::ExceedsAmount => {
+                ParseError::SplitExceedsAmount(self.descr.clone())
+            }
+            SplitError::SharesSumToZero => {
+                ParseError::SharesSumToZero(self.descr.clone())
+            }
+            SplitError::Overflow => overflow(),
+        };
+        if !self.is_itemized() {
+            return resolve_user_splits(
+                self.amount.value(),
+                &self.benef_to_shares,
+            )
+            .map_err(split_error);
+        }
+        let amount = Amount::from_rational(self.amount.value());
+        if self.lines_total()? != amount {
+            return Err(ParseError::ItemsMismatch(self.descr.clone()));
+        }
+        let mut costs = BTreeMap::new();
+        let mut items_total = Amount::zero();
+        for item in &self.items {
+            let item_costs =
+                resolve_user_splits(item.amount, &item.benef_to_shares)
+                    .map_err(split_error)?;
+            for (uid, item_cost) in item_costs {
+                let cost = costs.entry(uid).or_insert_with(Amount::zero);
+                *cost = cost.checked_add(&item_cost).ok_or_else(overflow)?;
+            }
+            items_total = items_total
+                .checked_add(&Amount::from_rational(item.amount))
+                .ok_or_else(overflow)?;
+        }
+        if items_total.is_zero() {
+            return Err(ParseError::SharesSumToZero(self.descr.clone()));
+        }
+        let adjustments_ratio =
+            amount.checked_div(&items_total).ok_or_else(overflow)?;
+        for cost in costs.values_mut() {
+            *cost =
+                cost.checked_mul(&adjustments_ratio).ok_or_else(overflow)?;
+        }
+        Ok(costs)
+    }
+}
+
+/// Compute how much each beneficiary owes, see `resolve_splits`
+fn resolve_user_splits(
+    amount: Rational64,
+    splits: &BTreeMap<UserId, Split>,
+) -> Result<BTreeMap<UserId, Amount>, SplitError> {
+    let values: Vec<Split> = splits.values().copied().collect();
+    let costs = resolve_splits(amount, &values)?;
+    Ok(splits.keys().copied().zip(costs).collect())
+}
+
+/// Money given directly by a user to another one, typically to pay back
+/// what is owed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParsedTransfer {
+    pub descr: String,
+    pub from: UserId,
+    pub to: UserId,
+    pub amount: Rational64,
+    pub date: Option<Date>,
+}
+
+/// A reference to a purchase of the accounts, by id, or to a transfer, by
+/// index
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TransactionRef {
+    Purchase(PurchaseId),
+    Transfer(usize),
+}
+
+/// The balances of the users right after a transaction
+#[derive(Debug, PartialEq, Clone)]
+pub struct BalanceStep {
+    pub transaction: TransactionRef,
+    pub date: Option<Date>,
+    /// The balance of each user, in the order of `ParsedAccounts::users`
+    pub balances: Vec<Amount>,
+}
+
+/// How purchases are grouped in spending reports
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReportKey {
+    Category,
+    /// A purchase with several tags counts in each of them
+    Tag,
+}
+
+impl std::str::FromStr for ReportKey {
+    type Err = String;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        match key {
+            "category" => Ok(ReportKey::Category),
+            "tag" => Ok(ReportKey::Tag),
+            _ => Err(format!("Cannot report by {}, use category or tag", key)),
+        }
+    }
+}
+
+/// What each user consumed and paid in a group of purchases, in the base
+/// currency
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpendingReport {
+    /// The category or the tag of the purchases, none for purchases without
+    /// any
+    pub key: Option<String>,
+    /// What each user consumed, in the order of `ParsedAccounts::users`
+    pub consumed: Vec<Amount>,
+    /// What each user paid, in the order of `ParsedAccounts::users`
+    pub paid: Vec<Amount>,
+}
+
+/// Add the effect of a valid purchase, whose amounts are multiplied by
+/// `rate`, to the balances.
+fn apply_purchase(
+    purchase: &ParsedPurchase,
+    rate: Rational64,
+    balances: &mut BTreeMap<UserId, Amount>,
+) -> Result<(), ParseError> {
+    let costs = purchase.per_user_costs()?;
+    let overflow = || ParseError::Overflow(purchase.descr.clone());
+    let rate = Amount::from_rational(rate);
+    for (uid, cost) in costs {
+        let cost = cost.checked_mul(&rate).ok_or_else(overflow)?;
+        let balance = balances.entry(uid).or_insert_with(Amount::zero);
+        *balance = balance.checked_sub(&cost).ok_or_else(overflow)?;
+    }
+    for (uid, paid) in &purchase.payers {
+        let paid = Amount::from_rational(*paid)
+            .checked_mul(&rate)
+            .ok_or_else(overflow)?;
+        let balance = balances.entry(*uid).or_insert_with(Amount::zero);
+        *balance = balance.checked_add(&paid).ok_or_else(overflow)?;
+    }
+    Ok(())
+}
+
+/// Format an optional date to be printed before a transaction
+fn date_prefix(date: Option<Date>) -> String {
+    date.map(|date| format!("{} ", date)).unwrap_or_default()
+}
+
+fn apply_transfer(
+    transfer: &ParsedTransfer,
+    balances: &mut BTreeMap<UserId, Amount>,
+) -> Result<(), ParseError> {
+    let overflow = || ParseError::Overflow(transfer.descr.clone());
+    let amount = Amount::from_rational(transfer.amount);
+    let from = balances.entry(transfer.from).or_insert_with(Amount::zero);
+    *from = from.checked_add(&amount).ok_or_else(overflow)?;
+    let to = balances.entry(transfer.to).or_insert_with(Amount::zero);
+    *to = to.checked_sub(&amount).ok_or_else(overflow)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParsedAccounts {
+    users: Users,
+    purchases: Vec<ParsedPurchase>,
+    /// The greatest id given to a purchase. It only ever goes up, so that
+    /// the id of a removed purchase is never given to another one.
+    last_purchase_id: PurchaseId,
+    /// Transfers are expressed in the base currency
+    transfers: Vec<ParsedTransfer>,
+    base_currency: Option<String>,
+    /// Value of one unit of each currency in the base currency
+    rates: BTreeMap<String, Rational64>,
+}
+
+/// Accounts are equal if they have the same content, whichever purchases
+/// were removed before
+impl PartialEq for ParsedAccounts {
+    fn eq(&self, other: &Self) -> bool {
+        self.users == other.users
+            && self.purchases == other.purchases
+            && self.transfers == other.transfers
+            && self.base_currency == other.base_currency
+            && self.rates == other.rates
+    }
+}
+
+impl ParsedAccounts {
+    /// Deserialize from json data
+    pub fn from_json(json: &str) -> Result<Self, ParseError> {
+        Self::from_json_with(json, ParseMode::Lenient)
+    }
+
+    /// Deserialize from json data, checked according to `mode`
+    pub fn from_json_with(
+        json: &str,
+        mode: ParseMode,
+    ) -> Result<Self, ParseError> {
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        let accounts =
+            SerializedAccounts::deserialize_with(&mut deserializer, mode)?;
+        deserializer.end()?;
+        accounts.parse_with(mode)
+    }
+
+    /// Deserialize from toml data, checked according to `mode`
+    pub fn from_toml_with(
+        toml: &str,
+        mode: ParseMode,
+    ) -> Result<Self, ParseError> {
+        let mut deserializer = toml::Deserializer::new(toml);
+        let accounts =
+            SerializedAccounts::deserialize_with(&mut deserializer, mode)?;
+        deserializer.end()?;
+        accounts.parse_with(mode)
+    }
+
+    pub fn from_yaml_reader<R: std::io::Read>(
+        yaml: R,
+    ) -> Result<Self, ParseError> {
+        Self::from_yaml_reader_with(yaml, ParseMode::Lenient)
+    }
+
+    /// Deserialize from yaml data, checked according to `mode`
+    pub fn from_yaml_reader_with<R: std::io::Read>(
+        mut yaml: R,
+        mode: ParseMode,
+    ) -> Result<Self, ParseError> {
+        let mut yaml_str = String::new();
+        yaml.read_to_string(&mut yaml_str)
+            .map_err(|err| ParseError::YamlError(err.to_string()))?;
+        Self::from_yaml_str_with(&yaml_str, mode)
+    }
+
+    /// Deserialize from yaml data, checked according to `mode`. Errors are
+    /// located in the yaml source.
+    pub fn from_yaml_str_with(
+        yaml: &str,
+        mode: ParseMode,
+    ) -> Result<Self, ParseError> {
+        let deserializer = serde_yaml::Deserializer::from_str(yaml);
+        SerializedAccounts::deserialize_with(deserializer, mode)
+            .and_then(|accounts| accounts.parse_with(mode))
+            .map_err(|err| YamlIndex::new(yaml).locate(err))
+    }
+
+    /// Return the existing users, in the order they were declared
+    pub fn users(&self) -> &[User] {
+        &self.users.users[..]
+    }
+
+    /// Get the id of a user from its name
+    pub fn user_id(&self, name: &str) -> Result<UserId, ParseError> {
+        self.users.find(name)
+    }
+
+    /// Get the name of a user from its id
+    pub fn user_name(&self, uid: UserId) -> Result<&str, ParseError> {
+        let position = self.users.position(uid)?;
+        Ok(&self.users.users[position].name)
+    }
+
+    /// Order values computed for each user like the users
+    fn in_users_order(&self, values: &BTreeMap<UserId, Amount>) -> Vec<Amount> {
+        self.users
+            .iter()
+            .map(|user| {
+                values.get(&user.id).cloned().unwrap_or_else(Amount::zero)
+            })
+            .collect()
+    }
+
+    pub fn purchases(&self) -> &[ParsedPurchase] {
+        &self.purchases[..]
+    }
+
+    pub fn transfers(&self) -> &[ParsedTransfer] {
+        &self.transfers[..]
+    }
+
+    /// Get the purchase with the given id
+    pub fn purchase(
+        &self,
+        purchase_id: PurchaseId,
+    ) -> Result<&ParsedPurchase, ParseError> {
+        self.purchases
+            .iter()
+            .find(|purchase| purchase.id == purchase_id)
+            .ok_or(ParseError::InvalidPurchase(purchase_id))
+    }
+
+    /// Get a mutable reference to the selected transaction.
+    ///
+    /// For borrow-checking reasons, we also return the view on our
+    /// users.
+    pub fn purchase_mut(
+        &mut self,
+        purchase_id: PurchaseId,
+    ) -> Result<(&mut ParsedPurchase, &[User]), ParseError> {
+        let purchase = self
+            .purchases
+            .iter_mut()
+            .find(|purchase| purchase.id == purchase_id)
+            .ok_or(ParseError::InvalidPurchase(purchase_id))?;
+        Ok((purchase, &self.users.users))
+    }
+
+    /// Get a mutable reference to the selected transfer.
+    pub fn transfer_mut(
+        &mut self,
+        transfer_idx: usize,
+    ) -> Result<&mut ParsedTransfer, ParseError> {
+        if transfer_idx >= self.transfers.len() {
+            return Err(ParseError::InvalidTransfer(transfer_idx));
+        }
+        Ok(&mut self.transfers[transfer_idx])
+    }
+
+    /// The currency in which balances are computed, if any
+    pub fn base_currency(&self) -> Option<&str> {
+        self.base_currency.as_deref()
+    }
+
+    /// Change the base currency. Purchases in the former base currency are
+    /// now in the new one.
+    pub fn set_base_currency(
+        &mut self,
+        currency: Option<String>,
+    ) -> Result<(), ParseError> {
+        let currency = currency.map(parse_currency).transpose()?;
+        for purchase in self.purchases.iter_mut() {
+            if purchase.currency() == self.base_currency.as_deref() {
+                purchase.amount =
+                    Money::new(purchase.amount.value(), currency.clone());
+            }
+        }
+        self.base_currency = currency;
+        Ok(())
+    }
+
+    /// Number of decimals of the minor unit of the base currency
+    pub fn nb_decimals(&self) -> u8 {
+        self.base_currency().map_or(DEFAULT_DECIMALS, minor_units)
+    }
+
+    /// An amount in the base currency
+    fn base_money(&self, value: Rational64) -> Money {
+        Money::new(value, self.base_currency.clone())
+    }
+
+    /// Get the value of one unit of each currency in the base currency
+    pub fn rates(&self) -> &BTreeMap<String, Rational64> {
+        &self.rates
+    }
+
+    /// Set the value of one unit of `currency` in the base currency
+    pub fn set_rate(
+        &mut self,
+        currency: String,
+        rate: Rational64,
+    ) -> Result<(), ParseError> {
+        self.rates.insert(parse_currency(currency)?, rate);
+        Ok(())
+    }
+
+    /// Get the rate converting the amounts of a purchase to the base
+    /// currency: its own rate if any, else the one of its currency.
+    pub fn purchase_rate(
+        &self,
+        purchase: &ParsedPurchase,
+    ) -> Result<Rational64, ParseError> {
+        match (purchase.currency(), purchase.rate) {
+            (_, Some(rate)) => Ok(rate),
+            (None, None) => Ok(1.into()),
+            (Some(currency), None) => {
+                if Some(currency) == self.base_currency() {
+                    Ok(1.into())
+                } else {
+                    self.rates.get(currency).copied().ok_or_else(|| {
+                        ParseError::UnknownRate(currency.to_string())
+                    })
+                }
+            }
+        }
+    }
+
+    /// Why a purchase is ignored when computing the balances, if it is.
+    ///
+    /// Overflows are errors rather than reasons to ignore a purchase, since
+    /// the balances cannot be computed without it.
+    pub fn ignored_reason(
+        &self,
+        purchase: &ParsedPurchase,
+    ) -> Result<Option<ParseError>, ParseError> {
+        let checked = self
+            .purchase_rate(purchase)
+            .and_then(|_| purchase.check_amount())
+            .and_then(|_| purchase.check_payers())
+            .and_then(|_| purchase.per_user_costs());
+        match checked {
+            Ok(_) => Ok(None),
+            Err(err @ ParseError::Overflow(_)) => Err(err),
+            Err(err) => Ok(Some(err)),
+        }
+    }
+
+    /// Add the effect of a purchase to the balances. Invalid purchases are
+    /// ignored, see `ignored_reason`, but overflows are errors.
+    fn apply_purchase(
+        &self,
+        purchase: &ParsedPurchase,
+        balances: &mut BTreeMap<UserId, Amount>,
+    ) -> Result<(), ParseError> {
+        if self.ignored_reason(purchase)?.is_some() {
+            return Ok(());
+        }
+        apply_purchase(purchase, self.purchase_rate(purchase)?, balances)
+    }
+
+    /// Compute the balance for each user. Fails if the computations
+    /// overflow.
+    pub fn user_balances(&self) -> Result<Vec<Amount>, ParseError> {
+        let mut balances = BTreeMap::new();
+        for purchase in &self.purchases {
+            self.apply_purchase(purchase, &mut balances)?;
+        }
+        for transfer in &self.transfers {
+            apply_transfer(transfer, &mut balances)?;
+        }
+        Ok(self.in_users_order(&balances))
+    }
+
+    /// Compute the balances at the end of the passed day, only taking into
+    /// account the transactions dated on or before it. Undated transactions
+    /// are ignored.
+    pub fn user_balances_as_of(
+        &self,
+        date: Date,
+    ) -> Result<Vec<Amount>, ParseError> {
+        let mut balances = BTreeMap::new();
+        let is_before = |trans_date: Option<Date>| {
+            trans_date.is_some_and(|trans_date| trans_date <= date)
+        };
+        for purchase in &self.purchases {
+            if is_before(purchase.date) {
+                self.apply_purchase(purchase, &mut balances)?;
+            }
+        }
+        for transfer in &self.transfers {
+            if is_before(transfer.date) {
+                apply_transfer(transfer, &mut balances)?;
+            }
+        }
+        Ok(self.in_users_order(&balances))
+    }
+
+    /// Get the transactions in chronological order. Undated transactions
+    /// come first, and purchases come before transfers of the same day.
+    /// Otherwise, the order of the file is kept.
+    pub fn chronological_order(&self) -> Vec<(TransactionRef, Option<Date>)> {
+        let mut transactions: Vec<_> = self
+            .purchases
+            .iter()
+            .map(|purchase| {
+                (TransactionRef::Purchase(purchase.id), purchase.date)
+            })
+            .chain(self.transfers.iter().enumerate().map(|(idx, transfer)| {
+                (TransactionRef::Transfer(idx), transfer.date)
+            }))
+            .collect();
+        // The sort is stable
+        transactions.sort_by_key(|(_, date)| *date);
+        transactions
+    }
+
+    /// Compute the balances after every transaction, taken in chronological
+    /// order.
+    pub fn balance_timeline(&self) -> Result<Vec<BalanceStep>, ParseError> {
+        let mut balances = BTreeMap::new();
+        self.chronological_order()
+            .into_iter()
+            .map(|(transaction, date)| {
+                match transaction {
+                    TransactionRef::Purchase(id) => {
+                        self.apply_purchase(self.purchase(id)?, &mut balances)?
+                    }
+                    TransactionRef::Transfer(idx) => {
+                        apply_transfer(&self.transfers[idx], &mut balances)?
+                    }
+                }
+                Ok(BalanceStep {
+                    transaction,
+                    date,
+                    balances: self.in_users_order(&balances),
+                })
+            })
+            .collect()
+    }
+
+    /// Sort the purchases and the transfers by date, undated ones first.
+    pub fn sort_by_date(&mut self) {
+        self.purchases.sort_by_key(|purchase| purchase.date);
+        self.transfers.sort_by_key(|transfer| transfer.date);
+    }
+
+    /// Compute what each user consumed and paid, grouped by category or by
+    /// tag. Groups are sorted by name, purchases without any come first.
+    /// Invalid purchases are ignored, but overflows are errors.
+    pub fn spending_report(
+        &self,
+        key: ReportKey,
+    ) -> Result<Vec<SpendingReport>, ParseError> {
+        // What each user consumed and paid, for each key
+        type Totals = BTreeMap<UserId, Amount>;
+        let mut reports: BTreeMap<Option<String>, (Totals, Totals)> =
+            BTreeMap::new();
+        for purchase in &self.purchases {
+            if self.ignored_reason(purchase)?.is_some() {
+                continue;
+            }
+            let rate = self.purchase_rate(purchase)?;
+            let costs = purchase.per_user_costs()?;
+            let overflow = || ParseError::Overflow(purchase.descr.clone());
+            let rate = Amount::from_rational(rate);
+            let keys: Vec<Option<String>> = match key {
+                ReportKey::Category => vec![purchase.category.clone()],
+                ReportKey::Tag if purchase.tags.is_empty() => vec![None],
+                ReportKey::Tag => {
+                    purchase.tags.iter().cloned().map(Some).collect()
+                }
+            };
+            for key in keys {
+                let (consumed, paid_by) = reports.entry(key).or_default();
+                for (uid, cost) in &costs {
+                    let cost = cost.checked_mul(&rate).ok_or_else(overflow)?;
+                    let total =
+                        consumed.entry(*uid).or_insert_with(Amount::zero);
+                    *total = total.checked_add(&cost).ok_or_else(overflow)?;
+                }
+                for (uid, paid) in &purchase.payers {
+                    let paid = Amount::from_rational(*paid)
+                        .checked_mul(&rate)
+                        .ok_or_else(overflow)?;
+                    let total =
+                        paid_by.entry(*uid).or_insert_with(Amount::zero);
+                    *total = total.checked_add(&paid).ok_or_else(overflow)?;
+                }
+            }
+        }
+        Ok(reports
+            .into_iter()
+            .map(|(key, (consumed, paid))| SpendingReport {
+                key,
+                consumed: self.in_users_order(&consumed),
+                paid: self.in_users_order(&paid),
+            })
+            .collect())
+    }
+
+    /// Print what each user consumed and paid, grouped by category or by
+    /// tag. Amounts are printed with `precision` decimals, by default the
+    /// ones of the base currency.
+    pub fn print_spending_report(
+        &self,
+        key: ReportKey,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        let nb_decimals = precision.unwrap_or_else(|| self.nb_decimals());
+        let format = |value: &Amount| {
+            let rounded = round_money(value, nb_decimals).ok_or_else(|| {
+                ParseError::Overflow("the report".to_string())
+            })?;
+            Ok::<_, ParseError>(self.base_money(rounded).format(precision))
+        };
+        for report in self.spending_report(key)? {
+            println!("{}:", report.key.as_deref().unwrap_or("(none)"));
+            for (uid, user) in self.users.iter().enumerate() {
+                println!(
+                    "  {} consumed {} and paid {}",
+                    user.name,
+                    format(&report.consumed[uid])?,
+                    format(&report.paid[uid])?,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the balance of each user, with `precision` decimals, by
+    /// default the ones of the base currency.
+    pub fn print_balances(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        self.print_some_balances(&self.user_balances()?, precision)
+    }
+
+    pub fn print_balances_as_of(
+        &self,
+        date: Date,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        self.print_some_balances(&self.user_balances_as_of(date)?, precision)
+    }
+
+    fn print_some_balances(
+        &self,
+        balances: &[Amount],
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        let nb_decimals = precision.unwrap_or_else(|| self.nb_decimals());
+        let balances = allocate_rounding(balances, nb_decimals)
+            .ok_or_else(|| ParseError::Overflow("the balances".to_string()))?;
+        for (user, balance) in self.users.iter().zip(balances) {
+            println!(
+                "{} has a balance of: {}",
+                user.name,
+                self.base_money(balance).format(precision),
+            );
+        }
+        Ok(())
+    }
+
+    /// Format the amount of a purchase, followed by its value in the base
+    /// currency if it is in another currency.
+    fn format_purchase_amount(
+        &self,
+        purchase: &ParsedPurchase,
+        precision: Option<u8>,
+    ) -> String {
+        let amount = purchase.amount.format(precision);
+        if purchase.currency() == self.base_currency() {
+            return amount;
+        }
+        let nb_decimals = precision.unwrap_or_else(|| self.nb_decimals());
+        let value = self.purchase_rate(purchase).ok().and_then(|rate| {
+            let value = Amount::from_rational(purchase.amount.value())
+                .checked_mul(&Amount::from_rational(rate))?;
+            round_money(&value, nb_decimals)
+        });
+        match value {
+            Some(value) => format!(
+                "{} ({})",
+                amount,
+                self.base_money(value).format(precision),
+            ),
+            None => amount,
+        }
+    }
+
+    /// Format an amount of a purchase, without its currency
+    fn format_purchase_value(
+        purchase: &ParsedPurchase,
+        value: Rational64,
+        precision: Option<u8>,
+    ) -> String {
+        Money::new(value, purchase.currency().map(String::from))
+            .format_value(precision)
+    }
+
+    /// Print what each beneficiary of a purchase owes, in the currency of
+    /// the purchase
+    fn print_purchase_costs(
+        &self,
+        purchase: &ParsedPurchase,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        let nb_decimals = precision
+            .or_else(|| purchase.amount.nb_decimals())
+            .unwrap_or(DEFAULT_DECIMALS);
+        if let Ok(costs) = purchase.rounded_per_user_costs(nb_decimals) {
+            let mut formatted = Vec::with_capacity(costs.len());
+            for user in self.users.iter() {
+                match costs.get(&user.id) {
+                    Some(cost) if *cost != 0.into() => {
+                        formatted.push(format!(
+                            "{}: {}",
+                            user.name,
+                            Self::format_purchase_value(
+                                purchase, *cost, precision
+                            )
+                        ));
+                    }
+                    _ => (),
+                }
+            }
+            println!("    costs: {}", formatted.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Format the users who paid for a purchase, with the amount they paid
+    /// if there are several of them.
+    fn format_payers(
+        &self,
+        purchase: &ParsedPurchase,
+        precision: Option<u8>,
+    ) -> Result<String, ParseError> {
+        if let Some(uid) = purchase.single_payer() {
+            return self.user_name(uid).map(String::from);
+        }
+        let mut payers = Vec::with_capacity(purchase.payers.len());
+        for user in self.users.iter() {
+            if let Some(paid) = purchase.payers.get(&user.id) {
+                payers.push(format!(
+                    "{} ({})",
+                    user.name,
+                    Self::format_purchase_value(purchase, *paid, precision)
+                ));
+            }
+        }
+        Ok(payers.join(", "))
+    }
+
+    /// Print the purchases, with their ids, then the transfers. Amounts are
+    /// printed with `precision` decimals, by default the ones of their
+    /// currency.
+    pub fn print_transactions(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        let nb_share_decimals = precision.unwrap_or(DEFAULT_DECIMALS);
+        println!("Purchases:");
+        for purchase in &self.purchases {
+            let shares: Vec<String> = purchase
+                .benef_to_shares(self)
+                .filter(|(_, split)| !split.is_zero())
+                .map(|(user, split)| {
+                    format!(
+                        "{}: {}",
+                        user,
+                        split_to_string(split, nb_share_decimals)
+                    )
+                })
+                .collect();
+            let payers = self.format_payers(purchase, precision)?;
+            let date = date_prefix(purchase.date);
+            if !purchase.is_itemized() {
+                println!(
+                    "  #{} {}{}: {} paid {} (shares: {})",
+                    purchase.id,
+                    date,
+                    purchase.descr,
+                    payers,
+                    self.format_purchase_amount(purchase, precision),
+                    shares.join(", "),
+                );
+                self.print_purchase_costs(purchase, precision)?;
+                continue;
+            }
+            println!(
+                "  #{} {}{}: {} paid {}",
+                purchase.id,
+                date,
+                purchase.descr,
+                payers,
+                self.format_purchase_amount(purchase, precision),
+            );
+            for item in &purchase.items {
+                let shares: Vec<String> = self
+                    .users
+                    .iter()
+                    .filter_map(|user| {
+                        let split = item.benef_to_shares.get(&user.id)?;
+                        Some(format!(
+                            "{}: {}",
+                            user.name,
+                            split_to_string(*split, nb_share_decimals)
+                        ))
+                    })
+                    .collect();
+                println!(
+                    "    {}: {} (shares: {})",
+                    item.descr,
+                    Self::format_purchase_value(
+                        purchase,
+                        item.amount,
+                        precision
+                    ),
+                    shares.join(", "),
+                );
+            }
+            for adjustment in &purchase.adjustments {
+                println!(
+                    "    {}: {}",
+                    adjustment.kind,
+                    Self::format_purchase_value(
+                        purchase,
+                        adjustment.signed_amount(),
+                        precision
+                    ),
+                );
+            }
+            self.print_purchase_costs(purchase, precision)?;
+        }
+        println!("Transfers:");
+        for transfer in &self.transfers {
+            let descr = if transfer.descr.is_empty() {
+                String::new()
+            } else {
+                format!("{}: ", transfer.descr)
+            };
+            println!(
+                "  {}{}{} gave {} to {}",
+                date_prefix(transfer.date),
+                descr,
+                self.user_name(transfer.from)?,
+                self.base_money(transfer.amount).format(precision),
+                self.user_name(transfer.to)?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Compute a list of transfers between users that would bring all
+    /// balances to zero, trying to minimize the number of transfers. Fails
+    /// if a balance does not fit in a `Rational64`.
+    pub fn settlement(&self) -> Result<Vec<settlement::Transfer>, ParseError> {
+        let balances: Option<Vec<Rational64>> = self
+            .user_balances()?
+            .iter()
+            .map(Numeric::to_rational)
+            .collect();
+        balances
+            .and_then(|balances| settle(&balances))
+            .ok_or_else(|| ParseError::Overflow("the balances".to_string()))
+    }
+
+    /// Round the balances to `precision` decimals, by default the ones of
+    /// the base currency, such that the rounded balances still add up to
+    /// zero.
+    pub fn rounded_balances(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<Vec<Money>, ParseError> {
+        self.round_balances(&self.user_balances()?, precision)
+    }
+
+    fn round_balances(
+        &self,
+        balances: &[Amount],
+        precision: Option<u8>,
+    ) -> Result<Vec<Money>, ParseError> {
+        let nb_decimals = precision.unwrap_or_else(|| self.nb_decimals());
+        Ok(allocate_rounding(balances, nb_decimals)
+            .ok_or_else(|| ParseError::Overflow("the balances".to_string()))?
+            .into_iter()
+            .map(|balance| self.base_money(balance))
+            .collect())
+    }
+
+    /// Compute a settlement of the rounded balances. Its transfers are
+    /// exact decimal numbers with the requested number of decimals.
+    pub fn rounded_settlement(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<Vec<settlement::Transfer>, ParseError> {
+        self.settle_rounded(&self.user_balances()?, precision)
+    }
+
+    /// Compute a settlement of the rounded balances at the end of the
+    /// passed day, as given by `user_balances_as_of`.
+    pub fn rounded_settlement_as_of(
+        &self,
+        date: Date,
+        precision: Option<u8>,
+    ) -> Result<Vec<settlement::Transfer>, ParseError> {
+        self.settle_rounded(&self.user_balances_as_of(date)?, precision)
+    }
+
+    fn settle_rounded(
+        &self,
+        balances: &[Amount],
+        precision: Option<u8>,
+    ) -> Result<Vec<settlement::Transfer>, ParseError> {
+        let balances: Vec<Rational64> = self
+            .round_balances(balances, precision)?
+            .iter()
+            .map(Money::value)
+            .collect();
+        settle(&balances)
+            .ok_or_else(|| ParseError::Overflow("the balances".to_string()))
+    }
+
+    pub fn print_settlement(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        self.print_some_settlement(
+            self.rounded_settlement(precision)?,
+            precision,
+        )
+    }
+
+    pub fn print_settlement_as_of(
+        &self,
+        date: Date,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        self.print_some_settlement(
+            self.rounded_settlement_as_of(date, precision)?,
+            precision,
+        )
+    }
+
+    fn print_some_settlement(
+        &self,
+        transfers: Vec<settlement::Transfer>,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        for transfer in transfers {
+            println!(
+                "{} should give {} to {}",
+                self.users()[transfer.from].name,
+                self.base_money(transfer.amount).format(precision),
+                self.users()[transfer.to].name,
+            );
+        }
+        Ok(())
+    }
+
+    /// Add a new user to the accounts. Its shares in all existing transactions
+    /// will be zero.
+    ///
+    /// On success, returns the id of the added user.
+    pub fn add_user(&mut self, user: String) -> Result<UserId, ParseError> {
+        if user.len() == 0 {
+            return Err(ParseError::EmptyUser);
+        }
+        if self.users.find(&user).is_ok() {
+            return Err(ParseError::UserAlreadyPresent(user));
+        }
+        Ok(self.users.push(user))
+    }
+
+    pub fn remove_user(&mut self, user: String) -> Result<(), ParseError> {
+        let uid = self.users.find(&user)?;
+        for purchase in self.purchases.iter() {
+            if purchase.payers.contains_key(&uid)
+                || purchase.benef_to_shares.contains_key(&uid)
+                || purchase
+                    .items
+                    .iter()
+                    .any(|item| item.benef_to_shares.contains_key(&uid))
+            {
+                return Err(ParseError::UserHasData(user));
+            }
+        }
+        for transfer in self.transfers.iter() {
+            if transfer.from == uid || transfer.to == uid {
+                return Err(ParseError::UserHasData(user));
+            }
+        }
+        self.users.remove(uid)
+    }
+
+    /// Rename a user. Its transactions refer to it by id, and are thus left
+    /// untouched.
+    pub fn rename_user(
+        &mut self,
+        uid: UserId,
+        name: String,
+    ) -> Result<(), ParseError> {
+        if name.is_empty() {
+            return Err(ParseError::EmptyUser);
+        }
+        if self.user_name(uid)? == name {
+            return Ok(());
+        }
+        if self.users.find(&name).is_ok() {
+            return Err(ParseError::UserAlreadyPresent(name));
+        }
+        self.users.rename(uid, name)
+    }
+
+    /// Add a purchase to the accounts, with empty shares. The purchase shares
+    /// shall be filled-in later.
+    ///
+    /// On success, returns the id of the added purchase.
+    pub fn add_purchase(
+        &mut self,
+        descr: String,
+        who_paid: String,
+        amount: Rational64,
+    ) -> Result<PurchaseId, ParseError> {
+        if who_paid.len() == 0 {
+            return Err(ParseError::EmptyUser);
+        }
+        if descr.len() == 0 {
+            return Err(ParseError::EmptyDescr);
+        }
+        let who_paid = self.user_id(&who_paid)?;
+        let mut payers = BTreeMap::new();
+        set_paid(&mut payers, who_paid, amount);
+        let id = next_purchase_id(
+            self.purchases
+                .iter()
+                .map(|purchase| &purchase.id)
+                .chain([&self.last_purchase_id]),
+        )?;
+        self.last_purchase_id = id;
+        self.purchases.push(ParsedPurchase {
+            id,
+            date: None,
+            rate: None,
+            category: None,
+            tags: Vec::new(),
+            descr,
+            payers,
+            amount: self.base_money(amount),
+            benef_to_shares: BTreeMap::new(),
+            items: Vec::new(),
+            adjustments: Vec::new(),
+        });
+        Ok(id)
+    }
+
+    /// Modify the shares for the requested user in a selected purchase
+    pub fn set_purchase_user_share(
+        &mut self,
+        purchase_id: PurchaseId,
+        user: String,
+        share: Rational64,
+    ) -> Result<(), ParseError> {
+        let user = self.user_id(&user)?;
+        self.purchase_mut(purchase_id)?.0.set_share(user, share);
+        Ok(())
+    }
+
+    /// Modify how the cost of a purchase is split for the requested user
+    pub fn set_purchase_user_split(
+        &mut self,
+        purchase_id: PurchaseId,
+        user: String,
+        split: Split,
+    ) -> Result<(), ParseError> {
+        let user = self.user_id(&user)?;
+        self.purchase_mut(purchase_id)?.0.set_split(user, split);
         Ok(())
     }
 
@@ -334,14 +2353,31 @@ impl ParsedAccounts {
     /// users whose shares must be set, and the corresponding share.
     pub fn set_purchase_shares(
         &mut self,
-        purchase_idx: usize,
-        shares: impl Iterator<Item = (usize, Rational64)>,
+        purchase_id: PurchaseId,
+        shares: impl Iterator<Item = (UserId, Rational64)>,
+    ) -> Result<(), ParseError> {
+        self.set_purchase_splits(
+            purchase_id,
+            shares.map(|(uid, share)| (uid, Split::Shares(share))),
+        )
+    }
+
+    /// Set all the splits of a transaction in one pass
+    ///
+    /// The `splits` should be an iterator yielding the user ids of the
+    /// users whose splits must be set, and the corresponding split.
+    pub fn set_purchase_splits(
+        &mut self,
+        purchase_id: PurchaseId,
+        splits: impl Iterator<Item = (UserId, Split)>,
     ) -> Result<(), ParseError> {
-        if purchase_idx >= self.purchases.len() {
-            return Err(ParseError::InvalidPurchase(purchase_idx));
+        let splits: Vec<_> = splits.collect();
+        for (uid, _) in &splits {
+            self.user_name(*uid)?;
         }
-        for (uid, share) in shares {
-            self.purchases[purchase_idx].set_share(uid, share)?;
+        let (purchase, _) = self.purchase_mut(purchase_id)?;
+        for (uid, split) in splits {
+            purchase.set_split(uid, split);
         }
         Ok(())
     }
@@ -349,217 +2385,1595 @@ impl ParsedAccounts {
     // Change the user who paid for a purchase
     pub fn change_purchase_creditor(
         &mut self,
-        purchase_idx: usize,
+        purchase_id: PurchaseId,
         who_paid: String,
     ) -> Result<(), ParseError> {
-        let loc = self.users.binary_search(&who_paid);
-        let who_paid = match loc {
-            Err(_) => return Err(ParseError::UnknownUser(who_paid)),
-            Ok(index) => index,
-        };
-        if purchase_idx >= self.purchases.len() {
-            return Err(ParseError::InvalidPurchase(purchase_idx));
-        }
-        self.purchases[purchase_idx].who_paid = who_paid;
+        let who_paid = self.user_id(&who_paid)?;
+        self.purchase_mut(purchase_id)?.0.set_single_payer(who_paid);
         Ok(())
     }
 
     // Change the user who paid for a purchase
     pub fn change_purchase_amount(
         &mut self,
-        purchase_idx: usize,
+        purchase_id: PurchaseId,
         amount: Rational64,
     ) -> Result<(), ParseError> {
-        if purchase_idx >= self.purchases.len() {
-            return Err(ParseError::InvalidPurchase(purchase_idx));
+        self.purchase_mut(purchase_id)?.0.set_amount(amount);
+        Ok(())
+    }
+
+    /// Set the amounts paid by users for a purchase in one pass. The amount
+    /// of the purchase becomes the total paid.
+    ///
+    /// The `payers` should be an iterator yielding the user ids of the
+    /// users who paid, and the amount they paid. Other users are considered
+    /// to have paid nothing.
+    pub fn set_purchase_payers(
+        &mut self,
+        purchase_id: PurchaseId,
+        payers: impl Iterator<Item = (UserId, Rational64)>,
+    ) -> Result<(), ParseError> {
+        let payers: Vec<_> = payers.collect();
+        for (uid, _) in &payers {
+            self.user_name(*uid)?;
+        }
+        let (purchase, _) = self.purchase_mut(purchase_id)?;
+        purchase.payers.clear();
+        for (uid, paid) in payers {
+            purchase.set_payer_amount(uid, paid);
         }
-        self.purchases[purchase_idx].amount = amount;
         Ok(())
     }
 
     // Remove a purchase
     pub fn remove_purchase(
         &mut self,
-        purchase_idx: usize,
+        purchase_id: PurchaseId,
+    ) -> Result<(), ParseError> {
+        let len = self.purchases.len();
+        self.purchases.retain(|purchase| purchase.id != purchase_id);
+        if self.purchases.len() == len {
+            return Err(ParseError::InvalidPurchase(purchase_id));
+        }
+        Ok(())
+    }
+
+    /// Add a transfer of `amount` from `from` to `to`.
+    ///
+    /// On success, returns the index of the added transfer.
+    pub fn add_transfer(
+        &mut self,
+        descr: String,
+        from: String,
+        to: String,
+        amount: Rational64,
+    ) -> Result<usize, ParseError> {
+        let from = self.user_id(&from)?;
+        let to = self.user_id(&to)?;
+        self.transfers.push(ParsedTransfer {
+            date: None,
+            descr,
+            from,
+            to,
+            amount,
+        });
+        Ok(self.transfers.len() - 1)
+    }
+
+    // Remove a transfer
+    pub fn remove_transfer(
+        &mut self,
+        transfer_idx: usize,
     ) -> Result<(), ParseError> {
-        if purchase_idx >= self.purchases.len() {
-            return Err(ParseError::InvalidPurchase(purchase_idx));
+        if transfer_idx >= self.transfers.len() {
+            return Err(ParseError::InvalidTransfer(transfer_idx));
         }
-        self.purchases.remove(purchase_idx);
+        self.transfers.remove(transfer_idx);
         Ok(())
     }
 
+    /// Serialize the splits of every user, including those owing nothing
+    fn serialize_splits(
+        &self,
+        splits: &BTreeMap<UserId, Split>,
+    ) -> BTreeMap<String, String> {
+        self.users
+            .iter()
+            .map(|user| {
+                let split = splits.get(&user.id).copied().unwrap_or_default();
+                (user.name.clone(), split_to_exact_string(split))
+            })
+            .collect()
+    }
+
+    /// Get the name of a user known to exist
+    fn name_of(&self, uid: UserId) -> String {
+        // No Panic: transactions only refer to users of the accounts
+        self.user_name(uid).unwrap().to_string()
+    }
+
     pub fn as_serializable(&self) -> SerializedAccounts {
         SerializedAccounts {
-            users: self.users.clone(),
+            base_currency: self.base_currency.clone(),
+            rates: self
+                .rates
+                .iter()
+                .map(|(currency, rate)| {
+                    (currency.clone(), rational_to_exact_string(*rate))
+                })
+                .collect(),
+            users: self.users.iter().map(|user| user.name.clone()).collect(),
             purchases: self
                 .purchases
                 .iter()
                 .map(|purchase| Purchase {
+                    id: Some(purchase.id),
                     descr: purchase.descr.clone(),
-                    who: self.users[purchase.who_paid].clone(),
-                    amount: rational_to_string(purchase.amount, 2),
-                    benef_to_shares: purchase
-                        .benef_to_shares
+                    date: purchase.date.map(|date| date.to_string()),
+                    currency: purchase
+                        .currency()
+                        .filter(|currency| {
+                            Some(*currency) != self.base_currency()
+                        })
+                        .map(String::from),
+                    category: purchase.category.clone(),
+                    tags: purchase.tags.clone(),
+                    rate: purchase.rate.map(rational_to_exact_string),
+                    who: purchase.single_payer().map(|uid| self.name_of(uid)),
+                    payers: match purchase.single_payer() {
+                        Some(_) => BTreeMap::new(),
+                        None => purchase
+                            .payers
+                            .iter()
+                            .map(|(uid, paid)| {
+                                (
+                                    self.name_of(*uid),
+                                    rational_to_exact_string(*paid),
+                                )
+                            })
+                            .collect(),
+                    },
+                    amount: purchase.amount.value_to_exact_string(),
+                    benef_to_shares: if purchase.is_itemized() {
+                        BTreeMap::new()
+                    } else {
+                        self.serialize_splits(&purchase.benef_to_shares)
+                    },
+                    items: purchase
+                        .items
                         .iter()
-                        .enumerate()
-                        .map(|(uid, share)| {
-                            (
-                                self.users[uid].clone(),
-                                rational_to_string(*share, 2),
-                            )
+                        .map(|item| Item {
+                            descr: item.descr.clone(),
+                            amount: rational_to_exact_string(item.amount),
+                            benef_to_shares: self
+                                .serialize_splits(&item.benef_to_shares),
+                        })
+                        .collect(),
+                    adjustments: purchase
+                        .adjustments
+                        .iter()
+                        .map(|adjustment| Adjustment {
+                            kind: adjustment.kind,
+                            amount: rational_to_exact_string(adjustment.amount),
                         })
                         .collect(),
                 })
                 .collect(),
+            transfers: self
+                .transfers
+                .iter()
+                .map(|transfer| Transfer {
+                    descr: transfer.descr.clone(),
+                    date: transfer.date.map(|date| date.to_string()),
+                    from: self.name_of(transfer.from),
+                    to: self.name_of(transfer.to),
+                    amount: rational_to_exact_string(transfer.amount),
+                })
+                .collect(),
         }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::{ParseError, ParsedAccounts, ParsedPurchase};
+#[cfg(test)]
+mod test {
+    use super::{
+        ParseError, ParseMode, ParsedAccounts, ParsedPurchase, ParsedTransfer,
+        PurchaseId, PurchaseLocation, ReportKey, SourceLocation,
+        SpendingReport, TransactionRef, UserId, Users,
+    };
+    use crate::date::Date;
+    use crate::money::{Amount, Money, Numeric};
+    use crate::settlement;
+    use crate::split::Split;
+    use num_rational::Rational64;
+    use std::collections::BTreeMap;
+
+    /// The result of parsing, with only the cause of the errors
+    fn cause(
+        parsed: Result<ParsedAccounts, ParseError>,
+    ) -> Result<ParsedAccounts, ParseError> {
+        parsed.map_err(|err| err.root_cause().clone())
+    }
+
+    /// Users whose ids are their positions
+    fn users(names: &[&str]) -> Users {
+        Users::from_names(names.iter().map(|name| name.to_string()).collect())
+            .unwrap()
+    }
+
+    /// Shares of the users whose ids are the positions of the shares
+    fn shares(shares: &[i64]) -> BTreeMap<UserId, Split> {
+        shares
+            .iter()
+            .enumerate()
+            .filter(|(_, &s)| s != 0)
+            .map(|(uid, &s)| (UserId(uid), Split::Shares(s.into())))
+            .collect()
+    }
+
+    /// Amounts paid by the users whose ids are the positions of the amounts
+    fn paid(paid: &[i64]) -> BTreeMap<UserId, Rational64> {
+        paid.iter()
+            .enumerate()
+            .filter(|(_, &p)| p != 0)
+            .map(|(uid, &p)| (UserId(uid), p.into()))
+            .collect()
+    }
+
+    /// Convert computed amounts to rationals, whatever the numeric type
+    /// of the computations
+    fn rationals(amounts: Vec<Amount>) -> Vec<Rational64> {
+        amounts
+            .iter()
+            .map(|amount| amount.to_rational().unwrap())
+            .collect()
+    }
+
+    fn values(amounts: Vec<Money>) -> Vec<Rational64> {
+        amounts.iter().map(Money::value).collect()
+    }
+
+    fn amounts(values: &[i64]) -> Vec<Amount> {
+        values
+            .iter()
+            .map(|value| Amount::from_rational((*value).into()))
+            .collect()
+    }
+
+    #[test]
+    fn add_remove_user() {
+        let mut accounts = ParsedAccounts {
+            users: users(&["Eska", "Shuba", "Simon"]),
+            purchases: vec![
+                ParsedPurchase {
+                    id: PurchaseId(1),
+                    descr: "jambon".to_string(),
+                    payers: paid(&[15, 0, 0]),
+                    amount: Money::new(15.into(), None),
+                    benef_to_shares: shares(&[1, 2, 1]),
+                    items: vec![],
+                    adjustments: vec![],
+                    date: None,
+                    rate: None,
+                    category: None,
+                    tags: vec![],
+                },
+                ParsedPurchase {
+                    id: PurchaseId(2),
+                    descr: "vin".to_string(),
+                    payers: paid(&[0, 0, 10]),
+                    amount: Money::new(10.into(), None),
+                    benef_to_shares: shares(&[0, 2, 1]),
+                    items: vec![],
+                    adjustments: vec![],
+                    date: None,
+                    rate: None,
+                    category: None,
+                    tags: vec![],
+                },
+            ],
+            transfers: vec![],
+            ..Default::default()
+        };
+        let orig = accounts.clone();
+        assert_eq!(
+            accounts.add_user("Eska".to_string()),
+            Err(ParseError::UserAlreadyPresent("Eska".to_string()))
+        );
+
+        assert_eq!(
+            accounts.add_user("PlappMachine".to_string()),
+            Ok(UserId(3))
+        );
+        // Adding a user does not change the transactions, it comes after
+        // the others
+        let expected = ParsedAccounts {
+            users: users(&["Eska", "Shuba", "Simon", "PlappMachine"]),
+            purchases: orig.purchases.clone(),
+            transfers: vec![],
+            ..Default::default()
+        };
+        assert_eq!(accounts, expected);
+
+        assert_eq!(
+            accounts.remove_user("Eska".to_string()),
+            Err(ParseError::UserHasData("Eska".to_string())),
+        );
+        assert!(accounts.remove_user("PlappMachine".to_string()).is_ok());
+        assert_eq!(accounts, orig);
+
+        // The id of the removed user is not given to the next one
+        assert_eq!(accounts.add_user("Abel".to_string()), Ok(UserId(4)));
+    }
+
+    #[test]
+    fn strict_mode() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: wine
+    who: Simon
+    amount: "15"
+    benef_to_shares:
+      Shuba: "2"
+      Simon: "1"
+  - descr: bread
+    who: Eska
+    amount: "3"
+    benef_to_shares:
+      Eska: "1"
+"#;
+        // Where the errors are is checked in `source_locations`
+        let strict = |yaml: &str| {
+            ParsedAccounts::from_yaml_reader_with(
+                yaml.as_bytes(),
+                ParseMode::Strict,
+            )
+            .map_err(|err| match err {
+                ParseError::At(_, err) => *err,
+                err => err,
+            })
+        };
+        let bread = PurchaseLocation {
+            index: 1,
+            descr: "bread".to_string(),
+        };
+        let in_bread = |err| Err(ParseError::InPurchase(bread.clone(), err));
+        assert_eq!(
+            strict(yaml),
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes())
+        );
+
+        // Typos are silently ignored in lenient mode only
+        let typo = yaml.replace("Eska: \"1\"", "Eskaa: \"1\"");
+        assert!(ParsedAccounts::from_yaml_reader(typo.as_bytes()).is_ok());
+        assert_eq!(
+            strict(&typo),
+            in_bread(Box::new(ParseError::UnknownBeneficiary(
+                "Eskaa".to_string()
+            )))
+        );
+        let typo = yaml.replace("who: Eska", "who: Eska\n    tag: food");
+        assert!(ParsedAccounts::from_yaml_reader(typo.as_bytes()).is_ok());
+        assert_eq!(
+            strict(&typo),
+            in_bread(Box::new(ParseError::UnknownField(
+                "purchases.1.tag".to_string()
+            )))
+        );
+        let typo = format!("{}transfer: []\n", yaml);
+        assert_eq!(
+            strict(&typo),
+            Err(ParseError::UnknownField("transfer".to_string()))
+        );
+        let typos = format!(
+            "{}transfer: []\n",
+            yaml.replace("who: Eska", "who: Eska\n    tag: food")
+        );
+        match strict(&typos) {
+            Err(ParseError::Several(errors)) => {
+                let causes: Vec<_> =
+                    errors.iter().map(ParseError::root_cause).collect();
+                assert_eq!(
+                    causes,
+                    vec![
+                        &ParseError::UnknownField(
+                            "purchases.1.tag".to_string()
+                        ),
+                        &ParseError::UnknownField("transfer".to_string()),
+                    ]
+                );
+                // The fields in purchases are located in the source
+                assert_eq!(errors[0].position(), Some((12, 5)));
+            }
+            err => panic!("Expected several errors, got {:?}", err),
+        }
+        let typo = yaml.replace("Eska: \"1\"", "Eska: \"0\"");
+        assert!(ParsedAccounts::from_yaml_reader(typo.as_bytes()).is_ok());
+        assert_eq!(
+            strict(&typo),
+            in_bread(Box::new(ParseError::SharesSumToZero(
+                "bread".to_string()
+            )))
+        );
+        let negative = yaml.replace("Simon: \"1\"", "Simon: \"-1\"");
+        assert_eq!(
+            strict(&negative),
+            Err(ParseError::InPurchase(
+                PurchaseLocation {
+                    index: 0,
+                    descr: "wine".to_string(),
+                },
+                Box::new(ParseError::NegativeShares("Simon".to_string()))
+            ))
+        );
+
+        // Transfers must be between two users, of a positive amount
+        let transfer =
+            |transfer: &str| format!("{}transfers:\n  - {}\n", yaml, transfer);
+        let valid = transfer("{from: Eska, to: Shuba, amount: \"2\"}");
+        assert!(strict(&valid).is_ok());
+        for (invalid, err) in [
+            (
+                "{from: Eska, to: Eska, amount: \"2\"}",
+                ParseError::SelfTransfer("Eska".to_string()),
+            ),
+            (
+                "{from: Eska, to: Shuba, amount: \"0\"}",
+                ParseError::NonPositiveTransfer("0".to_string()),
+            ),
+            (
+                "{from: Eska, to: Shuba, amount: \"-2\"}",
+                ParseError::NonPositiveTransfer("-2".to_string()),
+            ),
+        ] {
+            let invalid = transfer(invalid);
+            assert!(
+                ParsedAccounts::from_yaml_reader(invalid.as_bytes()).is_ok()
+            );
+            assert_eq!(
+                strict(&invalid),
+                Err(ParseError::InTransfer(0, Box::new(err)))
+            );
+        }
+
+        let json = serde_json::to_string(
+            &ParsedAccounts::from_yaml_reader(yaml.as_bytes())
+                .unwrap()
+                .as_serializable(),
+        )
+        .unwrap();
+        assert!(
+            ParsedAccounts::from_json_with(&json, ParseMode::Strict).is_ok()
+        );
+        let typo = json.replace("\"users\"", "\"user\":[],\"users\"");
+        assert_eq!(
+            ParsedAccounts::from_json_with(&typo, ParseMode::Strict),
+            Err(ParseError::UnknownField("user".to_string()))
+        );
+    }
+
+    #[test]
+    fn source_locations() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: wine
+    who: Simon
+    amount: "15"
+    benef_to_shares:
+      Shuba: "2"
+      Simon: "1"
+  - descr: bread
+    who: Eskaa
+    amount: "3"
+    benef_to_shares:
+      Eska: "1"
+"#;
+        let err = ParsedAccounts::from_yaml_reader(yaml.as_bytes())
+            .unwrap_err()
+            .in_file("input.yml");
+        assert_eq!(err.position(), Some((11, 10)));
+        assert_eq!(
+            err,
+            ParseError::At(
+                SourceLocation {
+                    file: Some("input.yml".to_string()),
+                    position: Some((11, 10)),
+                },
+                Box::new(ParseError::InPurchase(
+                    PurchaseLocation {
+                        index: 1,
+                        descr: "bread".to_string(),
+                    },
+                    Box::new(ParseError::UnknownUser("Eskaa".to_string()))
+                ))
+            )
+        );
+        assert_eq!(
+            err.to_string(),
+            "input.yml:11:10: In purchase 2 (bread): Unknown user: Eskaa"
+        );
+
+        // Errors of the deserializer are located by it
+        let invalid = yaml.replace("amount: \"3\"", "amount: [3]");
+        let err =
+            ParsedAccounts::from_yaml_reader(invalid.as_bytes()).unwrap_err();
+        assert_eq!(err.position(), Some((12, 13)));
+        assert!(matches!(
+            err,
+            ParseError::At(_, ref inner)
+                if matches!(**inner, ParseError::InPurchase(ref location, _)
+                    if location.index == 1)
+        ));
+
+        let transfer = format!(
+            "{}transfers:\n  - from: Eska\n    to: Shubaa\n    amount: \"1\"\n",
+            yaml.replace("Eskaa", "Eska")
+        );
+        let err =
+            ParsedAccounts::from_yaml_reader(transfer.as_bytes()).unwrap_err();
+        assert_eq!(err.position(), Some((17, 9)));
+        assert_eq!(
+            err.root_cause(),
+            &ParseError::UnknownUser("Shubaa".to_string())
+        );
+    }
+
+    #[test]
+    fn users_order() {
+        let yaml = r#"
+users: [Simon, Eska, Shuba]
+purchases:
+  - descr: wine
+    who: Simon
+    amount: "15"
+    benef_to_shares:
+      Shuba: "2"
+      Simon: "1"
+"#;
+        let accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let names: Vec<_> =
+            accounts.users().iter().map(|user| &user.name[..]).collect();
+        assert_eq!(names, vec!["Simon", "Eska", "Shuba"]);
+        assert_eq!(accounts.user_id("Shuba"), Ok(UserId(2)));
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![10.into(), 0.into(), (-10).into()]
+        );
+        let saved = accounts.as_serializable();
+        assert_eq!(saved.users, vec!["Simon", "Eska", "Shuba"]);
+
+        let duplicate = yaml.replace(
+            "[Simon, Eska, Shuba]",
+            "[Simon, Eska, Shuba, Eska, Simon, Eska]",
+        );
+        assert_eq!(
+            ParsedAccounts::from_yaml_reader(duplicate.as_bytes()),
+            Err(ParseError::DuplicateUser("Eska".to_string(), vec![1, 3, 5]))
+        );
+    }
 
     #[test]
-    fn add_remove_user() {
-        let mut accounts = ParsedAccounts {
-            users: vec![
-                "Eska".to_string(),
-                "Shuba".to_string(),
-                "Simon".to_string(),
-            ],
-            purchases: vec![
-                ParsedPurchase {
-                    descr: "jambon".to_string(),
-                    who_paid: 0,
-                    amount: 15.into(),
-                    benef_to_shares: vec![1.into(), 2.into(), 1.into()],
-                },
-                ParsedPurchase {
-                    descr: "vin".to_string(),
-                    who_paid: 2,
-                    amount: 10.into(),
-                    benef_to_shares: vec![0.into(), 2.into(), 1.into()],
-                },
-            ],
-        };
+    fn rename_user() {
+        let yaml = r#"
+users: [Shuba, Eska, Simon]
+purchases:
+  - descr: wine
+    who: Simon
+    amount: "15"
+    benef_to_shares:
+      Shuba: "2"
+      Simon: "1"
+transfers:
+  - from: Shuba
+    to: Simon
+    amount: "10"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let shuba = accounts.user_id("Shuba").unwrap();
         let orig = accounts.clone();
+        accounts.rename_user(shuba, "Abel".to_string()).unwrap();
+        assert_eq!(accounts.purchases(), orig.purchases());
+        assert_eq!(accounts.transfers(), orig.transfers());
+        let names: Vec<_> =
+            accounts.users().iter().map(|user| &user.name[..]).collect();
+        assert_eq!(names, vec!["Abel", "Eska", "Simon"]);
+        assert_eq!(accounts.user_id("Abel"), Ok(shuba));
         assert_eq!(
-            accounts.add_user("Eska".to_string()),
+            accounts.user_id("Shuba"),
+            Err(ParseError::UnknownUser("Shuba".to_string()))
+        );
+        assert_eq!(
+            accounts.rename_user(shuba, "Eska".to_string()),
             Err(ParseError::UserAlreadyPresent("Eska".to_string()))
         );
-
-        assert!(accounts.add_user("PlappMachine".to_string()).is_ok());
-        let expected = ParsedAccounts {
-            users: vec![
-                "Eska".to_string(),
-                "PlappMachine".to_string(),
-                "Shuba".to_string(),
-                "Simon".to_string(),
-            ],
-            purchases: vec![
-                ParsedPurchase {
-                    descr: "jambon".to_string(),
-                    who_paid: 0,
-                    amount: 15.into(),
-                    benef_to_shares: vec![
-                        1.into(),
-                        0.into(),
-                        2.into(),
-                        1.into(),
-                    ],
-                },
-                ParsedPurchase {
-                    descr: "vin".to_string(),
-                    who_paid: 3,
-                    amount: 10.into(),
-                    benef_to_shares: vec![
-                        0.into(),
-                        0.into(),
-                        2.into(),
-                        1.into(),
-                    ],
-                },
-            ],
-        };
-        assert_eq!(accounts, expected);
-
         assert_eq!(
-            accounts.remove_user("Eska".to_string()),
-            Err(ParseError::UserHasData("Eska".to_string())),
+            accounts.rename_user(shuba, String::new()),
+            Err(ParseError::EmptyUser)
         );
-        assert!(accounts.remove_user("PlappMachine".to_string()).is_ok());
-        assert_eq!(accounts, orig);
+
+        // Ids are kept once saved and loaded again
+        let saved = serde_yaml::to_string(&accounts.as_serializable()).unwrap();
+        assert!(saved.contains("from: Abel"));
+        let reloaded =
+            ParsedAccounts::from_yaml_reader(saved.as_bytes()).unwrap();
+        assert_eq!(reloaded, accounts);
     }
 
     #[test]
     fn add_purchase() {
         let mut accounts = ParsedAccounts {
-            users: vec![
-                "Eska".to_string(),
-                "Shuba".to_string(),
-                "Simon".to_string(),
-            ],
+            users: users(&["Eska", "Shuba", "Simon"]),
             purchases: vec![
                 ParsedPurchase {
+                    id: PurchaseId(1),
                     descr: "jambon".to_string(),
-                    who_paid: 0,
-                    amount: 15.into(),
-                    benef_to_shares: vec![1.into(), 2.into(), 1.into()],
+                    payers: paid(&[15, 0, 0]),
+                    amount: Money::new(15.into(), None),
+                    benef_to_shares: shares(&[1, 2, 1]),
+                    items: vec![],
+                    adjustments: vec![],
+                    date: None,
+                    rate: None,
+                    category: None,
+                    tags: vec![],
                 },
                 ParsedPurchase {
+                    id: PurchaseId(2),
                     descr: "vin".to_string(),
-                    who_paid: 2,
-                    amount: 10.into(),
-                    benef_to_shares: vec![0.into(), 2.into(), 1.into()],
+                    payers: paid(&[0, 0, 10]),
+                    amount: Money::new(10.into(), None),
+                    benef_to_shares: shares(&[0, 2, 1]),
+                    items: vec![],
+                    adjustments: vec![],
+                    date: None,
+                    rate: None,
+                    category: None,
+                    tags: vec![],
                 },
             ],
+            transfers: vec![],
+            ..Default::default()
         };
-        let purchase_idx = accounts
+        let purchase_id = accounts
             .add_purchase("fromage".to_string(), "Shuba".to_string(), 23.into())
             .unwrap();
         accounts
-            .set_purchase_user_share(
-                purchase_idx,
-                "Eska".to_string(),
-                42.into(),
-            )
+            .set_purchase_user_share(purchase_id, "Eska".to_string(), 42.into())
             .unwrap();
         accounts
-            .change_purchase_creditor(purchase_idx, "Simon".to_string())
+            .change_purchase_creditor(purchase_id, "Simon".to_string())
             .unwrap();
         accounts
-            .change_purchase_amount(purchase_idx, 20.into())
+            .change_purchase_amount(purchase_id, 20.into())
             .unwrap();
-        accounts.remove_purchase(0).unwrap();
+        accounts.remove_purchase(PurchaseId(1)).unwrap();
 
         accounts
             .set_purchase_shares(
-                0,
-                [(0, 1.into()), (2, 3.into())].iter().cloned(),
+                PurchaseId(2),
+                [(UserId(0), 1.into()), (UserId(2), 3.into())]
+                    .iter()
+                    .cloned(),
             )
             .unwrap();
         let expected = ParsedAccounts {
-            users: vec![
-                "Eska".to_string(),
-                "Shuba".to_string(),
-                "Simon".to_string(),
-            ],
+            users: users(&["Eska", "Shuba", "Simon"]),
             purchases: vec![
                 ParsedPurchase {
+                    id: PurchaseId(2),
                     descr: "vin".to_string(),
-                    who_paid: 2,
-                    amount: 10.into(),
-                    benef_to_shares: vec![1.into(), 2.into(), 3.into()],
+                    payers: paid(&[0, 0, 10]),
+                    amount: Money::new(10.into(), None),
+                    benef_to_shares: shares(&[1, 2, 3]),
+                    items: vec![],
+                    adjustments: vec![],
+                    date: None,
+                    rate: None,
+                    category: None,
+                    tags: vec![],
                 },
                 ParsedPurchase {
+                    id: PurchaseId(3),
                     descr: "fromage".to_string(),
-                    who_paid: 2,
-                    amount: 20.into(),
-                    benef_to_shares: vec![42.into(), 0.into(), 0.into()],
+                    payers: paid(&[0, 0, 20]),
+                    amount: Money::new(20.into(), None),
+                    benef_to_shares: shares(&[42, 0, 0]),
+                    items: vec![],
+                    adjustments: vec![],
+                    date: None,
+                    rate: None,
+                    category: None,
+                    tags: vec![],
                 },
             ],
+            transfers: vec![],
+            ..Default::default()
         };
         assert_eq!(accounts, expected);
     }
+
+    #[test]
+    fn zero_amount_keeps_payer() {
+        let mut accounts = ParsedAccounts {
+            users: users(&["Eska", "Shuba"]),
+            ..Default::default()
+        };
+        let id = accounts
+            .add_purchase("bread".to_string(), "Shuba".to_string(), 10.into())
+            .unwrap();
+        accounts
+            .set_purchase_user_share(id, "Eska".to_string(), 1.into())
+            .unwrap();
+        accounts.change_purchase_amount(id, 0.into()).unwrap();
+        let purchase = accounts.purchase(id).unwrap();
+        assert_eq!(purchase.single_payer(), Some(UserId(1)));
+        assert_eq!(purchase.paid_by(UserId(1)), 0.into());
+        accounts.change_purchase_amount(id, 12.into()).unwrap();
+        assert_eq!(accounts.purchase(id).unwrap().payers(), &paid(&[0, 12]));
+
+        let saved = serde_yaml::to_string(&accounts.as_serializable()).unwrap();
+        let reloaded = ParsedAccounts::from_yaml_reader(saved.as_bytes());
+        assert_eq!(reloaded, Ok(accounts.clone()));
+        assert_eq!(
+            accounts.user_balances().unwrap(),
+            vec![
+                Amount::from_rational((-12).into()),
+                Amount::from_rational(12.into())
+            ]
+        );
+
+        let id = accounts
+            .add_purchase("water".to_string(), "Eska".to_string(), 0.into())
+            .unwrap();
+        assert_eq!(
+            accounts.purchase(id).unwrap().single_payer(),
+            Some(UserId(0))
+        );
+        let saved = serde_yaml::to_string(&accounts.as_serializable()).unwrap();
+        assert_eq!(
+            ParsedAccounts::from_yaml_reader(saved.as_bytes()),
+            Ok(accounts)
+        );
+    }
+
+    #[test]
+    fn purchase_ids() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: wine
+    who: Simon
+    amount: "15"
+    benef_to_shares:
+      Shuba: "1"
+  - id: 4
+    descr: bread
+    who: Eska
+    amount: "3"
+    benef_to_shares:
+      Eska: "1"
+  - descr: cheese
+    who: Shuba
+    amount: "10"
+    benef_to_shares:
+      Simon: "1"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        // Missing ids follow the greatest one, in the order of the file
+        let ids: Vec<_> = accounts.purchases().iter().map(|p| p.id()).collect();
+        assert_eq!(ids, vec![PurchaseId(5), PurchaseId(4), PurchaseId(6)]);
+
+        // Ids are saved, and do not change when a purchase is removed
+        accounts.remove_purchase(PurchaseId(5)).unwrap();
+        assert_eq!(
+            accounts.remove_purchase(PurchaseId(5)),
+            Err(ParseError::InvalidPurchase(PurchaseId(5)))
+        );
+        let saved = serde_yaml::to_string(&accounts.as_serializable()).unwrap();
+        let mut reloaded =
+            ParsedAccounts::from_yaml_reader(saved.as_bytes()).unwrap();
+        assert_eq!(reloaded, accounts);
+        assert_eq!(reloaded.purchase(PurchaseId(6)).unwrap().descr, "cheese");
+        assert_eq!(
+            reloaded.add_purchase(
+                "ham".to_string(),
+                "Eska".to_string(),
+                5.into()
+            ),
+            Ok(PurchaseId(7))
+        );
+        // The id of a removed purchase is not given again
+        reloaded.remove_purchase(PurchaseId(7)).unwrap();
+        assert_eq!(
+            reloaded.add_purchase(
+                "ham".to_string(),
+                "Eska".to_string(),
+                5.into()
+            ),
+            Ok(PurchaseId(8))
+        );
+        assert_eq!("7".parse(), Ok(PurchaseId(7)));
+        assert_eq!("#7".parse(), Ok(PurchaseId(7)));
+        assert!("bread".parse::<PurchaseId>().is_err());
+
+        let duplicate =
+            yaml.replace("- descr: cheese", "- id: 4\n    descr: cheese");
+        assert_eq!(
+            ParsedAccounts::from_yaml_reader(duplicate.as_bytes()),
+            Err(ParseError::DuplicatePurchaseId(PurchaseId(4)))
+        );
+
+        // No id follows the greatest possible one
+        let last = yaml.replace("id: 4", "id: 18446744073709551615");
+        assert_eq!(
+            cause(ParsedAccounts::from_yaml_reader(last.as_bytes())),
+            Err(ParseError::NoPurchaseIdLeft)
+        );
+        let all_ids = last
+            .replace("- descr: wine", "- id: 1\n    descr: wine")
+            .replace("- descr: cheese", "- id: 2\n    descr: cheese");
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(all_ids.as_bytes()).unwrap();
+        assert_eq!(
+            accounts.add_purchase(
+                "ham".to_string(),
+                "Eska".to_string(),
+                5.into()
+            ),
+            Err(ParseError::NoPurchaseIdLeft)
+        );
+    }
+
+    #[test]
+    fn transfers() {
+        let yaml = r#"
+users:
+  - Eska
+  - Shuba
+  - Simon
+purchases:
+  - descr: wine
+    who: Simon
+    amount: "15"
+    benef_to_shares:
+      Shuba: "2"
+      Simon: "1"
+transfers:
+  - from: Shuba
+    to: Simon
+    amount: "10"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(
+            accounts.transfers(),
+            &[ParsedTransfer {
+                descr: String::new(),
+                from: UserId(1),
+                to: UserId(2),
+                amount: 10.into(),
+                date: None,
+            }]
+        );
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![0.into(), 0.into(), 0.into()]
+        );
+        assert_eq!(
+            accounts.remove_user("Shuba".to_string()),
+            Err(ParseError::UserHasData("Shuba".to_string())),
+        );
+        assert!(accounts.add_user("Abel".to_string()).is_ok());
+        assert_eq!(accounts.transfers()[0].from, UserId(1));
+        assert_eq!(accounts.transfers()[0].to, UserId(2));
+        let transfer_idx = accounts
+            .add_transfer(
+                "refund".to_string(),
+                "Simon".to_string(),
+                "Abel".to_string(),
+                5.into(),
+            )
+            .unwrap();
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![0.into(), 0.into(), 5.into(), (-5).into()]
+        );
+        accounts.remove_transfer(transfer_idx).unwrap();
+        assert_eq!(
+            accounts.remove_transfer(transfer_idx),
+            Err(ParseError::InvalidTransfer(transfer_idx)),
+        );
+        let reparsed = accounts.as_serializable().parse().unwrap();
+        assert_eq!(reparsed, accounts);
+    }
+
+    #[test]
+    fn multiple_payers() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: restaurant
+    payers:
+      Eska: "20"
+      Simon: "10"
+    amount: "30"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(accounts.purchases()[0].single_payer(), None);
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![10.into(), (-10).into(), 0.into()]
+        );
+        let reparsed = accounts.as_serializable().parse().unwrap();
+        assert_eq!(reparsed, accounts);
+
+        accounts
+            .set_purchase_payers(
+                PurchaseId(1),
+                [(UserId(1), 12.into())].iter().cloned(),
+            )
+            .unwrap();
+        assert_eq!(accounts.purchases()[0].single_payer(), Some(UserId(1)));
+        assert_eq!(accounts.purchases()[0].amount.value(), 12.into());
+        accounts
+            .change_purchase_amount(PurchaseId(1), 15.into())
+            .unwrap();
+        assert_eq!(accounts.purchases()[0].payers(), &paid(&[0, 15, 0]));
+
+        let mismatch = yaml.replace("\"30\"", "\"35\"");
+        assert_eq!(
+            cause(ParsedAccounts::from_yaml_reader(mismatch.as_bytes())),
+            Err(ParseError::PayersMismatch("restaurant".to_string())),
+        );
+        let no_payer = yaml.replace(
+            "    payers:\n      Eska: \"20\"\n      Simon: \"10\"\n",
+            "",
+        );
+        assert_eq!(
+            cause(ParsedAccounts::from_yaml_reader(no_payer.as_bytes())),
+            Err(ParseError::InvalidPayers("restaurant".to_string())),
+        );
+    }
+
+    #[test]
+    fn split_modes() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: tartiflette
+    who: Simon
+    amount: "42"
+    benef_to_shares:
+      Eska: "=12"
+      Shuba: "1"
+      Simon: "1"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(
+            accounts.purchases()[0]
+                .splits()
+                .values()
+                .collect::<Vec<_>>(),
+            vec![
+                &Split::Fixed(12.into()),
+                &Split::Shares(1.into()),
+                &Split::Shares(1.into())
+            ]
+        );
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![(-12).into(), (-15).into(), 27.into()]
+        );
+        let reparsed = accounts.as_serializable().parse().unwrap();
+        assert_eq!(reparsed, accounts);
+
+        accounts
+            .set_purchase_user_split(
+                PurchaseId(1),
+                "Shuba".to_string(),
+                Split::Percent(50.into()),
+            )
+            .unwrap();
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![(-12).into(), (-21).into(), 33.into()]
+        );
+
+        let exceeding = yaml.replace("=12", "=50");
+        assert_eq!(
+            cause(ParsedAccounts::from_yaml_reader(exceeding.as_bytes())),
+            Err(ParseError::SplitExceedsAmount("tartiflette".to_string())),
+        );
+    }
+
+    #[test]
+    fn itemized_purchase() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: restaurant
+    who: Eska
+    amount: "44"
+    items:
+      - descr: pizza
+        amount: "20"
+        benef_to_shares:
+          Shuba: "1"
+      - descr: wine
+        amount: "20"
+        benef_to_shares:
+          Eska: "1"
+          Simon: "1"
+    adjustments:
+      - kind: tip
+        amount: "6"
+      - kind: discount
+        amount: "2"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        assert!(accounts.purchases()[0].is_itemized());
+        // The tip and discount add 10% to each item
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![33.into(), (-22).into(), (-11).into()]
+        );
+        let reparsed = accounts.as_serializable().parse().unwrap();
+        assert_eq!(reparsed, accounts);
+
+        let (purchase, _) = accounts.purchase_mut(PurchaseId(1)).unwrap();
+        let item = purchase.add_item("dessert".to_string(), 10.into());
+        purchase
+            .item_mut(item)
+            .unwrap()
+            .set_split(UserId(2), Split::Shares(1.into()));
+        purchase.set_amount_from_lines().unwrap();
+        assert_eq!(purchase.amount.value(), 54.into());
+        // The adjustments now add 8% to each item
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![
+                Rational64::new(216, 5),
+                Rational64::new(-108, 5),
+                Rational64::new(-108, 5)
+            ]
+        );
+
+        let mismatch = yaml.replace("\"44\"", "\"45\"");
+        assert_eq!(
+            cause(ParsedAccounts::from_yaml_reader(mismatch.as_bytes())),
+            Err(ParseError::ItemsMismatch("restaurant".to_string())),
+        );
+    }
+
+    #[test]
+    fn dates() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: wine
+    date: 2021-08-02
+    who: Simon
+    amount: "12"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+  - descr: bread
+    who: Eska
+    amount: "3"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+  - descr: cheese
+    date: 2021-07-30
+    who: Shuba
+    amount: "6"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+transfers:
+  - from: Eska
+    to: Shuba
+    date: 2021-07-31
+    amount: "3"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let end_of_july = Date::new(2021, 7, 31).unwrap();
+        assert_eq!(
+            rationals(accounts.user_balances_as_of(end_of_july).unwrap()),
+            vec![0.into(), 0.into(), 0.into()]
+        );
+        assert_eq!(
+            rationals(
+                accounts
+                    .user_balances_as_of(Date::new(2021, 7, 30).unwrap())
+                    .unwrap()
+            ),
+            vec![(-3).into(), 3.into(), 0.into()]
+        );
+        let transfers = accounts
+            .rounded_settlement_as_of(Date::new(2021, 7, 30).unwrap(), None)
+            .unwrap();
+        assert_eq!(
+            transfers,
+            vec![settlement::Transfer {
+                from: 0,
+                to: 1,
+                amount: 3.into()
+            }]
+        );
+        assert_eq!(
+            accounts.rounded_settlement_as_of(end_of_july, None),
+            Ok(Vec::new())
+        );
+
+        let timeline = accounts.balance_timeline().unwrap();
+        let order: Vec<_> =
+            timeline.iter().map(|step| step.transaction).collect();
+        assert_eq!(
+            order,
+            vec![
+                TransactionRef::Purchase(PurchaseId(2)),
+                TransactionRef::Purchase(PurchaseId(3)),
+                TransactionRef::Transfer(0),
+                TransactionRef::Purchase(PurchaseId(1)),
+            ]
+        );
+        assert_eq!(timeline[2].date, Some(end_of_july));
+        assert_eq!(
+            rationals(timeline[0].balances.clone()),
+            vec![2.into(), (-1).into(), (-1).into()]
+        );
+        assert_eq!(timeline[3].balances, accounts.user_balances().unwrap());
+
+        let reparsed = accounts.as_serializable().parse().unwrap();
+        assert_eq!(reparsed, accounts);
+
+        accounts.sort_by_date();
+        let descrs: Vec<_> = accounts
+            .purchases()
+            .iter()
+            .map(|purchase| purchase.descr.as_str())
+            .collect();
+        assert_eq!(descrs, vec!["bread", "cheese", "wine"]);
+
+        let invalid = yaml.replace("2021-07-31", "2021-07-32");
+        assert!(matches!(
+            cause(ParsedAccounts::from_yaml_reader(invalid.as_bytes())),
+            Err(ParseError::InvalidDate(_))
+        ));
+    }
+
+    #[test]
+    fn currencies() {
+        let yaml = r#"
+base_currency: EUR
+rates:
+  USD: "0.8"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: museum
+    who: Eska
+    currency: USD
+    amount: "30"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+  - descr: taxi
+    who: Simon
+    currency: CHF
+    rate: "0.5"
+    amount: "9"
+    benef_to_shares:
+      Shuba: "1"
+  - descr: bread
+    who: Shuba
+    amount: "3"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(accounts.base_currency(), Some("EUR"));
+        assert_eq!(
+            accounts.purchase_rate(&accounts.purchases()[0]),
+            Ok(Rational64::new(4, 5))
+        );
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![15.into(), Rational64::new(-21, 2), Rational64::new(-9, 2)]
+        );
+        let reparsed = accounts.as_serializable().parse().unwrap();
+        assert_eq!(reparsed, accounts);
+
+        accounts.set_rate("USD".to_string(), 1.into()).unwrap();
+        assert_eq!(
+            rationals(accounts.user_balances().unwrap()),
+            vec![19.into(), Rational64::new(-25, 2), Rational64::new(-13, 2)]
+        );
+        assert_eq!(
+            accounts.set_rate("dollars".to_string(), 1.into()),
+            Err(ParseError::InvalidCurrency("dollars".to_string()))
+        );
+
+        let no_rate = yaml.replace("USD: \"0.8\"", "GBP: \"1.2\"");
+        assert_eq!(
+            cause(ParsedAccounts::from_yaml_reader(no_rate.as_bytes())),
+            Err(ParseError::UnknownRate("USD".to_string())),
+        );
+    }
+
+    #[test]
+    fn minor_units() {
+        let yaml = r#"
+base_currency: EUR
+rates:
+  JPY: "0.0075"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: sushi
+    who: Eska
+    currency: JPY
+    amount: "4000"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+  - descr: bread
+    who: Shuba
+    amount: "3.5"
+    benef_to_shares:
+      Simon: "1"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let sushi = &accounts.purchases()[0];
+        assert_eq!(sushi.amount.to_string(), "4000 JPY");
+        assert_eq!(
+            sushi
+                .rounded_per_user_costs(0)
+                .unwrap()
+                .values()
+                .collect::<Vec<_>>(),
+            vec![&1334.into(), &1333.into(), &1333.into()]
+        );
+        assert_eq!(accounts.purchases()[1].currency(), Some("EUR"));
+        assert_eq!(accounts.purchases()[1].amount.to_string(), "3.50 EUR");
+        assert_eq!(
+            accounts
+                .rounded_balances(None)
+                .unwrap()
+                .iter()
+                .map(Money::to_string)
+                .collect::<Vec<_>>(),
+            vec!["20.00 EUR", "-6.50 EUR", "-13.50 EUR"]
+        );
+
+        // Amounts in the base currency do not repeat it
+        let saved = serde_yaml::to_string(&accounts.as_serializable()).unwrap();
+        assert!(saved.contains("amount: \"3.50\""));
+        assert_eq!(saved.matches("currency: JPY").count(), 1);
+        assert_eq!(saved.matches("currency: EUR").count(), 1);
+
+        // Purchases in the former base currency follow it
+        accounts.set_base_currency(Some("USD".to_string())).unwrap();
+        assert_eq!(accounts.purchases()[1].currency(), Some("USD"));
+        assert_eq!(accounts.purchases()[0].currency(), Some("JPY"));
+
+        let too_precise = yaml.replace("\"4000\"", "\"4000.5\"");
+        assert_eq!(
+            cause(ParsedAccounts::from_yaml_reader(too_precise.as_bytes())),
+            Err(ParseError::AmountTooPrecise("sushi".to_string())),
+        );
+        let too_precise = yaml.replace("\"3.5\"", "\"3.505\"");
+        assert_eq!(
+            cause(ParsedAccounts::from_yaml_reader(too_precise.as_bytes())),
+            Err(ParseError::AmountTooPrecise("bread".to_string())),
+        );
+    }
+
+    #[test]
+    fn spending_report() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: bus
+    who: Eska
+    category: transport
+    tags: [day 1, day 2]
+    amount: "6"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+  - descr: pizza
+    who: Shuba
+    category: food
+    tags: [day 1]
+    amount: "12"
+    benef_to_shares:
+      Shuba: "1"
+      Simon: "1"
+  - descr: gift
+    who: Simon
+    amount: "5"
+    benef_to_shares:
+      Eska: "1"
+"#;
+        let accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let by_category =
+            accounts.spending_report(ReportKey::Category).unwrap();
+        assert_eq!(
+            by_category,
+            vec![
+                SpendingReport {
+                    key: None,
+                    consumed: amounts(&[5, 0, 0]),
+                    paid: amounts(&[0, 0, 5]),
+                },
+                SpendingReport {
+                    key: Some("food".to_string()),
+                    consumed: amounts(&[0, 6, 6]),
+                    paid: amounts(&[0, 12, 0]),
+                },
+                SpendingReport {
+                    key: Some("transport".to_string()),
+                    consumed: amounts(&[2, 2, 2]),
+                    paid: amounts(&[6, 0, 0]),
+                },
+            ]
+        );
+
+        let by_tag = accounts.spending_report(ReportKey::Tag).unwrap();
+        let keys: Vec<_> =
+            by_tag.iter().map(|report| report.key.as_deref()).collect();
+        assert_eq!(keys, vec![None, Some("day 1"), Some("day 2")]);
+        assert_eq!(by_tag[1].consumed, amounts(&[2, 8, 8]));
+
+        let reparsed = accounts.as_serializable().parse().unwrap();
+        assert_eq!(reparsed, accounts);
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        let yaml = r#"
+base_currency: EUR
+rates:
+  USD: "0.912345"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: cake
+    who: Eska
+    amount: "10"
+    benef_to_shares:
+      Eska: "1/3"
+      Shuba: "2/3"
+      Simon: "1.125"
+  - descr: museum
+    currency: USD
+    payers:
+      Shuba: "100/3"
+      Simon: "1/150"
+    amount: "33.34"
+    benef_to_shares:
+      Eska: "33.3%"
+      Shuba: "=1/7"
+      Simon: "1"
+transfers:
+  - from: Simon
+    to: Eska
+    amount: "-0.5"
+"#;
+        let accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let saved = serde_yaml::to_string(&accounts.as_serializable()).unwrap();
+        let reloaded =
+            ParsedAccounts::from_yaml_reader(saved.as_bytes()).unwrap();
+        assert_eq!(reloaded, accounts);
+        assert_eq!(reloaded.user_balances(), accounts.user_balances());
+        assert!(saved.contains("1/3"));
+        assert!(saved.contains("0.912345"));
+        let saved_again =
+            serde_yaml::to_string(&reloaded.as_serializable()).unwrap();
+        assert_eq!(saved_again, saved);
+    }
+
+    #[test]
+    fn rounded_outputs() {
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: cake
+    who: Eska
+    amount: "10"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+"#;
+        let accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let balances = values(accounts.rounded_balances(Some(2)).unwrap());
+        assert_eq!(
+            balances,
+            vec![
+                Rational64::new(667, 100),
+                Rational64::new(-333, 100),
+                Rational64::new(-334, 100),
+            ]
+        );
+        assert_eq!(
+            accounts.purchases()[0]
+                .rounded_per_user_costs(2)
+                .unwrap()
+                .values()
+                .collect::<Vec<_>>(),
+            vec![
+                &Rational64::new(334, 100),
+                &Rational64::new(333, 100),
+                &Rational64::new(333, 100),
+            ]
+        );
+        let transfers = accounts.rounded_settlement(Some(2)).unwrap();
+        let settled: Rational64 =
+            transfers.iter().map(|transfer| transfer.amount).sum();
+        assert_eq!(settled, Rational64::new(667, 100));
+    }
+
+    #[test]
+    fn pathological_ledgers() {
+        // Coprime shares, whose sum does not fit in a `Rational64`
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: cake
+    who: Eska
+    amount: "1/3"
+    benef_to_shares:
+      Eska: "1/2147483647"
+      Shuba: "1/2147483629"
+      Simon: "1/2147483587"
+"#;
+        let parsed = cause(ParsedAccounts::from_yaml_reader(yaml.as_bytes()));
+        if cfg!(feature = "big-rational") {
+            let accounts = parsed.unwrap();
+            assert_eq!(
+                values(accounts.rounded_balances(Some(2)).unwrap()),
+                vec![
+                    Rational64::new(22, 100),
+                    Rational64::new(-11, 100),
+                    Rational64::new(-11, 100)
+                ]
+            );
+        } else {
+            assert_eq!(parsed, Err(ParseError::Overflow("cake".to_string())));
+        }
+
+        // Every purchase can be computed, but not the balances
+        let yaml = r#"
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: bread
+    who: Eska
+    amount: "1/2147483647"
+    benef_to_shares:
+      Shuba: "1"
+  - descr: cheese
+    who: Eska
+    amount: "1/2147483629"
+    benef_to_shares:
+      Shuba: "1"
+  - descr: wine
+    who: Eska
+    amount: "1/2147483587"
+    benef_to_shares:
+      Shuba: "1"
+"#;
+        let parsed = cause(ParsedAccounts::from_yaml_reader(yaml.as_bytes()));
+        if cfg!(feature = "big-rational") {
+            let accounts = parsed.unwrap();
+            let balances = accounts.user_balances().unwrap();
+            assert_eq!(balances[0], -&balances[1]);
+            assert!(balances[0].to_rational().is_none());
+            assert_eq!(
+                accounts.settlement(),
+                Err(ParseError::Overflow("the balances".to_string()))
+            );
+            assert_eq!(
+                values(accounts.rounded_balances(Some(2)).unwrap()),
+                vec![0.into(), 0.into(), 0.into()]
+            );
+        } else {
+            assert_eq!(parsed, Err(ParseError::Overflow("wine".to_string())));
+            let mut accounts = ParsedAccounts::from_yaml_reader(
+                yaml.replace("1/2147483587", "1").as_bytes(),
+            )
+            .unwrap();
+            accounts
+                .change_purchase_amount(
+                    PurchaseId(3),
+                    Rational64::new(1, 2147483587),
+                )
+                .unwrap();
+            assert_eq!(
+                accounts.user_balances(),
+                Err(ParseError::Overflow("wine".to_string()))
+            );
+        }
+
+        // Huge amounts can be computed, but not rounded to cents
+        let yaml = r#"
+users: [Eska, Shuba]
+purchases:
+  - descr: castle
+    who: Eska
+    amount: "1000000000000000000"
+    benef_to_shares:
+      Shuba: "1"
+"#;
+        let accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let overflow = ParseError::Overflow("the balances".to_string());
+        assert_eq!(accounts.rounded_balances(None), Err(overflow.clone()));
+        assert_eq!(accounts.print_balances(None), Err(overflow.clone()));
+        assert_eq!(
+            crate::report::balances(&accounts, None, None),
+            Err(overflow)
+        );
+        assert_eq!(
+            accounts.purchases()[0].rounded_per_user_costs(2),
+            Err(ParseError::Overflow("castle".to_string()))
+        );
+        assert_eq!(
+            values(accounts.rounded_balances(Some(0)).unwrap()),
+            vec![
+                1_000_000_000_000_000_000.into(),
+                (-1_000_000_000_000_000_000).into()
+            ]
+        );
+
+        // Every balance fits, but not the sums tried by the settlement
+        let yaml = r#"
+users: [A, B, C, D]
+purchases:
+  - descr: first
+    who: C
+    amount: "1"
+    benef_to_shares:
+      A: "1"
+      C: "4294967310"
+  - descr: second
+    who: D
+    amount: "1"
+    benef_to_shares:
+      B: "1"
+      D: "4294967356"
+"#;
+        let accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(
+            accounts.settlement(),
+            Err(ParseError::Overflow("the balances".to_string()))
+        );
+        assert_eq!(accounts.rounded_settlement(Some(2)), Ok(vec![]));
+    }
 }
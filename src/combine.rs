@@ -0,0 +1,338 @@
+//! Balances of users across several accounts files
+//!
+//! A group may keep one file per trip and want to know who owes what once
+//! all of them are taken into account. Users are matched across files by
+//! name. Aliases map the name a user has in some files to the name used in
+//! the combined balances. The files must have the same base currency, as
+//! there is no exchange rate between them.
+
+use std::collections::BTreeMap;
+
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, Zero};
+
+use crate::accounts::{ParseError, ParsedAccounts};
+use crate::date::Date;
+use crate::decimal::allocate_rounding;
+use crate::money::{checked_sum, minor_units, Amount, Money, DEFAULT_DECIMALS};
+use crate::settlement::{self, settle};
+
+/// The balances of the users of several accounts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Combined {
+    base_currency: Option<String>,
+    /// The users of all the accounts, by their names once aliased, in the
+    /// order they first appear
+    users: Vec<String>,
+    /// The balances of the users in each accounts, in the order of `users`,
+    /// zero for users absent from the accounts
+    subtotals: Vec<Vec<Amount>>,
+}
+
+impl Combined {
+    /// Combine the balances of `accounts`. A user named as a key of
+    /// `aliases` is counted as the user named by its value.
+    pub fn new(
+        accounts: &[ParsedAccounts],
+        aliases: &BTreeMap<String, String>,
+    ) -> Result<Self, ParseError> {
+        Self::with_balances(accounts, aliases, ParsedAccounts::user_balances)
+    }
+
+    /// Combine the balances of `accounts` at the end of `date`, as `new`
+    pub fn as_of(
+        accounts: &[ParsedAccounts],
+        aliases: &BTreeMap<String, String>,
+        date: Date,
+    ) -> Result<Self, ParseError> {
+        Self::with_balances(accounts, aliases, |accounts| {
+            accounts.user_balances_as_of(date)
+        })
+    }
+
+    fn with_balances(
+        accounts: &[ParsedAccounts],
+        aliases: &BTreeMap<String, String>,
+        user_balances: impl Fn(&ParsedAccounts) -> Result<Vec<Amount>, ParseError>,
+    ) -> Result<Self, ParseError> {
+        let base_currency = accounts
+            .first()
+            .and_then(|accounts| accounts.base_currency());
+        let mut users: Vec<String> = Vec::new();
+        let mut subtotals = Vec::new();
+        for accounts in accounts {
+            if accounts.base_currency() != base_currency {
+                return Err(ParseError::BaseCurrencyMismatch(
+                    base_currency.map(String::from),
+                    accounts.base_currency().map(String::from),
+                ));
+            }
+            let mut balances = vec![Amount::zero(); users.len()];
+            for (user, balance) in
+                accounts.users().iter().zip(user_balances(accounts)?)
+            {
+                let name = aliases.get(&user.name).unwrap_or(&user.name);
+                let index = match users.iter().position(|user| user == name) {
+                    Some(index) => index,
+                    None => {
+                        users.push(name.clone());
+                        balances.push(Amount::zero());
+                        users.len() - 1
+                    }
+                };
+                // Two users of a file may be aliases of the same user
+                balances[index] = balances[index]
+                    .checked_add(&balance)
+                    .ok_or_else(overflow)?;
+            }
+            subtotals.push(balances);
+        }
+        for balances in &mut subtotals {
+            balances.resize(users.len(), Amount::zero());
+        }
+        Ok(Combined {
+            base_currency: base_currency.map(String::from),
+            users,
+            subtotals,
+        })
+    }
+
+    /// The base currency of all the accounts
+    pub fn base_currency(&self) -> Option<&str> {
+        self.base_currency.as_deref()
+    }
+
+    /// The names of the users, in the order of the balances
+    pub fn users(&self) -> &[String] {
+        &self.users
+    }
+
+    /// The balances of the users in each of the combined accounts
+    pub fn subtotals(&self) -> &[Vec<Amount>] {
+        &self.subtotals
+    }
+
+    /// The balances of the users across all the accounts
+    pub fn balances(&self) -> Result<Vec<Amount>, ParseError> {
+        (0..self.users.len())
+            .map(|index| {
+                checked_sum(
+                    self.subtotals.iter().map(|balances| &balances[index]),
+                )
+                .ok_or_else(overflow)
+            })
+            .collect()
+    }
+
+    /// Number of decimals of the base currency
+    pub fn nb_decimals(&self) -> u8 {
+        self.base_currency
+            .as_deref()
+            .map_or(DEFAULT_DECIMALS, minor_units)
+    }
+
+    /// Round balances to `precision` decimals, by default the ones of the
+    /// base currency, such that they still add up to zero
+    fn round(
+        &self,
+        balances: &[Amount],
+        precision: Option<u8>,
+    ) -> Result<Vec<Money>, ParseError> {
+        let nb_decimals = precision.unwrap_or_else(|| self.nb_decimals());
+        Ok(allocate_rounding(balances, nb_decimals)
+            .ok_or_else(overflow)?
+            .into_iter()
+            .map(|balance| Money::new(balance, self.base_currency.clone()))
+            .collect())
+    }
+
+    /// Round the combined balances, as `ParsedAccounts::rounded_balances`
+    pub fn rounded_balances(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<Vec<Money>, ParseError> {
+        self.round(&self.balances()?, precision)
+    }
+
+    /// Round the balances in each of the combined accounts
+    pub fn rounded_subtotals(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<Vec<Vec<Money>>, ParseError> {
+        self.subtotals
+            .iter()
+            .map(|balances| self.round(balances, precision))
+            .collect()
+    }
+
+    /// Compute a settlement of the rounded combined balances. The user ids
+    /// of the transfers are positions in `users`.
+    pub fn rounded_settlement(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<Vec<settlement::Transfer>, ParseError> {
+        let balances: Vec<Rational64> = self
+            .rounded_balances(precision)?
+            .iter()
+            .map(Money::value)
+            .collect();
+        settle(&balances).ok_or_else(overflow)
+    }
+
+    /// Print the combined balances, followed by the balance in each of the
+    /// accounts, named by `names`
+    pub fn print_balances(
+        &self,
+        names: &[String],
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        let subtotals = self.rounded_subtotals(precision)?;
+        for (index, (user, balance)) in self
+            .users
+            .iter()
+            .zip(self.rounded_balances(precision)?)
+            .enumerate()
+        {
+            let details: Vec<String> = names
+                .iter()
+                .zip(&subtotals)
+                .map(|(name, balances)| {
+                    format!("{}: {}", name, balances[index].format(precision))
+                })
+                .collect();
+            println!(
+                "{} has a balance of: {} ({})",
+                user,
+                balance.format(precision),
+                details.join(", "),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn print_settlement(
+        &self,
+        precision: Option<u8>,
+    ) -> Result<(), ParseError> {
+        for transfer in self.rounded_settlement(precision)? {
+            println!(
+                "{} should give {} to {}",
+                self.users[transfer.from],
+                Money::new(transfer.amount, self.base_currency.clone())
+                    .format(precision),
+                self.users[transfer.to],
+            );
+        }
+        Ok(())
+    }
+}
+
+fn overflow() -> ParseError {
+    ParseError::Overflow("the combined balances".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use num_rational::Rational64;
+
+    use super::Combined;
+    use crate::accounts::{ParseError, ParsedAccounts};
+    use crate::money::Money;
+    use crate::settlement::Transfer;
+
+    fn parse(yaml: &str) -> ParsedAccounts {
+        ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap()
+    }
+
+    fn values(balances: Vec<Money>) -> Vec<Rational64> {
+        balances.iter().map(Money::value).collect()
+    }
+
+    #[test]
+    fn combined_balances() {
+        let trip = parse(
+            r#"
+base_currency: EUR
+users: [Eska, Shuba]
+purchases:
+  - descr: train
+    who: Eska
+    amount: "30"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+"#,
+        );
+        let weekend = parse(
+            r#"
+base_currency: EUR
+users: [Simon, Shu]
+purchases:
+  - descr: cabin
+    who: Shu
+    amount: "10"
+    benef_to_shares:
+      Simon: "1"
+"#,
+        );
+        let accounts = vec![trip, weekend];
+
+        let combined = Combined::new(&accounts, &BTreeMap::new()).unwrap();
+        assert_eq!(combined.users(), ["Eska", "Shuba", "Simon", "Shu"]);
+        assert_eq!(
+            values(combined.rounded_balances(None).unwrap()),
+            vec![15.into(), (-15).into(), (-10).into(), 10.into()]
+        );
+
+        let aliases: BTreeMap<_, _> =
+            vec![("Shu".to_string(), "Shuba".to_string())]
+                .into_iter()
+                .collect();
+        let combined = Combined::new(&accounts, &aliases).unwrap();
+        assert_eq!(combined.users(), ["Eska", "Shuba", "Simon"]);
+        assert_eq!(
+            values(combined.rounded_balances(None).unwrap()),
+            vec![15.into(), (-5).into(), (-10).into()]
+        );
+        let subtotals: Vec<_> = combined
+            .rounded_subtotals(None)
+            .unwrap()
+            .into_iter()
+            .map(values)
+            .collect();
+        assert_eq!(
+            subtotals,
+            vec![
+                vec![15.into(), (-15).into(), 0.into()],
+                vec![0.into(), 10.into(), (-10).into()],
+            ]
+        );
+        assert_eq!(
+            combined.rounded_settlement(None).unwrap(),
+            vec![
+                Transfer {
+                    from: 2,
+                    to: 0,
+                    amount: 10.into()
+                },
+                Transfer {
+                    from: 1,
+                    to: 0,
+                    amount: 5.into()
+                },
+            ]
+        );
+
+        let mut dollars = accounts[1].clone();
+        dollars.set_base_currency(Some("USD".to_string())).unwrap();
+        assert_eq!(
+            Combined::new(&[accounts[0].clone(), dollars], &aliases),
+            Err(ParseError::BaseCurrencyMismatch(
+                Some("EUR".to_string()),
+                Some("USD".to_string())
+            ))
+        );
+    }
+}
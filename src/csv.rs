@@ -0,0 +1,523 @@
+//! Purchases as csv, to exchange them with spreadsheets
+//!
+//! Each row is a purchase paid by a single user, with columns `description`,
+//! `payer` and `amount`, in any order, and one column per user with the
+//! share of this user, empty if the user has none. Amounts are in the base
+//! currency. The delimiter and the decimal separator can be chosen to match
+//! the ones of the spreadsheet.
+
+use std::collections::BTreeMap;
+
+use crate::accounts::{
+    ParseError, ParseMode, ParsedAccounts, PurchaseLocation,
+};
+use crate::split::split_to_exact_string;
+
+/// How purchases are written as csv
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvOptions {
+    /// Separates the fields of a row
+    pub delimiter: char,
+    /// Separates the integer part of numbers from their decimals
+    pub decimal_separator: char,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            decimal_separator: '.',
+        }
+    }
+}
+
+impl CsvOptions {
+    /// A number of the csv, as written in accounts
+    fn parse_number(&self, number: &str) -> String {
+        number.replace(self.decimal_separator, ".")
+    }
+
+    /// A number of accounts, as written in the csv
+    fn format_number(&self, number: &str) -> String {
+        number.replace('.', &self.decimal_separator.to_string())
+    }
+
+    fn field(&self, field: &str) -> String {
+        if field.contains(&[self.delimiter, '"', '\n', '\r'][..]) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    fn line(&self, fields: &[String]) -> String {
+        let fields: Vec<String> =
+            fields.iter().map(|field| self.field(field)).collect();
+        format!("{}\n", fields.join(&self.delimiter.to_string()))
+    }
+}
+
+/// A field of a csv file, with the column it starts at, from 1
+struct Field {
+    column: usize,
+    value: String,
+}
+
+/// A row of a csv file, with the line it starts at, from 1
+struct Row {
+    line: usize,
+    fields: Vec<Field>,
+}
+
+impl Row {
+    fn is_blank(&self) -> bool {
+        self.fields
+            .iter()
+            .all(|field| field.value.trim().is_empty())
+    }
+
+    fn value(&self, index: usize) -> &str {
+        self.fields[index].value.trim()
+    }
+}
+
+/// Reads the characters of a csv file, keeping track of where it is
+struct Reader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(csv: &'a str) -> Self {
+        Reader {
+            chars: csv.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    /// Read a field, up to the delimiter or the end of its line
+    fn field(&mut self, delimiter: char) -> Result<Field, ParseError> {
+        let (line, column) = (self.line, self.column);
+        let mut value = String::new();
+        if self.peek() == Some('"') {
+            self.next();
+            loop {
+                match self.next() {
+                    Some('"') if self.peek() == Some('"') => {
+                        self.next();
+                        value.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => value.push(c),
+                    None => {
+                        return Err(ParseError::CsvError(
+                            "Unterminated quoted field".to_string(),
+                        )
+                        .at_position(line, column))
+                    }
+                }
+            }
+            while let Some(c) = self.peek() {
+                if c == delimiter || c == '\n' {
+                    break;
+                }
+                if !c.is_whitespace() {
+                    return Err(ParseError::CsvError(format!(
+                        "Unexpected {:?} after a quoted field",
+                        c
+                    ))
+                    .at_position(self.line, self.column));
+                }
+                self.next();
+            }
+        } else {
+            while let Some(c) = self.peek() {
+                if c == delimiter || c == '\n' {
+                    break;
+                }
+                value.push(c);
+                self.next();
+            }
+            if value.ends_with('\r') {
+                value.pop();
+            }
+        }
+        Ok(Field { column, value })
+    }
+
+    fn rows(mut self, delimiter: char) -> Result<Vec<Row>, ParseError> {
+        let mut rows = Vec::new();
+        while self.peek().is_some() {
+            let mut row = Row {
+                line: self.line,
+                fields: Vec::new(),
+            };
+            loop {
+                row.fields.push(self.field(delimiter)?);
+                match self.next() {
+                    Some(c) if c == delimiter => continue,
+                    _ => break,
+                }
+            }
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+/// The positions of the columns of a csv file, from its header
+struct Columns {
+    descr: usize,
+    payer: usize,
+    amount: usize,
+    /// The users with a column of shares
+    users: Vec<(usize, String)>,
+    len: usize,
+}
+
+impl Columns {
+    fn new(header: &Row) -> Result<Self, ParseError> {
+        let (mut descr, mut payer, mut amount) = (None, None, None);
+        let mut users = Vec::new();
+        for (index, field) in header.fields.iter().enumerate() {
+            let name = field.value.trim();
+            let at_field =
+                |err: ParseError| err.at_position(header.line, field.column);
+            if header.fields[..index]
+                .iter()
+                .any(|field| field.value.trim() == name)
+            {
+                return Err(at_field(ParseError::CsvError(format!(
+                    "Duplicate column {}",
+                    name
+                ))));
+            }
+            match name {
+                "description" => descr = Some(index),
+                "payer" => payer = Some(index),
+                "amount" => amount = Some(index),
+                "" => return Err(at_field(ParseError::EmptyUser)),
+                user => users.push((index, user.to_string())),
+            }
+        }
+        let missing = |name: &str| {
+            ParseError::CsvError(format!("Missing column {}", name))
+                .at_position(header.line, 1)
+        };
+        Ok(Columns {
+            descr: descr.ok_or_else(|| missing("description"))?,
+            payer: payer.ok_or_else(|| missing("payer"))?,
+            amount: amount.ok_or_else(|| missing("amount"))?,
+            users,
+            len: header.fields.len(),
+        })
+    }
+}
+
+/// Where the errors of a purchase read from a row occurred
+fn in_row(err: ParseError, row: &Row) -> ParseError {
+    match err {
+        ParseError::InPurchase(_, err) => *err,
+        err => err,
+    }
+    .at_position(row.line, 1)
+}
+
+/// The users with a column in the header of a csv file, to create accounts
+/// for its purchases
+pub fn csv_users(
+    csv: &str,
+    options: &CsvOptions,
+) -> Result<Vec<String>, ParseError> {
+    let rows = Reader::new(csv).rows(options.delimiter)?;
+    match rows.iter().find(|row| !row.is_blank()) {
+        Some(header) => Ok(Columns::new(header)?
+            .users
+            .into_iter()
+            .map(|(_, user)| user)
+            .collect()),
+        None => Ok(Vec::new()),
+    }
+}
+
+impl ParsedAccounts {
+    /// Write the purchases as csv. Transfers are not written, and purchases
+    /// which cannot be, because they have several payers, items or another
+    /// currency than the base one, are errors.
+    pub fn purchases_to_csv(
+        &self,
+        options: &CsvOptions,
+    ) -> Result<String, ParseError> {
+        let mut header: Vec<String> = vec![
+            "description".to_string(),
+            "payer".to_string(),
+            "amount".to_string(),
+        ];
+        header.extend(self.users().iter().map(|user| user.name.clone()));
+        let mut csv = options.line(&header);
+        for (index, purchase) in self.purchases().iter().enumerate() {
+            let unsupported = |reason: &str| {
+                ParseError::InPurchase(
+                    PurchaseLocation {
+                        index,
+                        descr: purchase.descr.clone(),
+                    },
+                    Box::new(ParseError::CsvError(format!(
+                        "Cannot write a purchase {} as csv",
+                        reason
+                    ))),
+                )
+            };
+            let payer = match purchase.single_payer() {
+                Some(uid) => self.user_name(uid)?,
+                None => return Err(unsupported("with several payers")),
+            };
+            if purchase.is_itemized() {
+                return Err(unsupported("with items"));
+            }
+            if purchase.currency() != self.base_currency() {
+                return Err(unsupported("in another currency"));
+            }
+            let mut fields = vec![
+                purchase.descr.clone(),
+                payer.to_string(),
+                options.format_number(&purchase.amount.value_to_exact_string()),
+            ];
+            fields.extend(purchase.benef_to_shares(self).map(|(_, split)| {
+                if split.is_zero() {
+                    String::new()
+                } else {
+                    options.format_number(&split_to_exact_string(split))
+                }
+            }));
+            csv.push_str(&options.line(&fields));
+        }
+        Ok(csv)
+    }
+
+    /// Add the purchases of a csv file to the accounts, checked according
+    /// to `mode`. Each row is checked on its own, and the errors of all the
+    /// rows are returned, located at their row.
+    pub fn import_csv(
+        &self,
+        csv: &str,
+        options: &CsvOptions,
+        mode: ParseMode,
+    ) -> Result<ParsedAccounts, Vec<ParseError>> {
+        let rows = Reader::new(csv)
+            .rows(options.delimiter)
+            .map_err(|err| vec![err])?;
+        let mut rows = rows.into_iter().filter(|row| !row.is_blank());
+        let header = match rows.next() {
+            Some(header) => header,
+            None => return Ok(self.clone()),
+        };
+        let columns = Columns::new(&header).map_err(|err| vec![err])?;
+        if mode == ParseMode::Strict {
+            for (index, user) in &columns.users {
+                if self.user_id(user).is_err() {
+                    let column = header.fields[*index].column;
+                    return Err(vec![ParseError::UnknownBeneficiary(
+                        user.clone(),
+                    )
+                    .at_position(header.line, column)]);
+                }
+            }
+        }
+        let mut accounts = self.as_serializable();
+        let without_purchases = accounts.without_transactions();
+        let mut errors = Vec::new();
+        for row in rows {
+            if row.fields.len() != columns.len {
+                errors.push(in_row(
+                    ParseError::CsvError(format!(
+                        "Expected {} fields, found {}",
+                        columns.len,
+                        row.fields.len()
+                    )),
+                    &row,
+                ));
+                continue;
+            }
+            let shares: BTreeMap<String, String> = columns
+                .users
+                .iter()
+                .filter(|(index, _)| !row.value(*index).is_empty())
+                .map(|(index, user)| {
+                    (user.clone(), options.parse_number(row.value(*index)))
+                })
+                .collect();
+            let descr = row.value(columns.descr).to_string();
+            let payer = row.value(columns.payer).to_string();
+            let amount = options.parse_number(row.value(columns.amount));
+            let mut alone = without_purchases.clone();
+            alone.push_purchase(
+                descr.clone(),
+                payer.clone(),
+                amount.clone(),
+                shares.clone(),
+            );
+            if let Err(err) = alone.parse_with(mode) {
+                errors.push(in_row(err, &row));
+                continue;
+            }
+            accounts.push_purchase(descr, payer, amount, shares);
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        accounts.parse_with(mode).map_err(|err| vec![err])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{csv_users, CsvOptions};
+    use crate::accounts::{ParseError, ParseMode, ParsedAccounts};
+    use crate::rational::rational_from_str;
+
+    fn errors(
+        accounts: &ParsedAccounts,
+        csv: &str,
+        mode: ParseMode,
+    ) -> Vec<(Option<(usize, usize)>, ParseError)> {
+        accounts
+            .import_csv(csv, &CsvOptions::default(), mode)
+            .unwrap_err()
+            .into_iter()
+            .map(|err| (err.position(), err.root_cause().clone()))
+            .collect()
+    }
+
+    #[test]
+    fn csv_purchases() {
+        let accounts = ParsedAccounts::from_yaml_reader(
+            r#"
+base_currency: EUR
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: wine; red
+    who: Simon
+    amount: "15.5"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "2.5"
+  - descr: "tartiflette \"maison\""
+    who: Eska
+    amount: "42"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let options = CsvOptions {
+            delimiter: ';',
+            decimal_separator: ',',
+        };
+        let csv = accounts.purchases_to_csv(&options).unwrap();
+        assert_eq!(
+            csv,
+            "description;payer;amount;Eska;Shuba;Simon\n\
+             \"wine; red\";Simon;15,50;1;2,5;\n\
+             \"tartiflette \"\"maison\"\"\";Eska;42,00;1;1;1\n"
+        );
+        assert_eq!(
+            csv_users(&csv, &options).unwrap(),
+            vec!["Eska", "Shuba", "Simon"]
+        );
+
+        let mut empty = accounts.clone();
+        for id in accounts.purchases().iter().map(|purchase| purchase.id()) {
+            empty.remove_purchase(id).unwrap();
+        }
+        let imported =
+            empty.import_csv(&csv, &options, ParseMode::Strict).unwrap();
+        assert_eq!(
+            imported.purchases_to_csv(&options).unwrap(),
+            csv,
+            "{:?}",
+            imported
+        );
+        assert_eq!(
+            imported.user_balances().unwrap(),
+            accounts.user_balances().unwrap()
+        );
+
+        let csv = "\
+amount,payer,description,Eska,Shuba
+10,Eska,bread,1,1
+
+3.5,Eskaa,cheese,,1
+abc,Shuba,\"wine
+and more\",1,
+4,Shuba,ham,1
+";
+        assert_eq!(
+            errors(&empty, csv, ParseMode::Lenient),
+            vec![
+                (Some((4, 1)), ParseError::UnknownUser("Eskaa".to_string())),
+                (
+                    Some((5, 1)),
+                    ParseError::RationalParsingFailed(
+                        rational_from_str("abc").unwrap_err()
+                    )
+                ),
+                (
+                    Some((7, 1)),
+                    ParseError::CsvError(
+                        "Expected 5 fields, found 4".to_string()
+                    )
+                ),
+            ]
+        );
+        assert_eq!(
+            errors(&empty, "description,payer,Eska\n", ParseMode::Lenient),
+            vec![(
+                Some((1, 1)),
+                ParseError::CsvError("Missing column amount".to_string())
+            )]
+        );
+        assert_eq!(
+            errors(
+                &empty,
+                "description,payer,amount,Abel\n",
+                ParseMode::Strict
+            ),
+            vec![(
+                Some((1, 26)),
+                ParseError::UnknownBeneficiary("Abel".to_string())
+            )]
+        );
+        assert_eq!(
+            errors(
+                &empty,
+                "description,payer,amount\n\"bread,Eska,1\n",
+                ParseMode::Lenient
+            ),
+            vec![(
+                Some((2, 1)),
+                ParseError::CsvError("Unterminated quoted field".to_string())
+            )]
+        );
+    }
+}
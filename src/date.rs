@@ -0,0 +1,132 @@
+//! Calendar dates of transactions
+//!
+//! Dates are written `YYYY-MM-DD`, eg `2021-07-31`. Only what is needed to
+//! order transactions is supported: there is no notion of time or time zone.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A day of the proleptic Gregorian calendar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    // Field order matters for the derived ordering
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseDateError(String);
+
+impl fmt::Display for ParseDateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not a valid YYYY-MM-DD date", self.0)
+    }
+}
+
+impl std::error::Error for ParseDateError {}
+
+fn is_leap_year(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100))
+        || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Date {
+    /// Build a date, checking that it exists
+    pub fn new(year: u16, month: u8, day: u8) -> Option<Self> {
+        if month == 0 || month > 12 || day == 0 {
+            return None;
+        }
+        if day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Self { year, month, day })
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+}
+
+impl FromStr for Date {
+    type Err = ParseDateError;
+
+    fn from_str(date_str: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDateError(date_str.to_string());
+        let parts: Vec<&str> = date_str.trim().split('-').collect();
+        if let [year, month, day] = parts[..] {
+            if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+                return Err(err());
+            }
+            let all_digits =
+                |part: &str| part.bytes().all(|c| c.is_ascii_digit());
+            if !(all_digits(year) && all_digits(month) && all_digits(day)) {
+                return Err(err());
+            }
+            // No Panic: the parts are made of 2 or 4 digits
+            Date::new(
+                year.parse().unwrap(),
+                month.parse().unwrap(),
+                day.parse().unwrap(),
+            )
+            .ok_or_else(err)
+        } else {
+            Err(err())
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Date;
+
+    #[test]
+    fn parse_date() {
+        assert_eq!("2021-07-31".parse(), Ok(Date::new(2021, 7, 31).unwrap()));
+        assert_eq!(
+            "2020-02-29".parse::<Date>().unwrap().to_string(),
+            "2020-02-29"
+        );
+        for invalid in &[
+            "2021-02-29",
+            "1900-02-29",
+            "2021-13-01",
+            "2021-00-10",
+            "2021-04-31",
+            "2021-7-31",
+            "21-07-31",
+            "2021/07/31",
+            "2021-+7-31",
+            "",
+        ] {
+            assert!(invalid.parse::<Date>().is_err(), "{}", invalid);
+        }
+        assert!(
+            "2021-07-31".parse::<Date>().unwrap()
+                < "2021-08-01".parse::<Date>().unwrap()
+        );
+    }
+}
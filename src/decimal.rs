@@ -0,0 +1,396 @@
+//! Formatting of rational numbers as decimal numbers
+//!
+//! Formatting is done with exact integer arithmetic: the number is scaled
+//! by a power of ten, rounded to an integer according to a rounding mode,
+//! and the digits of that integer are then split into the integral and the
+//! decimal parts.
+
+use num_rational::Rational64;
+
+use crate::money::{checked_sum, round_money, Numeric};
+
+/// Maximal number of decimals. Scaling any `Rational64` by `10^18` fits in
+/// an `i128`.
+pub const MAX_DECIMALS: u8 = 18;
+
+/// How to round a number that cannot be written exactly with the requested
+/// number of decimals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// To the closest decimal, ties to the even one: 0.125 gives 0.12
+    HalfEven,
+    /// To the closest decimal, ties away from zero: 0.125 gives 0.13
+    HalfUp,
+    /// Drop the extra decimals: 0.129 gives 0.12
+    TowardZero,
+}
+
+/// How to format a number as a decimal number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalFormat {
+    /// Number of decimals, at most `MAX_DECIMALS`
+    pub nb_decimals: u8,
+    pub rounding: RoundingMode,
+    /// Whether to keep trailing zeros, printing `1.50` rather than `1.5`
+    pub fixed: bool,
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        Self {
+            nb_decimals: 2,
+            rounding: RoundingMode::HalfUp,
+            fixed: false,
+        }
+    }
+}
+
+/// Round a number to the closest integer multiple of `10^-nb_decimals`
+/// according to the rounding mode, and return that multiple.
+pub fn round_scaled(
+    rat: Rational64,
+    nb_decimals: u8,
+    rounding: RoundingMode,
+) -> i128 {
+    let nb_decimals = std::cmp::min(nb_decimals, MAX_DECIMALS);
+    let numer = *rat.numer() as i128 * 10_i128.pow(nb_decimals as u32);
+    let denom = *rat.denom() as i128;
+    // Work on the absolute value, so that rounding is symmetric
+    let negative = (numer < 0) != (denom < 0);
+    let (numer, denom) = (numer.abs(), denom.abs());
+    let quotient = numer / denom;
+    let remainder = numer % denom;
+    let round_up = match rounding {
+        RoundingMode::TowardZero => false,
+        RoundingMode::HalfUp => 2 * remainder >= denom,
+        RoundingMode::HalfEven => {
+            2 * remainder > denom
+                || (2 * remainder == denom && quotient % 2 == 1)
+        }
+    };
+    let rounded = if round_up { quotient + 1 } else { quotient };
+    if negative {
+        -rounded
+    } else {
+        rounded
+    }
+}
+
+/// Format a number as a decimal number
+pub fn format_decimal(rat: Rational64, format: &DecimalFormat) -> String {
+    let nb_decimals = std::cmp::min(format.nb_decimals, MAX_DECIMALS) as usize;
+    let scaled = round_scaled(rat, nb_decimals as u8, format.rounding);
+    // A number rounded to zero is printed without sign
+    let sign = if scaled < 0 { "-" } else { "" };
+    let digits = format!("{:0width$}", scaled.abs(), width = nb_decimals + 1);
+    let (integral, decimal) = digits.split_at(digits.len() - nb_decimals);
+    let decimal = if format.fixed {
+        decimal
+    } else {
+        decimal.trim_end_matches('0')
+    };
+    if decimal.is_empty() {
+        format!("{}{}", sign, integral)
+    } else {
+        format!("{}{}.{}", sign, integral, decimal)
+    }
+}
+
+/// Round numbers to `nb_decimals` decimals, such that the rounded numbers
+/// add up to the rounded sum of the numbers. In particular, numbers adding
+/// up to zero, like balances, are rounded to numbers adding up to zero.
+///
+/// This uses the largest remainder method: all numbers are first rounded
+/// down, then the numbers with the largest remainders are rounded up until
+/// the expected sum is reached. Ties go to the first numbers.
+///
+/// If the sum of the numbers overflows, the numbers are rounded
+/// independently. Returns `None` if a rounded number does not fit in a
+/// `Rational64`.
+pub fn allocate_rounding<M: Numeric>(
+    values: &[M],
+    nb_decimals: u8,
+) -> Option<Vec<Rational64>> {
+    let nb_decimals = std::cmp::min(nb_decimals, MAX_DECIMALS);
+    let total = match checked_sum(values) {
+        Some(total) => total,
+        None => {
+            return values
+                .iter()
+                .map(|value| round_money(value, nb_decimals))
+                .collect()
+        }
+    };
+    // The rounded down scaled values, and the remainders of the divisions
+    let (mut floors, remainders): (Vec<i128>, Vec<M>) = values
+        .iter()
+        .map(|value| value.floor_scaled(nb_decimals))
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    let scale = 10_i128.pow(nb_decimals as u32);
+    let rounded_total = round_money(&total, nb_decimals)?;
+    // The denominator of the rounded total divides the scale
+    let target = *rounded_total.numer() as i128
+        * (scale / *rounded_total.denom() as i128);
+    let nb_round_up = target - floors.iter().sum::<i128>();
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    // Largest remainder first
+    order.sort_by(|&i, &j| remainders[j].cmp(&remainders[i]).then(i.cmp(&j)));
+    for &i in order.iter().take(nb_round_up.max(0) as usize) {
+        floors[i] += 1;
+    }
+    floors
+        .into_iter()
+        .map(|scaled| {
+            use std::convert::TryInto;
+            Some(Rational64::new(scaled.try_into().ok()?, scale as i64))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        allocate_rounding, format_decimal, DecimalFormat, RoundingMode,
+        MAX_DECIMALS,
+    };
+    use num_rational::{Ratio, Rational64};
+    use proptest::prelude::*;
+
+    fn format(
+        rat: Rational64,
+        nb_decimals: u8,
+        rounding: RoundingMode,
+        fixed: bool,
+    ) -> String {
+        format_decimal(
+            rat,
+            &DecimalFormat {
+                nb_decimals,
+                rounding,
+                fixed,
+            },
+        )
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let cases = [
+            ((1, 8), "0.12", "0.13", "0.12"),
+            ((3, 8), "0.38", "0.38", "0.37"),
+            ((-1, 8), "-0.12", "-0.13", "-0.12"),
+            ((2, 3), "0.67", "0.67", "0.66"),
+            ((-2, 3), "-0.67", "-0.67", "-0.66"),
+        ];
+        for ((numer, denom), half_even, half_up, toward_zero) in &cases {
+            let rat = Rational64::new(*numer, *denom);
+            assert_eq!(
+                &format(rat, 2, RoundingMode::HalfEven, false),
+                half_even
+            );
+            assert_eq!(&format(rat, 2, RoundingMode::HalfUp, false), half_up);
+            assert_eq!(
+                &format(rat, 2, RoundingMode::TowardZero, false),
+                toward_zero
+            );
+        }
+    }
+
+    #[test]
+    fn carry_and_zeros() {
+        let half_up = RoundingMode::HalfUp;
+        assert_eq!(format(Rational64::new(1, 1000), 2, half_up, false), "0");
+        assert_eq!(format(Rational64::new(-1, 1000), 2, half_up, false), "0");
+        assert_eq!(format(Rational64::new(999, 1000), 2, half_up, false), "1");
+        assert_eq!(
+            format(Rational64::new(-19999, 1000), 2, half_up, false),
+            "-20"
+        );
+        assert_eq!(
+            format(Rational64::new(999, 1000), 2, half_up, true),
+            "1.00"
+        );
+        assert_eq!(format(Rational64::new(3, 2), 2, half_up, true), "1.50");
+        assert_eq!(format(Rational64::new(3, 2), 2, half_up, false), "1.5");
+        assert_eq!(format(Rational64::new(3, 2), 0, half_up, true), "2");
+        assert_eq!(format(Rational64::new(-1, 20), 2, half_up, false), "-0.05");
+    }
+
+    #[test]
+    fn large_values() {
+        let max = Rational64::new(i64::MAX, 1);
+        assert_eq!(
+            format(max, MAX_DECIMALS, RoundingMode::HalfUp, false),
+            i64::MAX.to_string()
+        );
+        let tiny = Rational64::new(1, i64::MAX);
+        assert_eq!(
+            format(tiny, MAX_DECIMALS, RoundingMode::HalfUp, false),
+            "0"
+        );
+        assert_eq!(
+            format(-tiny, MAX_DECIMALS, RoundingMode::HalfUp, true),
+            "0.000000000000000000"
+        );
+        assert_eq!(
+            format(Rational64::new(i64::MAX, 3), 2, RoundingMode::HalfUp, true),
+            "3074457345618258602.33"
+        );
+    }
+
+    #[test]
+    fn allocate() {
+        // Three users sharing 10 evenly
+        let thirds = vec![
+            Rational64::new(20, 3),
+            Rational64::new(-10, 3),
+            Rational64::new(-10, 3),
+        ];
+        let rounded = allocate_rounding(&thirds, 2).unwrap();
+        assert_eq!(
+            rounded,
+            vec![
+                Rational64::new(667, 100),
+                Rational64::new(-333, 100),
+                Rational64::new(-334, 100),
+            ]
+        );
+        assert_eq!(rounded.iter().sum::<Rational64>(), 0.into());
+
+        // Per-user costs of a purchase of 10 add up to 10
+        let costs = vec![Rational64::new(10, 3); 3];
+        assert_eq!(
+            allocate_rounding(&costs, 2),
+            Some(vec![
+                Rational64::new(334, 100),
+                Rational64::new(333, 100),
+                Rational64::new(333, 100),
+            ])
+        );
+        assert_eq!(allocate_rounding::<Rational64>(&[], 2), Some(vec![]));
+        assert_eq!(
+            allocate_rounding(&[Rational64::new(1, 2)], 0),
+            Some(vec![1.into()])
+        );
+        // Values which fit, but not once scaled by the decimals
+        let huge = Rational64::from(i64::MAX / 10);
+        assert_eq!(allocate_rounding(&[huge, -huge], 2), None);
+    }
+
+    /// Straightforward implementation using rational arithmetic
+    fn reference(
+        rat: Rational64,
+        nb_decimals: u8,
+        rounding: RoundingMode,
+        fixed: bool,
+    ) -> String {
+        let rat = Ratio::new(*rat.numer() as i128, *rat.denom() as i128);
+        let scaled = rat * Ratio::from_integer(10_i128.pow(nb_decimals as u32));
+        let half = Ratio::new(1, 2);
+        let rounded = match rounding {
+            RoundingMode::TowardZero => scaled.trunc(),
+            RoundingMode::HalfUp => scaled.round(),
+            RoundingMode::HalfEven => {
+                let floor = scaled.floor();
+                let diff = scaled - floor;
+                if diff > half || (diff == half && floor.to_integer() % 2 != 0)
+                {
+                    floor + 1
+                } else {
+                    floor
+                }
+            }
+        }
+        .to_integer();
+        let scale = 10_i128.pow(nb_decimals as u32);
+        let mut result = if rounded < 0 {
+            "-".to_string()
+        } else {
+            String::new()
+        };
+        result += &(rounded.abs() / scale).to_string();
+        if nb_decimals > 0 {
+            let decimals = format!(
+                "{:0width$}",
+                rounded.abs() % scale,
+                width = nb_decimals as usize
+            );
+            let decimals = if fixed {
+                &decimals[..]
+            } else {
+                decimals.trim_end_matches('0')
+            };
+            if !decimals.is_empty() {
+                result += ".";
+                result += decimals;
+            }
+        }
+        result
+    }
+
+    fn rounding_mode() -> impl Strategy<Value = RoundingMode> {
+        prop_oneof![
+            Just(RoundingMode::HalfEven),
+            Just(RoundingMode::HalfUp),
+            Just(RoundingMode::TowardZero),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn allocation_keeps_sum(
+            numers in prop::collection::vec(-1_000_000_i64..1_000_000, 0..10),
+            denom in 1_i64..1_000,
+            nb_decimals in 0_u8..4,
+        ) {
+            let values: Vec<Rational64> = numers
+                .iter()
+                .map(|numer| Rational64::new(*numer, denom))
+                .collect();
+            let rounded = allocate_rounding(&values, nb_decimals).unwrap();
+            let unit = Rational64::new(1, 10_i64.pow(nb_decimals as u32));
+            let total: Rational64 = values.iter().sum();
+            let expected =
+                super::round_scaled(total, nb_decimals, RoundingMode::HalfUp);
+            prop_assert_eq!(
+                rounded.iter().sum::<Rational64>(),
+                Rational64::from_integer(expected as i64) * unit
+            );
+            for (value, rounded) in values.iter().zip(&rounded) {
+                // Each value is rounded either up or down
+                prop_assert!(value - rounded < unit && rounded - value < unit);
+            }
+        }
+
+        #[test]
+        fn matches_reference(
+            numer in any::<i64>(),
+            denom in 1..i64::MAX,
+            nb_decimals in 0..=MAX_DECIMALS,
+            rounding in rounding_mode(),
+            fixed in any::<bool>(),
+        ) {
+            let rat = Rational64::new(numer, denom);
+            prop_assert_eq!(
+                format(rat, nb_decimals, rounding, fixed),
+                reference(rat, nb_decimals, rounding, fixed)
+            );
+        }
+
+        #[test]
+        fn matches_reference_small(
+            numer in -10_000_i64..10_000,
+            denom in 1_i64..1_000,
+            nb_decimals in 0_u8..4,
+            rounding in rounding_mode(),
+            fixed in any::<bool>(),
+        ) {
+            let rat = Rational64::new(numer, denom);
+            prop_assert_eq!(
+                format(rat, nb_decimals, rounding, fixed),
+                reference(rat, nb_decimals, rounding, fixed)
+            );
+        }
+    }
+}
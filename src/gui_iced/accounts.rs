@@ -2,15 +2,24 @@
 
 use iced::{button, text_input, Button, Column, Element, Row, Text, TextInput};
 
-use std::io::Read;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
-use crate::accounts::{ParseError, ParsedAccounts};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::accounts::ParseMode;
+use crate::accounts::{
+    ParseError, ParsedAccounts, PurchaseId, ReportKey, UserId,
+};
 use crate::gui_iced::style;
 use crate::gui_iced::transaction;
+use crate::gui_iced::transfer;
+use crate::lint::{lint, Lint};
 #[cfg(target_arch = "wasm32")]
 use crate::local_storage;
-use crate::rational::rational_to_string;
+use crate::money::Money;
+use crate::settlement::settle;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage::{save_file, FileFormat};
 
 #[derive(Default)]
 pub struct Accounts {
@@ -18,14 +27,21 @@ pub struct Accounts {
     title: String,
     #[cfg(not(target_arch = "wasm32"))]
     path: PathBuf,
+    /// Format in which the file is saved
+    #[cfg(not(target_arch = "wasm32"))]
+    format: FileFormat,
     accounts: ParsedAccounts,
     last_error: Option<ParseError>,
     new_user: String,
     new_user_btn_state: button::State,
     new_user_state: text_input::State,
-    transactions: Vec<transaction::Transaction>,
+    user_names: BTreeMap<UserId, UserName>,
+    transactions: Vec<(PurchaseId, transaction::Transaction)>,
     new_purchase_btn_state: button::State,
     new_transaction: transaction::Transaction,
+    transfers: Vec<transfer::Transfer>,
+    new_transfer_btn_state: button::State,
+    new_transfer: transfer::Transfer,
     #[cfg(target_arch = "wasm32")]
     go_home_btn_state: button::State,
     #[cfg(feature = "debug")]
@@ -33,13 +49,25 @@ pub struct Accounts {
     status: String,
 }
 
+/// Input to rename a user
+#[derive(Default)]
+struct UserName {
+    name: String,
+    state: text_input::State,
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     NewUserStrChange(String),
+    UserNameStrChange(UserId, String),
+    RenameUser(UserId),
     NewTransaction(transaction::Message),
-    TransactionChange(usize, transaction::Message),
+    TransactionChange(PurchaseId, transaction::Message),
+    NewTransfer(transfer::Message),
+    TransferChange(usize, transfer::Message),
     AddUser,
     AddPurchase,
+    AddTransfer,
     #[cfg(target_arch = "wasm32")]
     GoHome,
 }
@@ -63,49 +91,68 @@ impl Accounts {
         let transactions = accounts
             .purchases()
             .iter()
-            .map(|purch| transaction::Transaction::new(purch, accounts.users()))
+            .map(|purchase| {
+                (purchase.id(), transaction::Transaction::new(purchase))
+            })
+            .collect();
+        let transfers = accounts
+            .transfers()
+            .iter()
+            .map(transfer::Transfer::new)
             .collect();
         Ok(Accounts {
             title,
             accounts,
             transactions,
+            transfers,
             ..Default::default()
         })
     }
 
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn from_inexistent_yaml_path(yaml_path: PathBuf) -> Self {
+    pub fn from_inexistent_path(path: PathBuf) -> Self {
         Accounts {
-            path: yaml_path,
+            format: FileFormat::from_path(&path).unwrap_or_default(),
+            path,
             ..Default::default()
         }
     }
 
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn from_yaml_path_and_reader<R: Read>(
-        yaml_path: PathBuf,
-        yaml_reader: R,
+    pub fn from_path_and_contents(
+        path: PathBuf,
+        contents: &str,
     ) -> Result<Self, ParseError> {
-        let accounts = ParsedAccounts::from_yaml_reader(yaml_reader)?;
+        let format = FileFormat::of_file(&path, contents);
+        let accounts =
+            ParsedAccounts::from_str_as(contents, format, ParseMode::Lenient)?;
         let transactions = accounts
             .purchases()
             .iter()
-            .map(|purch| transaction::Transaction::new(purch, accounts.users()))
+            .map(|purchase| {
+                (purchase.id(), transaction::Transaction::new(purchase))
+            })
+            .collect();
+        let transfers = accounts
+            .transfers()
+            .iter()
+            .map(transfer::Transfer::new)
             .collect();
         Ok(Accounts {
-            path: yaml_path,
+            path,
+            format,
             accounts,
             transactions,
+            transfers,
             ..Default::default()
         })
     }
 
+    /// Save the accounts to their file, as the CLI does: they are checked
+    /// first, and the file is replaced at once.
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn save(&self) -> std::io::Result<()> {
-        use std::io;
-        let file = std::fs::File::create(&self.path)?;
-        serde_yaml::to_writer(file, &self.accounts.as_serializable())
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        save_file(&self.path, &self.accounts, self.format, ParseMode::Lenient)
     }
 
     pub fn new(title: String) -> Self {
@@ -139,45 +186,105 @@ impl Accounts {
             Message::AddUser => {
                 let mut new_user = String::new();
                 std::mem::swap(&mut self.new_user, &mut new_user);
-                self.accounts.add_user(new_user)
+                self.accounts.add_user(new_user).map(|_| ())
             }
             Message::NewUserStrChange(new_user) => {
                 self.new_user = new_user;
                 Ok(())
             }
+            Message::UserNameStrChange(uid, name) => {
+                self.user_names.entry(uid).or_default().name = name;
+                Ok(())
+            }
+            Message::RenameUser(uid) => {
+                let name = self.user_names.entry(uid).or_default().name.clone();
+                self.accounts.rename_user(uid, name)
+            }
             Message::NewTransaction(message) => {
-                self.new_transaction.update(
-                    message,
-                    self.accounts.users(),
-                    None,
-                );
+                self.new_transaction.update(message, None);
                 Ok(())
             }
             Message::AddPurchase => {
-                self.transactions.push(self.new_transaction.clone());
-                let purchase_idx = self.accounts.add_purchase(
-                    self.new_transaction.take_descr(),
-                    self.new_transaction.take_creditor(),
-                    self.new_transaction.take_amount(),
-                );
-                if let Ok(purchase_idx) = purchase_idx {
-                    self.accounts.set_purchase_shares(
-                        purchase_idx,
-                        self.new_transaction.per_user_shares(),
-                    )
+                let transaction = self.new_transaction.clone();
+                let payers: Vec<_> = self.new_transaction.payers().collect();
+                self.new_transaction.clear_payers();
+                let descr = self.new_transaction.take_descr();
+                let amount = self.new_transaction.take_amount();
+                let accounts = &mut self.accounts;
+                // No Panic: a transaction can only be added with a payer
+                let purchase_id = accounts
+                    .user_name(payers[0].0)
+                    .map(String::from)
+                    .and_then(|first_payer| {
+                        accounts.add_purchase(descr, first_payer, amount)
+                    });
+                if let Ok(purchase_id) = purchase_id {
+                    self.transactions.push((purchase_id, transaction));
+                    if let Ok((purchase, _)) =
+                        self.accounts.purchase_mut(purchase_id)
+                    {
+                        self.new_transaction.fill_lines(purchase);
+                    }
+                    self.new_transaction.clear_lines();
+                    self.accounts
+                        .set_purchase_payers(purchase_id, payers.into_iter())
+                        .and_then(|_| {
+                            self.accounts.set_purchase_splits(
+                                purchase_id,
+                                self.new_transaction.per_user_splits(),
+                            )
+                        })
                 } else {
-                    purchase_idx.map(|_| ())
+                    purchase_id.map(|_| ())
                 }
             }
-            Message::TransactionChange(purchase_idx, message) => {
-                let transaction = self.accounts.purchase_mut(purchase_idx);
+            Message::TransactionChange(purchase_id, message) => {
+                let transaction = self.accounts.purchase_mut(purchase_id);
                 match transaction {
-                    Ok((transaction, users)) => {
-                        self.transactions[purchase_idx].update(
-                            message,
-                            users,
-                            Some(transaction),
-                        );
+                    Ok((transaction, _)) => {
+                        if let Some((_, gui_transaction)) = self
+                            .transactions
+                            .iter_mut()
+                            .find(|(id, _)| *id == purchase_id)
+                        {
+                            gui_transaction.update(message, Some(transaction));
+                        }
+                        Ok(())
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            Message::NewTransfer(message) => {
+                self.new_transfer.update(message, None);
+                Ok(())
+            }
+            Message::AddTransfer => {
+                self.transfers.push(self.new_transfer.clone());
+                match self.new_transfer.take_users() {
+                    Some((from, to)) => {
+                        let descr = self.new_transfer.take_descr();
+                        let amount = self.new_transfer.take_amount();
+                        let from =
+                            self.accounts.user_name(from).map(String::from);
+                        let to = self.accounts.user_name(to).map(String::from);
+                        match (from, to) {
+                            (Ok(from), Ok(to)) => self
+                                .accounts
+                                .add_transfer(descr, from, to, amount)
+                                .map(|_| ()),
+                            (Err(err), _) | (_, Err(err)) => Err(err),
+                        }
+                    }
+                    None => Err(ParseError::InvalidState(
+                        "Transfer users should be selected".to_string(),
+                    )),
+                }
+            }
+            Message::TransferChange(transfer_idx, message) => {
+                match self.accounts.transfer_mut(transfer_idx) {
+                    Ok(transfer) => {
+                        self.transfers[transfer_idx]
+                            .update(message, Some(transfer));
                         Ok(())
                     }
                     Err(err) => Err(err),
@@ -221,16 +328,46 @@ impl Accounts {
                     .on_press(Message::GoHome),
             );
         }
+        // Overflows are reported with the balances
+        let lints = lint(&self.accounts).unwrap_or_default();
+        let accounts = &self.accounts;
+        let warning = |lint: &Lint| {
+            Text::new(format!("Warning: {}", lint.describe(accounts)))
+                .color([1.0, 0.5, 0.])
+        };
         let mut users_row = Row::new().spacing(20);
         users_row = users_row
             .push(Text::new(format!("{} users:", self.accounts.users().len())));
+        // Users are renamed by editing their name
+        for user in self.accounts.users() {
+            self.user_names.entry(user.id).or_insert_with(|| UserName {
+                name: user.name.clone(),
+                ..Default::default()
+            });
+        }
+        let mut user_names: BTreeMap<_, _> = self
+            .user_names
+            .iter_mut()
+            .map(|(uid, input)| (*uid, input))
+            .collect();
         for user in self.accounts.users() {
+            let uid = user.id;
+            // No Panic: there is an input for each user
+            let input = user_names.remove(&uid).unwrap();
             users_row = users_row.push(
-                Text::new(user.clone())
-                    .horizontal_alignment(iced::HorizontalAlignment::Center),
+                TextInput::new(
+                    &mut input.state,
+                    "Name",
+                    &input.name,
+                    move |name| Message::UserNameStrChange(uid, name),
+                )
+                .on_submit(Message::RenameUser(uid)),
             );
         }
         column = column.push(users_row);
+        for lint in lints.iter().filter(|lint| lint.purchase().is_none()) {
+            column = column.push(warning(lint));
+        }
         column = column.push(
             Row::new()
                 .spacing(10)
@@ -257,12 +394,17 @@ impl Accounts {
             "{} transactions:",
             self.accounts.purchases().len()
         )));
-        for (tid, transaction) in self.transactions.iter_mut().enumerate() {
+        for (tid, transaction) in self.transactions.iter_mut() {
+            let tid = *tid;
             column = column.push(
                 transaction
                     .view(self.accounts.users())
                     .map(move |msg| Message::TransactionChange(tid, msg)),
             );
+            for lint in lints.iter().filter(|lint| lint.purchase() == Some(tid))
+            {
+                column = column.push(warning(lint));
+            }
         }
         if self.accounts.users().len() > 0 {
             let can_add_transaction = self.new_transaction.is_valid();
@@ -290,15 +432,123 @@ impl Accounts {
             }
         }
 
+        column = column.push(Text::new(format!(
+            "{} transfers:",
+            self.accounts.transfers().len()
+        )));
+        for (tid, transfer) in self.transfers.iter_mut().enumerate() {
+            column = column.push(
+                transfer
+                    .view(self.accounts.users())
+                    .map(move |msg| Message::TransferChange(tid, msg)),
+            );
+        }
+        if self.accounts.users().len() > 1 {
+            let can_add_transfer = self.new_transfer.is_valid();
+            column = column.push(
+                self.new_transfer
+                    .view(self.accounts.users())
+                    .map(Message::NewTransfer),
+            );
+            if can_add_transfer {
+                column = column.push(
+                    Button::new(
+                        &mut self.new_transfer_btn_state,
+                        Text::new("Add transfer"),
+                    )
+                    .style(style::Button)
+                    .padding(2)
+                    .on_press(Message::AddTransfer),
+                );
+            }
+        }
+
         column = column.push(Text::new("User balances:"));
-        let balances = self.accounts.user_balances();
         let users = self.accounts.users();
-        for (user, balance) in users.iter().zip(&balances) {
-            column = column.push(Text::new(format!(
-                "{} has a balance of: {}",
-                user,
-                rational_to_string(*balance, 2),
-            )));
+        let base_currency = self.accounts.base_currency().map(String::from);
+        match self.accounts.rounded_balances(None) {
+            Ok(balances) => {
+                for (user, balance) in users.iter().zip(&balances) {
+                    column = column.push(Text::new(format!(
+                        "{} has a balance of: {}",
+                        user.name, balance,
+                    )));
+                }
+
+                column = column.push(Text::new("Settlement:"));
+                let balances: Vec<_> =
+                    balances.iter().map(Money::value).collect();
+                match settle(&balances) {
+                    Some(transfers) => {
+                        if transfers.is_empty() {
+                            column =
+                                column.push(Text::new("Nothing to settle"));
+                        }
+                        for transfer in transfers {
+                            column = column.push(Text::new(format!(
+                                "{} should give {} to {}",
+                                users[transfer.from].name,
+                                Money::new(
+                                    transfer.amount,
+                                    base_currency.clone()
+                                ),
+                                users[transfer.to].name,
+                            )));
+                        }
+                    }
+                    None => {
+                        let err =
+                            ParseError::Overflow("the balances".to_string());
+                        column = column.push(
+                            Text::new(format!("Error: {}", err))
+                                .color([1.0, 0., 0.]),
+                        )
+                    }
+                }
+            }
+            Err(err) => {
+                column = column.push(
+                    Text::new(format!("Error: {}", err)).color([1.0, 0., 0.]),
+                )
+            }
+        }
+
+        // Overflows are reported with the balances
+        let reports = self
+            .accounts
+            .spending_report(ReportKey::Category)
+            .unwrap_or_default();
+        if !reports.is_empty() {
+            column = column
+                .push(Text::new("Spending by category (consumed / paid):"));
+            let mut table = Row::new().spacing(20).push(reports.iter().fold(
+                Column::new().push(Text::new("Category")),
+                |col, report| {
+                    col.push(Text::new(
+                        report.key.as_deref().unwrap_or("(none)"),
+                    ))
+                },
+            ));
+            for (uid, user) in users.iter().enumerate() {
+                table = table.push(reports.iter().fold(
+                    Column::new().push(Text::new(&user.name)),
+                    |col, report| {
+                        let format = |value| {
+                            Money::rounded(value, base_currency.clone())
+                                .map_or_else(
+                                    || "overflow".to_string(),
+                                    |money| money.format_value(None),
+                                )
+                        };
+                        col.push(Text::new(format!(
+                            "{} / {}",
+                            format(&report.consumed[uid]),
+                            format(&report.paid[uid]),
+                        )))
+                    },
+                ));
+            }
+            column = column.push(table);
         }
 
         if let Some(last_error) = &self.last_error {
@@ -4,12 +4,16 @@
 use iced::Row;
 use iced::{button, text_input, Button, Column, Element, Text, TextInput};
 
+#[cfg(target_arch = "wasm32")]
+use crate::accounts::{ParseMode, ParsedAccounts};
 #[cfg(target_arch = "wasm32")]
 use crate::gui_iced::file_input::FileInput;
 use crate::gui_iced::style;
 #[cfg(target_arch = "wasm32")]
 use crate::local_storage;
 #[cfg(target_arch = "wasm32")]
+use crate::storage::FileFormat;
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::{closure::Closure, JsCast, JsValue};
 #[cfg(target_arch = "wasm32")]
 use web_sys::console;
@@ -62,7 +66,7 @@ impl FileSelector {
             #[cfg(target_arch = "wasm32")]
             Message::UploadAccounts => {
                 let document = web_sys::window()?.document()?;
-                let elem = document.get_element_by_id("upload_json")?;
+                let elem = document.get_element_by_id("upload_accounts")?;
                 // FIXME errors should be displayed ?
                 let input: &web_sys::HtmlInputElement = elem.dyn_ref()?;
                 let file = input.files()?.item(0)?;
@@ -75,12 +79,26 @@ impl FileSelector {
                             event_tgt.dyn_ref().ok_or(JsValue::NULL)?;
                         let result = reader.result()?;
                         let result = result.as_string().ok_or(JsValue::NULL)?;
+                        // Accounts are kept as json in local storage
+                        let format = FileFormat::of_file(
+                            std::path::Path::new(&name),
+                            &result,
+                        );
+                        let json = ParsedAccounts::from_str_as(
+                            &result,
+                            format,
+                            ParseMode::Lenient,
+                        )
+                        .and_then(|accounts| {
+                            accounts.to_string_as(FileFormat::Json)
+                        })
+                        .map_err(|err| JsValue::from_str(&err.to_string()))?;
                         // path less name so this works
                         let name =
                             &name[..name.rfind(".").unwrap_or(name.len())];
                         local_storage::set_item(
                             &format!("aaacs:{}", name),
-                            &result,
+                            &json,
                         )?;
                         console::log_1(
                             &format!(
@@ -127,8 +145,8 @@ impl FileSelector {
             let mut row = Row::new().spacing(10);
             row = row.push(Text::new("Upload accounts:"));
             row = row.push(FileInput {
-                accept: ".json".to_string(),
-                id: "upload_json".to_string(),
+                accept: ".json,.yml,.yaml,.toml".to_string(),
+                id: "upload_accounts".to_string(),
             });
             row = row.push(
                 Button::new(
@@ -152,16 +170,22 @@ impl FileSelector {
 
 mod existing_accounts {
 
+    #[cfg(target_arch = "wasm32")]
+    use crate::accounts::ParsedAccounts;
     use crate::gui_iced::style;
     #[cfg(target_arch = "wasm32")]
     use crate::gui_iced::url::UrlA;
+    #[cfg(target_arch = "wasm32")]
+    use crate::storage::FileFormat;
     use iced::{button, Button, Element, Row, Text};
 
     #[derive(Default)]
     pub struct ExistingAccounts {
         title: String,
+        /// The accounts in each format they can be downloaded in, base64
+        /// encoded
         #[cfg(target_arch = "wasm32")]
-        json_b64: String,
+        downloads: Vec<(FileFormat, String)>,
         open_button_state: button::State,
     }
 
@@ -173,9 +197,27 @@ mod existing_accounts {
     impl ExistingAccounts {
         #[cfg(target_arch = "wasm32")]
         pub fn new(title: String, json: &str) -> Self {
+            let downloads = match ParsedAccounts::from_json(json) {
+                Ok(accounts) => FileFormat::ALL
+                    .iter()
+                    .filter_map(|&format| {
+                        Some((format, accounts.to_string_as(format).ok()?))
+                    })
+                    .collect(),
+                // Still let the stored json be downloaded, to be fixed
+                Err(_) => vec![(FileFormat::Json, json.to_string())],
+            };
             Self {
                 title,
-                json_b64: data_encoding::BASE64.encode(json.as_bytes()),
+                downloads: downloads
+                    .into_iter()
+                    .map(|(format, contents)| {
+                        (
+                            format,
+                            data_encoding::BASE64.encode(contents.as_bytes()),
+                        )
+                    })
+                    .collect(),
                 ..Default::default()
             }
         }
@@ -195,11 +237,20 @@ mod existing_accounts {
                 );
             #[cfg(target_arch = "wasm32")]
             {
-                row = row.push(UrlA {
-                    text: "Download json".to_string(),
-                    href: format!("data:text/plain;base64,{}", self.json_b64),
-                    download: format!("{}.json", self.title),
-                });
+                for (format, contents_b64) in &self.downloads {
+                    row = row.push(UrlA {
+                        text: format!("Download {}", format),
+                        href: format!(
+                            "data:text/plain;base64,{}",
+                            contents_b64
+                        ),
+                        download: format!(
+                            "{}.{}",
+                            self.title,
+                            format.extension()
+                        ),
+                    });
+                }
             }
             row.into()
         }
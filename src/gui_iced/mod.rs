@@ -10,6 +10,7 @@ mod accounts;
 mod file_selector;
 mod style;
 mod transaction;
+mod transfer;
 use file_selector::FileSelector;
 
 #[cfg(target_arch = "wasm32")]
@@ -75,12 +76,12 @@ impl Application for Aaacs {
         #[cfg(not(target_arch = "wasm32"))]
         {
             if let Some(path) = flags.file {
-                let accounts_file = std::fs::File::open(&path);
-                match accounts_file {
-                    Ok(accounts_file) => {
-                        let accounts = Accounts::from_yaml_path_and_reader(
+                let contents = std::fs::read_to_string(&path);
+                match contents {
+                    Ok(contents) => {
+                        let accounts = Accounts::from_path_and_contents(
                             path.clone(),
-                            accounts_file,
+                            &contents,
                         );
                         match accounts {
                             Ok(accounts) => {
@@ -88,7 +89,7 @@ impl Application for Aaacs {
                             }
                             Err(err) => (
                                 Aaacs::FatalError(format!(
-                                    "Could not parse yaml file {:?}: {:?}",
+                                    "Could not parse accounts file {:?}: {}",
                                     path, err,
                                 )),
                                 Command::none(),
@@ -97,9 +98,9 @@ impl Application for Aaacs {
                     }
                     Err(err) => match err.kind() {
                         std::io::ErrorKind::NotFound => (
-                            Aaacs::Editing(
-                                Accounts::from_inexistent_yaml_path(path),
-                            ),
+                            Aaacs::Editing(Accounts::from_inexistent_path(
+                                path,
+                            )),
                             Command::none(),
                         ),
                         _ => (
@@ -1,44 +1,68 @@
 //! Widget describing a transaction being edited
 
-use iced::{text_input, Column, Element, Radio, Row, Text, TextInput};
+use std::collections::BTreeMap;
+
+use iced::{button, text_input, Button, Column, Element, Row, Text, TextInput};
 
 use num_rational::Rational64;
 
-use crate::accounts::ParsedPurchase;
-use crate::rational::{rational_from_str, rational_to_string};
+use crate::accounts::{AdjustmentKind, ParsedPurchase, User, UserId};
+use crate::money::checked_sum;
+use crate::rational::{
+    rational_from_str, rational_to_exact_string, ParseRationalError,
+};
+use crate::split::Split;
 
 #[derive(Default, Clone)]
 pub struct Transaction {
     descr: String,
     descr_state: text_input::State,
-    uid: usize,
-    creditor: String,
     amount: String,
     amount_state: text_input::State,
-    shares: Vec<shares::Share>,
+    amount_error: Option<ParseRationalError>,
+    paid: BTreeMap<UserId, shares::Paid>,
+    shares: BTreeMap<UserId, shares::Share>,
+    items: Vec<lines::Item>,
+    adjustments: Vec<lines::Adjustment>,
+    add_item_btn_state: button::State,
+    add_adjustment_btn_state: button::State,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     DescrStrChange(String),
-    UserSelected(usize),
     AmountStrChange(String),
-    ShareChanged(usize, shares::Message),
+    PaidChanged(UserId, shares::Message),
+    ShareChanged(UserId, shares::Message),
+    AddItem,
+    AddAdjustment,
+    ItemChanged(usize, lines::ItemMessage),
+    AdjustmentChanged(usize, lines::AdjustmentMessage),
 }
 
 impl Transaction {
-    pub fn new(purchase: &ParsedPurchase, users: &[String]) -> Self {
+    pub fn new(purchase: &ParsedPurchase) -> Self {
+        let paid = purchase
+            .payers()
+            .iter()
+            .map(|(uid, x)| (*uid, shares::Paid::from_rat(*x)))
+            .collect();
         let shares = purchase
-            .shares()
+            .splits()
             .iter()
-            .map(|x| shares::Share::from_rat(*x))
+            .map(|(uid, x)| (*uid, shares::Share::from_split(*x)))
             .collect();
         Self {
             descr: purchase.descr.clone(),
-            uid: purchase.who_paid,
-            creditor: users[purchase.who_paid].clone(),
-            amount: rational_to_string(purchase.amount, 2),
+            amount: purchase.amount.value_to_exact_string(),
+            paid,
             shares: shares,
+            items: purchase.items().iter().map(lines::Item::new).collect(),
+            adjustments: purchase
+                .adjustments()
+                .iter()
+                .map(lines::Adjustment::new)
+                .collect(),
             ..Default::default()
         }
     }
@@ -46,7 +70,6 @@ impl Transaction {
     pub fn update(
         &mut self,
         message: Message,
-        users: &[String],
         to_update: Option<&mut ParsedPurchase>,
     ) {
         match message {
@@ -54,59 +77,192 @@ impl Transaction {
                 self.descr = new_descr.clone();
                 to_update.map(|trans| trans.descr = new_descr);
             }
+            // The amount of an itemized purchase is the total of its lines
+            Message::AmountStrChange(_) if !self.items.is_empty() => (),
             Message::AmountStrChange(new_amount) => {
-                if new_amount.len() == 0 {
-                    self.amount = new_amount;
-                    to_update.map(|trans| trans.amount = 0.into());
-                } else if let Ok(val) = rational_from_str(&new_amount) {
-                    self.amount = new_amount;
-                    to_update.map(|trans| trans.amount = val);
+                let val = if new_amount.is_empty() {
+                    Ok(0.into())
+                } else {
+                    rational_from_str(&new_amount)
+                };
+                // Invalid amounts are accepted while typing, but are not
+                // applied.
+                self.amount = new_amount;
+                self.amount_error = val.as_ref().err().cloned();
+                if let Ok(val) = val {
+                    // A single payer pays the whole amount
+                    let payers: Vec<_> = self.payers().collect();
+                    if let [(uid, _)] = payers[..] {
+                        self.paid.insert(uid, shares::Paid::from_rat(val));
+                    }
+                    if let Some(trans) = to_update {
+                        trans.set_amount(val);
+                    }
                 }
             }
-            Message::UserSelected(uid) => {
-                self.creditor = users[uid].to_string();
-                self.uid = uid;
-                to_update.map(|trans| trans.who_paid = uid);
+            Message::PaidChanged(uid, message) => {
+                let paid = self.paid.entry(uid).or_default();
+                paid.update(message);
+                let value = paid.value().unwrap_or(0.into());
+                // On overflow, the amount is left as is, and the payers will
+                // be reported as not matching it.
+                if let Some(total) = self.total_paid() {
+                    self.amount = rational_to_exact_string(total);
+                    self.amount_error = None;
+                }
+                if let Some(trans) = to_update {
+                    trans.set_payer_amount(uid, value);
+                }
             }
             Message::ShareChanged(uid, message) => {
-                self.shares[uid].update(message);
-                to_update.map(|trans| {
-                    trans.set_share(
-                        uid,
-                        self.shares[uid].value().unwrap_or(0.into()),
-                    )
-                });
-            }
-        }
-    }
-
-    pub fn view(&mut self, users: &[String]) -> Element<Message> {
-        let mut user_choice = Column::new();
-        self.creditor = users[self.uid].to_string();
-        for (uid, user) in users.iter().enumerate() {
-            user_choice = user_choice.push(Radio::new(
-                uid,
-                user,
-                Some(self.uid),
-                Message::UserSelected,
-            ));
-        }
-        if self.shares.len() != users.len() {
-            // number of users has changed, probably because a new user
-            // has been added. If that is the case, we are probably the
-            // new transaction widget, and the safest thing to do is to
-            // reset the shares to an empty value.
-            self.shares.clear();
-            self.shares.resize(users.len(), Default::default());
-        }
-        let mut shares_col = Column::new().max_width(100);
-        for (uid, share) in self.shares.iter_mut().enumerate() {
-            shares_col = shares_col.push(
-                Row::new().spacing(5).push(Text::new(&users[uid])).push(
+                let share = self.shares.entry(uid).or_default();
+                share.update(message);
+                let value = share.value().unwrap_or_default();
+                if let Some(trans) = to_update {
+                    trans.set_split(uid, value);
+                }
+            }
+            Message::AddItem => {
+                self.items.push(Default::default());
+                if let Some(trans) = to_update {
+                    trans.add_item(String::new(), 0.into());
+                    self.update_amount_from_lines(Some(trans));
+                }
+            }
+            Message::AddAdjustment => {
+                self.adjustments.push(Default::default());
+                if let Some(trans) = to_update {
+                    trans.add_adjustment(AdjustmentKind::Tax, 0.into());
+                    self.update_amount_from_lines(Some(trans));
+                }
+            }
+            Message::ItemChanged(item_idx, message) => {
+                self.items[item_idx].update(message);
+                if let Some(trans) = to_update {
+                    if let Ok(item) = trans.item_mut(item_idx) {
+                        self.items[item_idx].apply(item);
+                    }
+                    self.update_amount_from_lines(Some(trans));
+                } else {
+                    self.update_amount_from_lines(None);
+                }
+            }
+            Message::AdjustmentChanged(adjustment_idx, message) => {
+                self.adjustments[adjustment_idx].update(message);
+                if let Some(trans) = to_update {
+                    if let Ok(adjustment) = trans.adjustment_mut(adjustment_idx)
+                    {
+                        self.adjustments[adjustment_idx].apply(adjustment);
+                    }
+                    self.update_amount_from_lines(Some(trans));
+                } else {
+                    self.update_amount_from_lines(None);
+                }
+            }
+        }
+    }
+
+    /// Set the amount of an itemized transaction to the total of its
+    /// lines. As when the amount is edited, a single payer pays the whole
+    /// amount.
+    fn update_amount_from_lines(
+        &mut self,
+        to_update: Option<&mut ParsedPurchase>,
+    ) {
+        let values: Vec<Rational64> = self
+            .items
+            .iter()
+            .filter_map(lines::Item::value)
+            .chain(
+                self.adjustments
+                    .iter()
+                    .filter_map(lines::Adjustment::signed_value),
+            )
+            .collect();
+        // On overflow, the amount is left as is, and the purchase will be
+        // reported as not matching its lines.
+        let total = match checked_sum(&values) {
+            Some(total) => total,
+            None => return,
+        };
+        self.amount = rational_to_exact_string(total);
+        let payers: Vec<_> = self.payers().collect();
+        if let [(uid, _)] = payers[..] {
+            self.paid.insert(uid, shares::Paid::from_rat(total));
+        }
+        if let Some(trans) = to_update {
+            trans.set_amount(total);
+        }
+    }
+
+    pub fn view(&mut self, users: &[User]) -> Element<'_, Message> {
+        let payers_mismatch = !self.payers_match_amount();
+        let itemized = !self.items.is_empty();
+        let mut paid = shares::inputs(&mut self.paid, users);
+        let mut shares = shares::inputs(&mut self.shares, users);
+        let mut users_col = Column::new().max_width(300);
+        for user in users {
+            let uid = user.id;
+            // No Panic: there is an input for each user
+            let paid = paid.remove(&uid).unwrap();
+            let share = shares.remove(&uid).unwrap();
+            let mut row =
+                Row::new().spacing(5).push(Text::new(&user.name)).push(
+                    paid.view().map(move |msg| Message::PaidChanged(uid, msg)),
+                );
+            // The shares of an itemized transaction are those of its items
+            if !itemized {
+                row = row.push(
                     share
                         .view()
                         .map(move |msg| Message::ShareChanged(uid, msg)),
+                );
+            }
+            users_col = users_col.push(row);
+        }
+        let mut lines_col = Column::new().spacing(5);
+        for (item_idx, item) in self.items.iter_mut().enumerate() {
+            lines_col = lines_col.push(
+                item.view(users)
+                    .map(move |msg| Message::ItemChanged(item_idx, msg)),
+            );
+        }
+        for (adjustment_idx, adjustment) in
+            self.adjustments.iter_mut().enumerate()
+        {
+            lines_col = lines_col.push(adjustment.view().map(move |msg| {
+                Message::AdjustmentChanged(adjustment_idx, msg)
+            }));
+        }
+        lines_col = lines_col.push(
+            Row::new()
+                .spacing(5)
+                .push(
+                    Button::new(
+                        &mut self.add_item_btn_state,
+                        Text::new("Add item"),
+                    )
+                    .on_press(Message::AddItem),
+                )
+                .push(
+                    Button::new(
+                        &mut self.add_adjustment_btn_state,
+                        Text::new("Add tax, tip or discount"),
+                    )
+                    .on_press(Message::AddAdjustment),
                 ),
+        );
+        let mut amount_col = Column::new().push(TextInput::new(
+            &mut self.amount_state,
+            "Amount",
+            &self.amount,
+            Message::AmountStrChange,
+        ));
+        if let Some(error) = &self.amount_error {
+            amount_col = amount_col.push(shares::error_text(error));
+        } else if payers_mismatch {
+            amount_col = amount_col.push(
+                Text::new("Paid amounts do not add up").color([1.0, 0., 0.]),
             );
         }
         Row::new()
@@ -118,21 +274,61 @@ impl Transaction {
                 &self.descr,
                 Message::DescrStrChange,
             ))
-            .push(user_choice)
-            .push(TextInput::new(
-                &mut self.amount_state,
-                "Amount",
-                &self.amount,
-                Message::AmountStrChange,
-            ))
-            .push(shares_col)
+            .push(amount_col)
+            .push(users_col)
+            .push(lines_col)
             .into()
     }
 
-    pub fn take_creditor(&mut self) -> String {
-        let mut creditor = String::new();
-        std::mem::swap(&mut creditor, &mut self.creditor);
-        creditor
+    /// Get an iterator yielding the user ids of users who paid for this
+    /// transaction and the amount they paid.
+    pub fn payers(&self) -> impl Iterator<Item = (UserId, Rational64)> + '_ {
+        self.paid
+            .iter()
+            .filter_map(|(uid, paid)| paid.value().map(|val| (*uid, val)))
+            .filter(|(_, val)| *val != 0.into())
+    }
+
+    /// The sum of the amounts paid, `None` on overflow
+    fn total_paid(&self) -> Option<Rational64> {
+        let paid: Vec<Rational64> =
+            self.payers().map(|(_, paid)| paid).collect();
+        checked_sum(&paid)
+    }
+
+    fn payers_match_amount(&self) -> bool {
+        let amount = if self.amount.is_empty() {
+            Some(0.into())
+        } else {
+            rational_from_str(&self.amount).ok()
+        };
+        amount.is_some() && amount == self.total_paid()
+    }
+
+    /// Add the items and adjustments of this transaction to a purchase
+    pub fn fill_lines(&self, purchase: &mut ParsedPurchase) {
+        for item in &self.items {
+            let item_idx = purchase.add_item(String::new(), 0.into());
+            // No Panic: the item has just been added
+            item.apply(purchase.item_mut(item_idx).unwrap());
+        }
+        for adjustment in &self.adjustments {
+            let adjustment_idx =
+                purchase.add_adjustment(AdjustmentKind::Tax, 0.into());
+            // No Panic: the adjustment has just been added
+            adjustment.apply(purchase.adjustment_mut(adjustment_idx).unwrap());
+        }
+    }
+
+    /// Reset the items and adjustments
+    pub fn clear_lines(&mut self) {
+        self.items.clear();
+        self.adjustments.clear();
+    }
+
+    /// Reset the amounts paid by users
+    pub fn clear_payers(&mut self) {
+        self.paid.clear();
     }
 
     pub fn take_amount(&mut self) -> Rational64 {
@@ -151,43 +347,143 @@ impl Transaction {
     /// Checks wether this transaction contains all the information
     /// to be added to the accounts
     pub fn is_valid(&self) -> bool {
-        self.descr.len() > 0 && self.creditor.len() > 0 && self.amount.len() > 0
+        !self.descr.is_empty()
+            && !self.amount.is_empty()
+            && self.payers().next().is_some()
+            && self.payers_match_amount()
     }
 
     /// Get an iterator yielding the user ids of users with set shares
-    /// and the corresponding share value.
-    pub fn per_user_shares(
+    /// and the corresponding split.
+    pub fn per_user_splits(
         &self,
-    ) -> impl Iterator<Item = (usize, Rational64)> + '_ {
+    ) -> impl Iterator<Item = (UserId, Split)> + '_ {
         self.shares
             .iter()
-            .enumerate()
-            .filter_map(|(uid, share)| share.value().map(|val| (uid, val)))
+            .filter_map(|(uid, share)| share.value().map(|val| (*uid, val)))
     }
 }
 
 mod shares {
 
-    use crate::rational::{rational_from_str, rational_to_string};
-    use iced::{text_input, Element, TextInput};
+    use std::collections::BTreeMap;
+
+    use crate::accounts::{User, UserId};
+    use crate::rational::{
+        rational_from_str, rational_to_exact_string, ParseRationalError,
+    };
+    use crate::split::{split_from_str, split_to_exact_string, Split};
+    use iced::{text_input, Column, Element, Text, TextInput};
     use num_rational::Rational64;
 
+    /// Input for the split of a beneficiary
     #[derive(Default, Clone)]
     pub struct Share {
+        value: String,
+        value_parsed: Option<Split>,
+        error: Option<ParseRationalError>,
+        state: text_input::State,
+    }
+
+    /// Input for an amount paid by a user
+    #[derive(Default, Clone)]
+    pub struct Paid {
         value: String,
         value_parsed: Option<Rational64>,
+        error: Option<ParseRationalError>,
         state: text_input::State,
     }
 
+    /// Get the input of each user, adding empty ones for new users
+    pub fn inputs<'a, T: Default>(
+        inputs: &'a mut BTreeMap<UserId, T>,
+        users: &[User],
+    ) -> BTreeMap<UserId, &'a mut T> {
+        for user in users {
+            inputs.entry(user.id).or_default();
+        }
+        inputs
+            .iter_mut()
+            .map(|(uid, input)| (*uid, input))
+            .collect()
+    }
+
+    /// Text explaining why an input could not be parsed
+    pub fn error_text(error: &ParseRationalError) -> Text {
+        Text::new(error.to_string()).size(14).color([1.0, 0., 0.])
+    }
+
+    /// Parse the content of an input, empty inputs having no value but no
+    /// error either.
+    fn parse<T>(
+        value: &str,
+        parser: impl Fn(&str) -> Result<T, ParseRationalError>,
+    ) -> (Option<T>, Option<ParseRationalError>) {
+        if value.trim().is_empty() {
+            return (None, None);
+        }
+        match parser(value) {
+            Ok(parsed) => (Some(parsed), None),
+            Err(error) => (None, Some(error)),
+        }
+    }
+
+    fn view_input<'a>(
+        state: &'a mut text_input::State,
+        placeholder: &str,
+        value: &str,
+        error: &Option<ParseRationalError>,
+    ) -> Element<'a, Message> {
+        let input =
+            TextInput::new(state, placeholder, value, Message::StrChange);
+        match error {
+            Some(error) => {
+                Column::new().push(input).push(error_text(error)).into()
+            }
+            None => input.into(),
+        }
+    }
+
     #[derive(Debug, Clone)]
     pub enum Message {
         StrChange(String),
     }
 
     impl Share {
+        pub fn from_split(split: Split) -> Self {
+            Self {
+                value: split_to_exact_string(split),
+                value_parsed: Some(split),
+                ..Default::default()
+            }
+        }
+
+        pub fn update(&mut self, message: Message) {
+            match message {
+                Message::StrChange(share) => {
+                    // Incomplete splits such as "=" are accepted while
+                    // typing, but have no value.
+                    let (value_parsed, error) = parse(&share, split_from_str);
+                    self.value_parsed = value_parsed;
+                    self.error = error;
+                    self.value = share;
+                }
+            }
+        }
+
+        pub fn view(&mut self) -> Element<'_, Message> {
+            view_input(&mut self.state, "Share", &self.value, &self.error)
+        }
+
+        pub fn value(&self) -> Option<Split> {
+            self.value_parsed
+        }
+    }
+
+    impl Paid {
         pub fn from_rat(rat: Rational64) -> Self {
             Self {
-                value: rational_to_string(rat, 2),
+                value: rational_to_exact_string(rat),
                 value_parsed: Some(rat),
                 ..Default::default()
             }
@@ -195,26 +491,17 @@ mod shares {
 
         pub fn update(&mut self, message: Message) {
             match message {
-                Message::StrChange(share) => {
-                    if share.len() == 0 {
-                        self.value = share;
-                        self.value_parsed = None;
-                    } else if let Ok(value_parsed) = rational_from_str(&share) {
-                        self.value = share;
-                        self.value_parsed = Some(value_parsed);
-                    }
+                Message::StrChange(paid) => {
+                    let (value_parsed, error) = parse(&paid, rational_from_str);
+                    self.value_parsed = value_parsed;
+                    self.error = error;
+                    self.value = paid;
                 }
             }
         }
 
-        pub fn view(&mut self) -> Element<Message> {
-            TextInput::new(
-                &mut self.state,
-                "Share",
-                &self.value,
-                Message::StrChange,
-            )
-            .into()
+        pub fn view(&mut self) -> Element<'_, Message> {
+            view_input(&mut self.state, "Paid", &self.value, &self.error)
         }
 
         pub fn value(&self) -> Option<Rational64> {
@@ -222,3 +509,163 @@ mod shares {
         }
     }
 }
+
+mod lines {
+
+    use std::collections::BTreeMap;
+
+    use super::shares;
+    use crate::accounts::{
+        AdjustmentKind, ParsedAdjustment, ParsedItem, User, UserId,
+    };
+    use iced::{text_input, Column, Element, Radio, Row, Text, TextInput};
+    use num_rational::Rational64;
+
+    /// Inputs for an item of an itemized transaction
+    #[derive(Default, Clone)]
+    pub struct Item {
+        descr: String,
+        descr_state: text_input::State,
+        amount: shares::Paid,
+        shares: BTreeMap<UserId, shares::Share>,
+    }
+
+    /// Inputs for a tax, tip or discount of an itemized transaction
+    #[derive(Default, Clone)]
+    pub struct Adjustment {
+        kind: Option<AdjustmentKind>,
+        amount: shares::Paid,
+    }
+
+    #[derive(Debug, Clone)]
+    pub enum ItemMessage {
+        DescrStrChange(String),
+        AmountChanged(shares::Message),
+        ShareChanged(UserId, shares::Message),
+    }
+
+    #[derive(Debug, Clone)]
+    pub enum AdjustmentMessage {
+        KindSelected(AdjustmentKind),
+        AmountChanged(shares::Message),
+    }
+
+    impl Item {
+        pub fn new(item: &ParsedItem) -> Self {
+            Self {
+                descr: item.descr.clone(),
+                amount: shares::Paid::from_rat(item.amount),
+                shares: item
+                    .splits()
+                    .iter()
+                    .map(|(uid, x)| (*uid, shares::Share::from_split(*x)))
+                    .collect(),
+                ..Default::default()
+            }
+        }
+
+        pub fn update(&mut self, message: ItemMessage) {
+            match message {
+                ItemMessage::DescrStrChange(descr) => self.descr = descr,
+                ItemMessage::AmountChanged(message) => {
+                    self.amount.update(message)
+                }
+                ItemMessage::ShareChanged(uid, message) => {
+                    self.shares.entry(uid).or_default().update(message)
+                }
+            }
+        }
+
+        /// Copy the content of the inputs to a parsed item
+        pub fn apply(&self, item: &mut ParsedItem) {
+            item.descr = self.descr.clone();
+            item.amount = self.value().unwrap_or(0.into());
+            for (uid, share) in &self.shares {
+                item.set_split(*uid, share.value().unwrap_or_default());
+            }
+        }
+
+        pub fn view(&mut self, users: &[User]) -> Element<'_, ItemMessage> {
+            let mut shares = shares::inputs(&mut self.shares, users);
+            let mut shares_col = Column::new();
+            for user in users {
+                let uid = user.id;
+                // No Panic: there is an input for each user
+                let share = shares.remove(&uid).unwrap();
+                shares_col = shares_col.push(
+                    Row::new().spacing(5).push(Text::new(&user.name)).push(
+                        share.view().map(move |msg| {
+                            ItemMessage::ShareChanged(uid, msg)
+                        }),
+                    ),
+                );
+            }
+            Row::new()
+                .spacing(5)
+                .push(TextInput::new(
+                    &mut self.descr_state,
+                    "Item",
+                    &self.descr,
+                    ItemMessage::DescrStrChange,
+                ))
+                .push(self.amount.view().map(ItemMessage::AmountChanged))
+                .push(shares_col)
+                .into()
+        }
+
+        pub fn value(&self) -> Option<Rational64> {
+            self.amount.value()
+        }
+    }
+
+    impl Adjustment {
+        pub fn new(adjustment: &ParsedAdjustment) -> Self {
+            Self {
+                kind: Some(adjustment.kind),
+                amount: shares::Paid::from_rat(adjustment.amount),
+            }
+        }
+
+        pub fn update(&mut self, message: AdjustmentMessage) {
+            match message {
+                AdjustmentMessage::KindSelected(kind) => self.kind = Some(kind),
+                AdjustmentMessage::AmountChanged(message) => {
+                    self.amount.update(message)
+                }
+            }
+        }
+
+        /// Copy the content of the inputs to a parsed adjustment
+        pub fn apply(&self, adjustment: &mut ParsedAdjustment) {
+            adjustment.kind = self.kind.unwrap_or(AdjustmentKind::Tax);
+            adjustment.amount = self.amount.value().unwrap_or(0.into());
+        }
+
+        pub fn view(&mut self) -> Element<'_, AdjustmentMessage> {
+            let mut row = Row::new().spacing(5);
+            for kind in &[
+                AdjustmentKind::Tax,
+                AdjustmentKind::Tip,
+                AdjustmentKind::Discount,
+            ] {
+                row = row.push(Radio::new(
+                    *kind,
+                    kind.to_string(),
+                    self.kind,
+                    AdjustmentMessage::KindSelected,
+                ));
+            }
+            row.push(self.amount.view().map(AdjustmentMessage::AmountChanged))
+                .into()
+        }
+
+        /// The amount added to the transaction by this adjustment
+        pub fn signed_value(&self) -> Option<Rational64> {
+            let amount = self.amount.value()?;
+            match self.kind {
+                Some(AdjustmentKind::Discount) => Some(-amount),
+                _ => Some(amount),
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Widget describing a transfer being edited
+
+use iced::{text_input, Column, Element, Radio, Row, Text, TextInput};
+
+use num_rational::Rational64;
+
+use crate::accounts::{ParsedTransfer, User, UserId};
+use crate::rational::{
+    rational_from_str, rational_to_exact_string, ParseRationalError,
+};
+
+#[derive(Default, Clone)]
+pub struct Transfer {
+    descr: String,
+    descr_state: text_input::State,
+    from: Option<UserId>,
+    to: Option<UserId>,
+    amount: String,
+    amount_state: text_input::State,
+    amount_error: Option<ParseRationalError>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    DescrStrChange(String),
+    FromSelected(UserId),
+    ToSelected(UserId),
+    AmountStrChange(String),
+}
+
+impl Transfer {
+    pub fn new(transfer: &ParsedTransfer) -> Self {
+        Self {
+            descr: transfer.descr.clone(),
+            from: Some(transfer.from),
+            to: Some(transfer.to),
+            amount: rational_to_exact_string(transfer.amount),
+            ..Default::default()
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        message: Message,
+        to_update: Option<&mut ParsedTransfer>,
+    ) {
+        match message {
+            Message::DescrStrChange(new_descr) => {
+                self.descr = new_descr.clone();
+                if let Some(trans) = to_update {
+                    trans.descr = new_descr;
+                }
+            }
+            Message::AmountStrChange(new_amount) => {
+                let val = if new_amount.is_empty() {
+                    Ok(0.into())
+                } else {
+                    rational_from_str(&new_amount)
+                };
+                // Invalid amounts are accepted while typing, but are not
+                // applied.
+                self.amount = new_amount;
+                self.amount_error = val.as_ref().err().cloned();
+                if let Ok(val) = val {
+                    if let Some(trans) = to_update {
+                        trans.amount = val;
+                    }
+                }
+            }
+            Message::FromSelected(uid) => {
+                self.from = Some(uid);
+                if let Some(trans) = to_update {
+                    trans.from = uid;
+                }
+            }
+            Message::ToSelected(uid) => {
+                self.to = Some(uid);
+                if let Some(trans) = to_update {
+                    trans.to = uid;
+                }
+            }
+        }
+    }
+
+    pub fn view(&mut self, users: &[User]) -> Element<'_, Message> {
+        let mut from_choice = Column::new().push(Text::new("From"));
+        let mut to_choice = Column::new().push(Text::new("To"));
+        for user in users {
+            from_choice = from_choice.push(Radio::new(
+                user.id,
+                &user.name,
+                self.from,
+                Message::FromSelected,
+            ));
+            to_choice = to_choice.push(Radio::new(
+                user.id,
+                &user.name,
+                self.to,
+                Message::ToSelected,
+            ));
+        }
+        let mut amount_col = Column::new().push(TextInput::new(
+            &mut self.amount_state,
+            "Amount",
+            &self.amount,
+            Message::AmountStrChange,
+        ));
+        if let Some(error) = &self.amount_error {
+            amount_col = amount_col.push(
+                Text::new(error.to_string()).size(14).color([1.0, 0., 0.]),
+            );
+        }
+        Row::new()
+            .spacing(10)
+            .padding(10)
+            .push(TextInput::new(
+                &mut self.descr_state,
+                "Transfer description",
+                &self.descr,
+                Message::DescrStrChange,
+            ))
+            .push(from_choice)
+            .push(to_choice)
+            .push(amount_col)
+            .into()
+    }
+
+    pub fn take_descr(&mut self) -> String {
+        let mut descr = String::new();
+        std::mem::swap(&mut descr, &mut self.descr);
+        descr
+    }
+
+    pub fn take_amount(&mut self) -> Rational64 {
+        // No Panic: validity of rational enforced by GUI
+        let rat = rational_from_str(&self.amount).unwrap();
+        self.amount.clear();
+        rat
+    }
+
+    /// Take the user ids of the giver and the receiver of the transfer
+    pub fn take_users(&mut self) -> Option<(UserId, UserId)> {
+        let users = self.from.zip(self.to);
+        self.from = None;
+        self.to = None;
+        users
+    }
+
+    /// Checks wether this transfer contains all the information
+    /// to be added to the accounts
+    pub fn is_valid(&self) -> bool {
+        self.from.is_some()
+            && self.to.is_some()
+            && rational_from_str(&self.amount).is_ok()
+    }
+}
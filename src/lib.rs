@@ -1,5 +1,16 @@
 pub mod accounts;
+pub mod combine;
+pub mod csv;
+pub mod date;
+pub mod decimal;
 pub mod gui_iced;
+pub mod lint;
 #[cfg(target_arch = "wasm32")]
 pub mod local_storage;
+pub mod money;
 pub mod rational;
+pub mod report;
+pub mod settlement;
+pub mod source;
+pub mod split;
+pub mod storage;
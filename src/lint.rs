@@ -0,0 +1,223 @@
+//! Checks of accounts for likely mistakes
+//!
+//! Unlike parse errors, lints do not prevent computing the balances. They
+//! point to purchases which are ignored in the balances, or which are valid
+//! but probably not what was meant.
+
+use std::collections::BTreeMap;
+
+use num_rational::Rational64;
+
+use crate::accounts::{
+    ParseError, ParsedAccounts, ParsedPurchase, PurchaseId, UserId,
+};
+use crate::date::Date;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lint {
+    /// The shares of a purchase sum to zero, it is ignored in the balances
+    ZeroShares(PurchaseId),
+    /// The amount of a purchase is zero
+    ZeroAmount(PurchaseId),
+    /// A purchase is ignored in the balances for another reason
+    IgnoredPurchase(PurchaseId, ParseError),
+    /// A user neither paid for nor benefited from any purchase, nor took
+    /// part in any transfer
+    InactiveUser(UserId),
+    /// A purchase has the same description, date, amount, currency and
+    /// payers as the second, earlier one
+    ProbableDuplicate(PurchaseId, PurchaseId),
+}
+
+impl Lint {
+    /// The purchase the lint is about, if any
+    pub fn purchase(&self) -> Option<PurchaseId> {
+        match self {
+            Lint::ZeroShares(id)
+            | Lint::ZeroAmount(id)
+            | Lint::IgnoredPurchase(id, _)
+            | Lint::ProbableDuplicate(id, _) => Some(*id),
+            Lint::InactiveUser(_) => None,
+        }
+    }
+
+    /// Describe the lint, naming the purchases and users it is about
+    pub fn describe(&self, accounts: &ParsedAccounts) -> String {
+        let descr = |id| match accounts.purchase(id) {
+            Ok(purchase) => purchase.descr.clone(),
+            Err(_) => format!("purchase {}", id),
+        };
+        match self {
+            Lint::ZeroShares(id) => format!(
+                "The shares of {} sum to zero, it is ignored in the balances.",
+                descr(*id),
+            ),
+            Lint::ZeroAmount(id) => {
+                format!("The amount of {} is zero.", descr(*id))
+            }
+            Lint::IgnoredPurchase(id, err) => {
+                format!("{} {} is ignored in the balances.", err, descr(*id))
+            }
+            Lint::InactiveUser(uid) => format!(
+                "{} has no purchases nor transfers.",
+                accounts.user_name(*uid).unwrap_or("An unknown user"),
+            ),
+            Lint::ProbableDuplicate(id, original) => format!(
+                "{} has the same description, date, amount, currency and \
+                 payers as purchase {}, it may be a duplicate.",
+                descr(*id),
+                original,
+            ),
+        }
+    }
+}
+
+/// What makes two purchases probable duplicates
+type DuplicateKey = (
+    String,
+    Option<Date>,
+    Rational64,
+    Option<String>,
+    Vec<(UserId, Rational64)>,
+);
+
+fn duplicate_key(purchase: &ParsedPurchase) -> DuplicateKey {
+    (
+        purchase.descr.trim().to_lowercase(),
+        purchase.date,
+        purchase.amount.value(),
+        purchase.currency().map(String::from),
+        purchase
+            .payers()
+            .iter()
+            .map(|(uid, paid)| (*uid, *paid))
+            .collect(),
+    )
+}
+
+/// Check the accounts for likely mistakes, in the order of the purchases,
+/// then of the users. Fails if the computations overflow, as the balances
+/// would.
+pub fn lint(accounts: &ParsedAccounts) -> Result<Vec<Lint>, ParseError> {
+    let mut lints = Vec::new();
+    let mut seen: BTreeMap<DuplicateKey, PurchaseId> = BTreeMap::new();
+    for purchase in accounts.purchases() {
+        let id = purchase.id();
+        if purchase.amount.value() == 0.into() {
+            lints.push(Lint::ZeroAmount(id));
+        }
+        match accounts.ignored_reason(purchase)? {
+            Some(ParseError::SharesSumToZero(_)) => {
+                lints.push(Lint::ZeroShares(id))
+            }
+            Some(err) => lints.push(Lint::IgnoredPurchase(id, err)),
+            None => (),
+        }
+        match seen.get(&duplicate_key(purchase)) {
+            Some(original) => {
+                lints.push(Lint::ProbableDuplicate(id, *original))
+            }
+            None => {
+                seen.insert(duplicate_key(purchase), id);
+            }
+        }
+    }
+    for user in accounts.users() {
+        let uid = user.id;
+        let in_purchases = accounts.purchases().iter().any(|purchase| {
+            purchase.payers().contains_key(&uid)
+                || purchase.splits().contains_key(&uid)
+                || purchase
+                    .items()
+                    .iter()
+                    .any(|item| item.splits().contains_key(&uid))
+        });
+        let in_transfers = accounts
+            .transfers()
+            .iter()
+            .any(|transfer| transfer.from == uid || transfer.to == uid);
+        if !in_purchases && !in_transfers {
+            lints.push(Lint::InactiveUser(uid));
+        }
+    }
+    Ok(lints)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lint, Lint};
+    use crate::accounts::{ParseError, ParsedAccounts};
+
+    #[test]
+    fn lints() {
+        let yaml = r#"
+base_currency: EUR
+users: [Eska, Shuba, Simon, Abel]
+purchases:
+  - descr: wine
+    date: 2021-03-01
+    who: Simon
+    amount: "15"
+    benef_to_shares:
+      Shuba: "2"
+      Simon: "1"
+  - descr: Wine
+    date: 2021-03-01
+    who: Simon
+    amount: "15"
+    benef_to_shares:
+      Eska: "1"
+  - descr: bread
+    who: Eska
+    amount: "0"
+    benef_to_shares:
+      Eska: "1"
+  - descr: cheese
+    who: Eska
+    amount: "4"
+    benef_to_shares:
+      Eska: "0"
+  - descr: ham
+    amount: "4"
+    payers:
+      Eska: "2"
+      Shuba: "2"
+    benef_to_shares:
+      Eska: "1"
+"#;
+        let mut accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let ids: Vec<_> = accounts.purchases().iter().map(|p| p.id()).collect();
+        // The payers of ham do not add up to its amount anymore
+        accounts.change_purchase_amount(ids[4], 5.into()).unwrap();
+        let lints = lint(&accounts).unwrap();
+        assert_eq!(
+            lints,
+            vec![
+                Lint::ProbableDuplicate(ids[1], ids[0]),
+                Lint::ZeroAmount(ids[2]),
+                Lint::ZeroShares(ids[3]),
+                Lint::IgnoredPurchase(
+                    ids[4],
+                    ParseError::PayersMismatch("ham".to_string())
+                ),
+                Lint::InactiveUser(accounts.user_id("Abel").unwrap()),
+            ]
+        );
+        assert_eq!(lints[0].purchase(), Some(ids[1]));
+        assert_eq!(lints[4].purchase(), None);
+        assert_eq!(
+            lints[0].describe(&accounts),
+            "Wine has the same description, date, amount, currency and payers \
+             as purchase 1, it may be a duplicate."
+        );
+        assert_eq!(
+            lints[2].describe(&accounts),
+            "The shares of cheese sum to zero, it is ignored in the balances."
+        );
+        assert_eq!(
+            lints[4].describe(&accounts),
+            "Abel has no purchases nor transfers."
+        );
+    }
+}
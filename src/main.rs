@@ -1,12 +1,29 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 
-use aaacs::accounts::ParsedAccounts;
+use aaacs::accounts::{
+    ParseError, ParseMode, ParsedAccounts, PurchaseId, ReportKey,
+};
+use aaacs::combine::Combined;
+use aaacs::csv::{csv_users, CsvOptions};
+use aaacs::date::Date;
 use aaacs::gui_iced;
+use aaacs::lint::lint;
+use aaacs::rational::rational_from_str;
+use aaacs::report::{self, FileReport, OutputFormat, Report};
+use aaacs::storage::{edit_file, load_file, save_file, FileFormat};
+use num_rational::Rational64;
 use structopt::StructOpt;
 
 /// Automated Accurate Accounting Collaborative System
 ///
 /// A simple application to handle accounts between friends
+///
+/// In CLI mode, the exit code is 2 if warnings were printed about the
+/// accounts, such as purchases ignored in the balances.
+///
+/// The subcommands editing a file check that the edited accounts are valid
+/// before replacing it.
 #[derive(StructOpt, Debug)]
 #[structopt(name = "aaacs")]
 struct Args {
@@ -15,27 +32,616 @@ struct Args {
     #[structopt(long)]
     cli: bool,
 
-    /// Number of decimal points to print
-    #[structopt(long, default_value = "2")]
-    precision: u8,
+    /// In CLI mode, also list the purchases and transfers.
+    #[structopt(long)]
+    list: bool,
+
+    /// In CLI mode, also print the transfers that would settle the
+    /// balances.
+    #[structopt(long)]
+    settle: bool,
+
+    /// In CLI mode, print the balances at the end of this day (YYYY-MM-DD)
+    /// instead of the current ones. Undated transactions are ignored.
+    #[structopt(long)]
+    as_of: Option<Date>,
+
+    /// Number of decimal points to print, by default the ones of the
+    /// currency of each amount, or 2 without currency
+    #[structopt(long)]
+    precision: Option<u8>,
+
+    /// In CLI mode, also print the balances and the settlement of all the
+    /// files combined, users being matched by name.
+    #[structopt(long)]
+    combine: bool,
+
+    /// With --combine, count a user named Old in some files as the user
+    /// named New, passed as Old=New. Can be repeated.
+    #[structopt(long = "alias", number_of_values = 1)]
+    aliases: Vec<Alias>,
+
+    /// How to print balances, listings and settlements: text, json, csv,
+    /// table or markdown. The json schema is documented in the README.
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// In CLI mode, ignore unknown fields and shares of unknown users
+    /// instead of failing, as the GUI does
+    #[structopt(long)]
+    lenient: bool,
 
     /// Files to process
     #[structopt(name = "FILE", parse(from_os_str))]
     files: Vec<std::path::PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Print what each user consumed and paid, by category or by tag
+    Report {
+        /// How to group purchases: category or tag
+        #[structopt(long, default_value = "category")]
+        by: ReportKey,
+
+        /// Files to process
+        #[structopt(name = "FILE", parse(from_os_str))]
+        files: Vec<std::path::PathBuf>,
+    },
+
+    /// Rename a user of a file, its transactions are kept
+    RenameUser {
+        /// Current name of the user
+        user: String,
+
+        /// New name of the user
+        new_name: String,
+
+        /// File to edit
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// Add a user to a file
+    AddUser {
+        /// Name of the user
+        user: String,
+
+        /// File to edit
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// Remove a user from a file, if they have no transactions
+    RemoveUser {
+        /// Name of the user
+        user: String,
+
+        /// File to edit
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// Add a purchase to a file, and print its id
+    AddPurchase {
+        /// Description of the purchase
+        descr: String,
+
+        /// User who paid the purchase
+        #[structopt(long)]
+        payer: String,
+
+        /// Amount of the purchase, in the base currency
+        #[structopt(long, parse(try_from_str = rational_from_str))]
+        amount: Rational64,
+
+        /// Share of a user in the purchase, as Name=2. Can be repeated.
+        #[structopt(long = "share", required = true, number_of_values = 1)]
+        shares: Vec<Share>,
+
+        /// Day of the purchase (YYYY-MM-DD)
+        #[structopt(long)]
+        date: Option<Date>,
+
+        /// File to edit
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// Set the share of a user in a purchase
+    SetShare {
+        /// Id of the purchase, as printed by list
+        purchase: PurchaseId,
+
+        /// Name of the user
+        user: String,
+
+        /// Share of the user, 0 to remove them from the purchase
+        #[structopt(parse(try_from_str = rational_from_str))]
+        share: Rational64,
+
+        /// File to edit
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// Set the amount of a purchase
+    SetAmount {
+        /// Id of the purchase, as printed by list
+        purchase: PurchaseId,
+
+        /// Amount of the purchase, in its currency
+        #[structopt(parse(try_from_str = rational_from_str))]
+        amount: Rational64,
+
+        /// File to edit
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// Remove a purchase from a file
+    RemovePurchase {
+        /// Id of the purchase, as printed by list
+        purchase: PurchaseId,
+
+        /// File to edit
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// List the purchases, with their ids, and the transfers of a file
+    List {
+        /// File to process
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// Write the accounts of a file in another format
+    Convert {
+        /// Format to write: yaml, json or toml. By default, the one given by
+        /// the extension of OUTPUT.
+        #[structopt(long)]
+        to: Option<FileFormat>,
+
+        /// File to read
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: std::path::PathBuf,
+
+        /// File to write
+        #[structopt(name = "OUTPUT", parse(from_os_str))]
+        output: std::path::PathBuf,
+    },
+
+    /// Add the purchases of a csv file to a file, which is created with the
+    /// users of the csv if it does not exist
+    ImportCsv {
+        #[structopt(flatten)]
+        csv: CsvArgs,
+
+        /// Csv file to read, with columns description, payer, amount and
+        /// one column of shares per user
+        #[structopt(name = "CSV", parse(from_os_str))]
+        csv_file: std::path::PathBuf,
+
+        /// File to edit
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+
+    /// Print the purchases of a file as csv
+    ExportCsv {
+        #[structopt(flatten)]
+        csv: CsvArgs,
+
+        /// File to process
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+}
+
+/// How purchases are written in csv files
+#[derive(StructOpt, Debug)]
+struct CsvArgs {
+    /// Separator of the fields of a row
+    #[structopt(long, default_value = ",")]
+    delimiter: char,
+
+    /// Separator of the decimals of numbers
+    #[structopt(long, default_value = ".")]
+    decimal_separator: char,
+}
+
+impl CsvArgs {
+    fn options(&self) -> CsvOptions {
+        CsvOptions {
+            delimiter: self.delimiter,
+            decimal_separator: self.decimal_separator,
+        }
+    }
+}
+
+/// The share of a user in a purchase, passed as Name=2
+#[derive(Debug)]
+struct Share {
+    user: String,
+    share: Rational64,
+}
+
+impl std::str::FromStr for Share {
+    type Err = String;
+
+    fn from_str(share: &str) -> Result<Self, Self::Err> {
+        let separator = share
+            .rfind('=')
+            .ok_or_else(|| format!("Expected Name=share, got {}", share))?;
+        let value = &share[separator + 1..];
+        Ok(Share {
+            user: share[..separator].to_string(),
+            share: rational_from_str(value).map_err(|err| err.to_string())?,
+        })
+    }
+}
+
+/// Another name of a user in some files, passed as Old=New
+#[derive(Debug)]
+struct Alias {
+    name: String,
+    alias_of: String,
+}
+
+impl std::str::FromStr for Alias {
+    type Err = String;
+
+    fn from_str(alias: &str) -> Result<Self, Self::Err> {
+        let separator = alias
+            .find('=')
+            .ok_or_else(|| format!("Expected Old=New, got {}", alias))?;
+        Ok(Alias {
+            name: alias[..separator].to_string(),
+            alias_of: alias[separator + 1..].to_string(),
+        })
+    }
+}
+
+/// Load the accounts of a yaml, json or toml file
+fn load(
+    path: &std::path::Path,
+    mode: ParseMode,
+) -> Result<ParsedAccounts, Box<dyn Error>> {
+    Ok(load_file(path, mode)?.0)
+}
+
+/// Print an error, with the faulty line of the file it occurred in if known
+fn print_error(err: &(dyn Error + 'static)) {
+    match err.downcast_ref::<ParseError>() {
+        Some(err) => print_parse_error(err),
+        None => eprintln!("Error: {}", err),
+    }
+}
+
+/// Print an error in accounts, or each of them if there are several, with
+/// the faulty line of the file
+fn print_parse_error(err: &ParseError) {
+    if let ParseError::Several(errors) = err.root_cause() {
+        for inner in errors {
+            match err.file() {
+                Some(file) => print_parse_error(&inner.clone().in_file(file)),
+                None => print_parse_error(inner),
+            }
+        }
+        return;
+    }
+    eprintln!("Error: {}", err);
+    if let (Some(file), Some(position)) = (err.file(), err.position()) {
+        if let Ok(contents) = std::fs::read_to_string(file) {
+            eprint!("{}", snippet(&contents, position));
+        }
+    }
+}
+
+/// The line of `source` at `position`, with a caret under the column
+fn snippet(source: &str, (line, column): (usize, usize)) -> String {
+    let text = match source.lines().nth(line.saturating_sub(1)) {
+        Some(text) => text,
+        None => return String::new(),
+    };
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "{} |\n{} | {}\n{} | {}^\n",
+        gutter,
+        line,
+        text,
+        gutter,
+        " ".repeat(column.saturating_sub(1)),
+    )
+}
+
+/// Run a subcommand, in `mode`
+fn run_command(
+    command: Command,
+    mode: ParseMode,
+    precision: Option<u8>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Report { by, files } => {
+            for accounts_path in files {
+                println!(
+                    "Spending report for {}:",
+                    accounts_path.to_string_lossy()
+                );
+                let accounts = load(&accounts_path, mode)?;
+                accounts.print_spending_report(by, precision)?;
+            }
+        }
+        Command::RenameUser {
+            user,
+            new_name,
+            file,
+        } => {
+            edit_file(&file, mode, |accounts| {
+                accounts.rename_user(accounts.user_id(&user)?, new_name)
+            })?;
+        }
+        Command::AddUser { user, file } => {
+            edit_file(&file, mode, |accounts| accounts.add_user(user))?;
+        }
+        Command::RemoveUser { user, file } => {
+            edit_file(&file, mode, |accounts| accounts.remove_user(user))?;
+        }
+        Command::AddPurchase {
+            descr,
+            payer,
+            amount,
+            shares,
+            date,
+            file,
+        } => {
+            let id = edit_file(&file, mode, |accounts| {
+                let id = accounts.add_purchase(descr, payer, amount)?;
+                for Share { user, share } in shares {
+                    accounts.set_purchase_user_share(id, user, share)?;
+                }
+                accounts.purchase_mut(id)?.0.date = date;
+                Ok(id)
+            })?;
+            println!("Added purchase #{}", id);
+        }
+        Command::SetShare {
+            purchase,
+            user,
+            share,
+            file,
+        } => {
+            edit_file(&file, mode, |accounts| {
+                accounts.set_purchase_user_share(purchase, user, share)
+            })?;
+        }
+        Command::SetAmount {
+            purchase,
+            amount,
+            file,
+        } => {
+            edit_file(&file, mode, |accounts| {
+                accounts.change_purchase_amount(purchase, amount)
+            })?;
+        }
+        Command::RemovePurchase { purchase, file } => {
+            edit_file(&file, mode, |accounts| {
+                accounts.remove_purchase(purchase)
+            })?;
+        }
+        Command::List { file } => {
+            let accounts = load(&file, mode)?;
+            if format == OutputFormat::Text {
+                accounts.print_transactions(precision)?;
+            } else {
+                let report = Report {
+                    files: vec![FileReport {
+                        file: Some(file.to_string_lossy().into_owned()),
+                        base_currency: accounts
+                            .base_currency()
+                            .map(String::from),
+                        transactions: Some(report::transactions(
+                            &accounts, precision,
+                        )?),
+                        balances: None,
+                        settlement: None,
+                    }],
+                    ..Report::default()
+                };
+                print!("{}", report.write(format));
+            }
+        }
+        Command::Convert { to, input, output } => {
+            let accounts = load(&input, mode)?;
+            let format = match to.or_else(|| FileFormat::from_path(&output)) {
+                Some(format) => format,
+                None => {
+                    return Err(format!(
+                        "Unknown format for {}, use --to",
+                        output.to_string_lossy()
+                    )
+                    .into())
+                }
+            };
+            save_file(&output, &accounts, format, mode)?;
+        }
+        Command::ImportCsv {
+            csv,
+            csv_file,
+            file,
+        } => {
+            let contents = std::fs::read_to_string(&csv_file)?;
+            let (accounts, format) = if file.exists() {
+                load_file(&file, mode)?
+            } else {
+                let mut accounts = ParsedAccounts::default();
+                for user in csv_users(&contents, &csv.options())? {
+                    accounts.add_user(user)?;
+                }
+                let format = FileFormat::from_path(&file).unwrap_or_default();
+                (accounts, format)
+            };
+            match accounts.import_csv(&contents, &csv.options(), mode) {
+                Ok(accounts) => save_file(&file, &accounts, format, mode)?,
+                Err(errors) => {
+                    for err in errors {
+                        print_error(&err.in_file(&csv_file.to_string_lossy()));
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::ExportCsv { csv, file } => {
+            let accounts = load(&file, mode)?;
+            print!("{}", accounts.purchases_to_csv(&csv.options())?);
+        }
+    }
+    Ok(())
+}
+
+/// Print the reports of the files in CLI mode. Returns whether warnings
+/// were printed about the accounts.
+fn run_cli(args: &Args, mode: ParseMode) -> Result<bool, Box<dyn Error>> {
+    let text = args.format == OutputFormat::Text;
+    let mut has_lints = false;
+    let mut all_accounts = Vec::new();
+    let mut report = Report::default();
+    for accounts_path in &args.files {
+        let name = accounts_path.to_string_lossy().into_owned();
+        if text {
+            println!("Processing accounts for {}:", name);
+        }
+
+        let accounts = load(accounts_path, mode)?;
+        if text {
+            if args.list {
+                accounts.print_transactions(args.precision)?;
+            }
+            match args.as_of {
+                Some(date) => {
+                    accounts.print_balances_as_of(date, args.precision)?
+                }
+                None => accounts.print_balances(args.precision)?,
+            }
+            if args.settle {
+                println!("Settlement:");
+                match args.as_of {
+                    Some(date) => {
+                        accounts.print_settlement_as_of(date, args.precision)?
+                    }
+                    None => accounts.print_settlement(args.precision)?,
+                }
+            }
+        } else {
+            report.files.push(FileReport {
+                file: Some(name),
+                base_currency: accounts.base_currency().map(String::from),
+                transactions: if args.list {
+                    Some(report::transactions(&accounts, args.precision)?)
+                } else {
+                    None
+                },
+                balances: Some(report::balances(
+                    &accounts,
+                    args.as_of,
+                    args.precision,
+                )?),
+                settlement: if args.settle {
+                    Some(report::settlement(
+                        &accounts,
+                        args.as_of,
+                        args.precision,
+                    )?)
+                } else {
+                    None
+                },
+            });
+        }
+        for lint in lint(&accounts)? {
+            eprintln!("Warning: {}", lint.describe(&accounts));
+            has_lints = true;
+        }
+        all_accounts.push(accounts);
+    }
+    if args.combine {
+        let aliases: BTreeMap<String, String> = args
+            .aliases
+            .iter()
+            .map(|alias| (alias.name.clone(), alias.alias_of.clone()))
+            .collect();
+        let combined = match args.as_of {
+            Some(date) => Combined::as_of(&all_accounts, &aliases, date)?,
+            None => Combined::new(&all_accounts, &aliases)?,
+        };
+        let names: Vec<String> = args
+            .files
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        if text {
+            println!("Combined balances:");
+            combined.print_balances(&names, args.precision)?;
+            if args.settle {
+                println!("Combined settlement:");
+                combined.print_settlement(args.precision)?;
+            }
+        } else {
+            report.combined = Some(FileReport {
+                file: None,
+                base_currency: combined.base_currency().map(String::from),
+                transactions: None,
+                balances: Some(report::combined_balances(
+                    &combined,
+                    &names,
+                    args.precision,
+                )?),
+                settlement: if args.settle {
+                    Some(report::combined_settlement(
+                        &combined,
+                        args.precision,
+                    )?)
+                } else {
+                    None
+                },
+            });
+        }
+    }
+    if !text {
+        print!("{}", report.write(args.format));
+    }
+    Ok(has_lints)
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::from_args();
-    if args.cli {
-        for accounts_path in args.files {
-            println!(
-                "Processing accounts for {}:",
-                accounts_path.to_string_lossy()
-            );
-
-            let accounts_file = std::fs::File::open(&accounts_path)?;
-            let accounts = ParsedAccounts::from_yaml_reader(accounts_file)?;
-            accounts.print_balances(args.precision);
+    let mode = if args.lenient {
+        ParseMode::Lenient
+    } else {
+        ParseMode::Strict
+    };
+    if let Some(command) = args.command {
+        if let Err(err) =
+            run_command(command, mode, args.precision, args.format)
+        {
+            print_error(&*err);
+            std::process::exit(1);
+        }
+    } else if args.cli {
+        match run_cli(&args, mode) {
+            Ok(has_lints) => {
+                if has_lints {
+                    std::process::exit(2);
+                }
+            }
+            Err(err) => {
+                print_error(&*err);
+                std::process::exit(1);
+            }
         }
     } else if args.files.len() == 0 {
         gui_iced::run(None);
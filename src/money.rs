@@ -0,0 +1,343 @@
+//! Amounts of money, and the numeric type of the computations on them
+//!
+//! An amount paid is a `Money`, a value in a currency. Currencies have a
+//! minor unit, such as the cent of the euro, and amounts paid in a currency
+//! are whole numbers of its minor unit.
+//!
+//! Values entered by users are `Rational64`s. Computing what each user owes
+//! multiplies and divides them, so the denominators of the results can grow
+//! until they do not fit in an `i64` anymore. All computations are thus
+//! checked, and reported as errors when they overflow.
+//!
+//! With the `big-rational` feature, computations are done on
+//! `BigRational`s, which never overflow, at the cost of speed.
+
+use std::fmt::Debug;
+
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Zero};
+
+use crate::decimal::{
+    format_decimal, DecimalFormat, RoundingMode, MAX_DECIMALS,
+};
+use crate::rational::{rational_to_exact_string, rational_to_string};
+
+/// Number of decimals used for amounts without currency
+pub const DEFAULT_DECIMALS: u8 = 2;
+
+/// The type in which balances and costs are computed
+#[cfg(not(feature = "big-rational"))]
+pub type Amount = Rational64;
+
+/// The type in which balances and costs are computed
+#[cfg(feature = "big-rational")]
+pub type Amount = num_rational::BigRational;
+
+/// A rational number on which money computations can be done
+pub trait Numeric:
+    Clone
+    + Debug
+    + Ord
+    + Zero
+    + CheckedAdd
+    + CheckedSub
+    + CheckedMul
+    + CheckedDiv
+    + std::ops::Neg<Output = Self>
+{
+    /// Convert a value entered by a user
+    fn from_rational(rat: Rational64) -> Self;
+
+    /// Convert back to a `Rational64`, if it fits in one
+    fn to_rational(&self) -> Option<Rational64>;
+
+    /// Round `self * 10^nb_decimals` down to an integer, and return it along
+    /// with the remainder, in `[0, 1)`. Returns `None` if the integer does
+    /// not fit in an `i128`.
+    fn floor_scaled(&self, nb_decimals: u8) -> Option<(i128, Self)>;
+
+    /// Format the value such that it is parsed back exactly, as a decimal
+    /// number if possible, else as a fraction
+    fn to_exact_string(&self) -> String;
+}
+
+impl Numeric for Rational64 {
+    fn from_rational(rat: Rational64) -> Self {
+        rat
+    }
+
+    fn to_rational(&self) -> Option<Rational64> {
+        Some(*self)
+    }
+
+    fn floor_scaled(&self, nb_decimals: u8) -> Option<(i128, Self)> {
+        let nb_decimals = std::cmp::min(nb_decimals, MAX_DECIMALS);
+        let numer = *self.numer() as i128 * 10_i128.pow(nb_decimals as u32);
+        // The denominator of a `Ratio` is always positive
+        let denom = *self.denom() as i128;
+        // No Panic: the remainder is smaller than the denominator
+        Some((
+            numer.div_euclid(denom),
+            Rational64::new(numer.rem_euclid(denom) as i64, *self.denom()),
+        ))
+    }
+
+    fn to_exact_string(&self) -> String {
+        rational_to_exact_string(*self)
+    }
+}
+
+#[cfg(feature = "big-rational")]
+impl Numeric for num_rational::BigRational {
+    fn from_rational(rat: Rational64) -> Self {
+        num_rational::BigRational::new(
+            (*rat.numer()).into(),
+            (*rat.denom()).into(),
+        )
+    }
+
+    fn to_rational(&self) -> Option<Rational64> {
+        use num_traits::ToPrimitive;
+        Some(Rational64::new_raw(
+            self.numer().to_i64()?,
+            self.denom().to_i64()?,
+        ))
+    }
+
+    fn floor_scaled(&self, nb_decimals: u8) -> Option<(i128, Self)> {
+        use num_traits::{Pow, ToPrimitive};
+        let nb_decimals = std::cmp::min(nb_decimals, MAX_DECIMALS);
+        let scale = num_bigint::BigInt::from(10).pow(nb_decimals as u32);
+        let scaled = self * num_rational::BigRational::from_integer(scale);
+        let floor = scaled.floor();
+        let remainder = scaled - &floor;
+        Some((floor.to_integer().to_i128()?, remainder))
+    }
+
+    fn to_exact_string(&self) -> String {
+        self.to_rational()
+            .map_or_else(|| self.to_string(), rational_to_exact_string)
+    }
+}
+
+/// Number of decimals of the minor unit of a currency, as given by ISO 4217:
+/// 2 for EUR, whose minor unit is the cent, but 0 for JPY.
+pub fn minor_units(currency: &str) -> u8 {
+    match currency {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW"
+        | "PYG" | "RWF" | "UGX" | "UYI" | "VND" | "VUV" | "XAF" | "XOF"
+        | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        "CLF" | "UYW" => 4,
+        _ => 2,
+    }
+}
+
+/// An amount of money, in a currency if any
+#[derive(Debug, Clone, PartialEq)]
+pub struct Money {
+    value: Rational64,
+    currency: Option<String>,
+}
+
+impl Money {
+    pub fn new(value: Rational64, currency: Option<String>) -> Self {
+        Self { value, currency }
+    }
+
+    /// Round a computed value to the minor unit of the currency, or to
+    /// `DEFAULT_DECIMALS` decimals without currency. Returns `None` if the
+    /// rounded value overflows.
+    pub fn rounded<N: Numeric>(
+        value: &N,
+        currency: Option<String>,
+    ) -> Option<Self> {
+        let nb_decimals =
+            currency.as_deref().map_or(DEFAULT_DECIMALS, minor_units);
+        Some(Self::new(round_money(value, nb_decimals)?, currency))
+    }
+
+    pub fn value(&self) -> Rational64 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: Rational64) {
+        self.value = value;
+    }
+
+    pub fn currency(&self) -> Option<&str> {
+        self.currency.as_deref()
+    }
+
+    /// Number of decimals of the minor unit of the currency, if any
+    pub fn nb_decimals(&self) -> Option<u8> {
+        self.currency().map(minor_units)
+    }
+
+    /// Check whether the value is a whole number of minor units of the
+    /// currency. Values without currency can have any precision.
+    pub fn is_whole(&self) -> bool {
+        self.nb_decimals().is_none_or(|nb_decimals| {
+            // No Panic: scaling a `Rational64` cannot overflow
+            let (_, remainder) = self.value.floor_scaled(nb_decimals).unwrap();
+            remainder == 0.into()
+        })
+    }
+
+    /// Format the value, without the currency. Without `precision`, the
+    /// value is printed with the decimals of the currency, eg `15.00` for
+    /// euros or `1500` for yens.
+    pub fn format_value(&self, precision: Option<u8>) -> String {
+        match (precision, self.nb_decimals()) {
+            (Some(nb_decimals), _) => {
+                rational_to_string(self.value, nb_decimals)
+            }
+            (None, Some(nb_decimals)) => {
+                format_decimal(self.value, &fixed_format(nb_decimals))
+            }
+            (None, None) => rational_to_string(self.value, DEFAULT_DECIMALS),
+        }
+    }
+
+    /// Format the value followed by the currency, eg `15.00 EUR`
+    pub fn format(&self, precision: Option<u8>) -> String {
+        match self.currency() {
+            Some(currency) => {
+                format!("{} {}", self.format_value(precision), currency)
+            }
+            None => self.format_value(precision),
+        }
+    }
+
+    /// Format the value such that it is parsed back exactly. Whole numbers
+    /// of minor units are written with the decimals of the currency.
+    pub fn value_to_exact_string(&self) -> String {
+        match self.nb_decimals() {
+            Some(nb_decimals) if self.is_whole() => {
+                format_decimal(self.value, &fixed_format(nb_decimals))
+            }
+            _ => rational_to_exact_string(self.value),
+        }
+    }
+}
+
+fn fixed_format(nb_decimals: u8) -> DecimalFormat {
+    DecimalFormat {
+        nb_decimals,
+        rounding: RoundingMode::HalfUp,
+        fixed: true,
+    }
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(None))
+    }
+}
+
+/// Round a value to `nb_decimals` decimals, ties away from zero.
+///
+/// Returns `None` if the rounded value does not fit in a `Rational64`.
+pub fn round_money<M: Numeric>(
+    value: &M,
+    nb_decimals: u8,
+) -> Option<Rational64> {
+    use std::convert::TryInto;
+    let nb_decimals = std::cmp::min(nb_decimals, MAX_DECIMALS);
+    let (floor, remainder) = value.floor_scaled(nb_decimals)?;
+    let half = M::from_rational(Rational64::new(1, 2));
+    let round_up =
+        remainder > half || (remainder == half && *value >= M::zero());
+    let scaled = if round_up { floor + 1 } else { floor };
+    Some(Rational64::new(
+        scaled.try_into().ok()?,
+        10_i64.pow(nb_decimals as u32),
+    ))
+}
+
+/// Sum values, returning `None` on overflow
+pub fn checked_sum<'a, M, I>(values: I) -> Option<M>
+where
+    M: Numeric + 'a,
+    I: IntoIterator<Item = &'a M>,
+{
+    values
+        .into_iter()
+        .try_fold(M::zero(), |sum, value| sum.checked_add(value))
+}
+
+#[cfg(test)]
+mod test {
+    use num_rational::Rational64;
+
+    use super::{Money, Numeric};
+
+    #[test]
+    fn floor_scaled() {
+        assert_eq!(
+            Rational64::new(-1, 3).floor_scaled(2),
+            Some((-34, Rational64::new(2, 3)))
+        );
+        assert_eq!(
+            Rational64::new(5, 4).floor_scaled(0),
+            Some((1, Rational64::new(1, 4)))
+        );
+    }
+
+    #[test]
+    fn round_money() {
+        assert_eq!(
+            super::round_money(&Rational64::new(1, 3), 2),
+            Some(Rational64::new(33, 100))
+        );
+        assert_eq!(
+            super::round_money(&Rational64::new(-1, 8), 2),
+            Some(Rational64::new(-13, 100))
+        );
+        assert_eq!(
+            super::round_money(&Rational64::new(5, 2), 0),
+            Some(Rational64::new(3, 1))
+        );
+        // The value fits, but not once scaled by the decimals
+        assert_eq!(
+            super::round_money(&Rational64::from(i64::MAX / 10), 2),
+            None
+        );
+    }
+
+    #[test]
+    fn checked_sum() {
+        let values = [Rational64::new(1, 2), Rational64::new(1, 3)];
+        assert_eq!(super::checked_sum(&values), Some(Rational64::new(5, 6)));
+        let values = [
+            Rational64::new(1, 2_147_483_647),
+            Rational64::new(1, 2_147_483_629),
+            Rational64::new(1, 2_147_483_587),
+        ];
+        assert_eq!(super::checked_sum(&values), None);
+    }
+
+    #[test]
+    fn money() {
+        let eur = Money::new(Rational64::new(31, 2), Some("EUR".to_string()));
+        assert_eq!(eur.to_string(), "15.50 EUR");
+        assert_eq!(eur.format(Some(0)), "16 EUR");
+        assert!(eur.is_whole());
+        let yen = Money::new(1500.into(), Some("JPY".to_string()));
+        assert_eq!(yen.to_string(), "1500 JPY");
+        let too_precise = Money::new(
+            Rational64::new(150_001, 10_000),
+            Some("EUR".to_string()),
+        );
+        assert!(!too_precise.is_whole());
+        assert_eq!(too_precise.value_to_exact_string(), "15.0001");
+        assert_eq!(eur.value_to_exact_string(), "15.50");
+        let none = Money::new(Rational64::new(1, 3), None);
+        assert!(none.is_whole());
+        assert_eq!(none.to_string(), "0.33");
+        assert_eq!(
+            Money::rounded(&Rational64::new(1000, 3), Some("JPY".to_string())),
+            Some(Money::new(333.into(), Some("JPY".to_string())))
+        );
+    }
+}
@@ -1,27 +1,59 @@
 use std::error::Error;
-use std::num::ParseIntError;
 
 use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub};
 
+use crate::decimal::{format_decimal, DecimalFormat};
+
+/// Errors of `rational_from_str`. Positions are indices of characters in
+/// the parsed string, starting from 0.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseRationalError {
-    NumerError(ParseIntError),
-    DenomError(ParseIntError),
     EmptyString,
+    /// A character that cannot appear at this position
+    UnexpectedChar(usize, char),
+    /// The string ends at this position while a number was expected
+    UnexpectedEnd(usize),
+    /// A second decimal separator in a number
+    SeveralSeparators(usize),
+    /// A division by zero, at the position of the division sign
+    DivisionByZero(usize),
+    /// A number or an operation, starting at this position, whose result is
+    /// too large
+    Overflow(usize),
 }
 
 impl std::fmt::Display for ParseRationalError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Positions are displayed starting from 1
         match self {
-            ParseRationalError::NumerError(error) => {
-                write!(f, "Error parsing numerator: {}.", error)
-            }
-            ParseRationalError::DenomError(error) => {
-                write!(f, "Error parsing denominator: {}.", error)
-            }
             ParseRationalError::EmptyString => {
                 write!(f, "Could not parse empty string as a rational.")
             }
+            ParseRationalError::UnexpectedChar(pos, c) => {
+                write!(
+                    f,
+                    "Unexpected character '{}' at position {}.",
+                    c,
+                    pos + 1
+                )
+            }
+            ParseRationalError::UnexpectedEnd(pos) => write!(
+                f,
+                "Expected a number at position {}, found the end.",
+                pos + 1
+            ),
+            ParseRationalError::SeveralSeparators(pos) => write!(
+                f,
+                "Unexpected second decimal separator at position {}.",
+                pos + 1
+            ),
+            ParseRationalError::DivisionByZero(pos) => {
+                write!(f, "Division by zero at position {}.", pos + 1)
+            }
+            ParseRationalError::Overflow(pos) => {
+                write!(f, "Number too large at position {}.", pos + 1)
+            }
         }
     }
 }
@@ -30,82 +62,248 @@ impl Error for ParseRationalError {}
 
 /// Deserialize a rational number from its string representation
 ///
-/// The string representation of a rational is `<integral_part>.<decimal_part>`
+/// The string representation of a rational is a decimal number such as
+/// `12.5`, where the decimal separator can also be a comma: `12,5`. Numbers
+/// can be combined with `+`, `-`, `*`, `/` and parentheses, so that
+/// fractions can be written `1/3`, or amounts be computed: `12.5+3*2`. The
+/// computation is exact.
 pub fn rational_from_str(
     rat_str: &str,
 ) -> Result<Rational64, ParseRationalError> {
-    let mut parts_iter = rat_str.trim().split('.');
-    let integral_part =
-        parts_iter.next().ok_or(ParseRationalError::EmptyString)?;
-    let integral_part: i64 = integral_part
-        .parse()
-        .map_err(|e| ParseRationalError::NumerError(e))?;
-    let integral_part = Rational64::new(integral_part, 1);
-    if let Some(decimal_part) = parts_iter.next() {
-        let nb_decimals = decimal_part.len();
-        if nb_decimals == 0 {
-            return Ok(integral_part);
+    if rat_str.trim().is_empty() {
+        return Err(ParseRationalError::EmptyString);
+    }
+    let mut parser = Parser {
+        chars: rat_str.chars().collect(),
+        pos: 0,
+    };
+    let rat = parser.expr()?;
+    match parser.peek() {
+        Some(c) => Err(ParseRationalError::UnexpectedChar(parser.pos, c)),
+        None => Ok(rat),
+    }
+}
+
+/// Recursive descent parser of arithmetic expressions
+///
+/// The grammar is:
+/// ```text
+/// expr   := term (('+' | '-') term)*
+/// term   := factor (('*' | '/') factor)*
+/// factor := ('+' | '-') factor | '(' expr ')' | number
+/// number := digits? (('.' | ',') digits?)?
+/// ```
+/// where numbers must contain at least one digit.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Get the next character which is not a whitespace
+    fn peek(&mut self) -> Option<char> {
+        while self.pos < self.chars.len()
+            && self.chars[self.pos].is_whitespace()
+        {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expr(&mut self) -> Result<Rational64, ParseRationalError> {
+        let mut rat = self.term()?;
+        loop {
+            let op = self.peek();
+            let op_pos = self.pos;
+            match op {
+                Some('+') => {
+                    self.pos += 1;
+                    rat = rat
+                        .checked_add(&self.term()?)
+                        .ok_or(ParseRationalError::Overflow(op_pos))?;
+                }
+                Some('-') => {
+                    self.pos += 1;
+                    rat = rat
+                        .checked_sub(&self.term()?)
+                        .ok_or(ParseRationalError::Overflow(op_pos))?;
+                }
+                _ => return Ok(rat),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Rational64, ParseRationalError> {
+        let mut rat = self.factor()?;
+        loop {
+            let op = self.peek();
+            let op_pos = self.pos;
+            match op {
+                Some('*') => {
+                    self.pos += 1;
+                    rat = rat
+                        .checked_mul(&self.factor()?)
+                        .ok_or(ParseRationalError::Overflow(op_pos))?;
+                }
+                Some('/') => {
+                    self.pos += 1;
+                    let divisor = self.factor()?;
+                    if divisor == Rational64::new(0, 1) {
+                        return Err(ParseRationalError::DivisionByZero(op_pos));
+                    }
+                    rat = rat
+                        .checked_div(&divisor)
+                        .ok_or(ParseRationalError::Overflow(op_pos))?;
+                }
+                _ => return Ok(rat),
+            }
+        }
+    }
+
+    fn factor(&mut self) -> Result<Rational64, ParseRationalError> {
+        let start = self.pos;
+        match self.peek() {
+            None => Err(ParseRationalError::UnexpectedEnd(self.pos)),
+            Some('+') => {
+                self.pos += 1;
+                self.factor()
+            }
+            Some('-') => {
+                self.pos += 1;
+                Rational64::new(0, 1)
+                    .checked_sub(&self.factor()?)
+                    .ok_or(ParseRationalError::Overflow(start))
+            }
+            Some('(') => {
+                self.pos += 1;
+                let rat = self.expr()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.pos += 1;
+                        Ok(rat)
+                    }
+                    Some(c) => {
+                        Err(ParseRationalError::UnexpectedChar(self.pos, c))
+                    }
+                    None => Err(ParseRationalError::UnexpectedEnd(self.pos)),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' || c == ',' => {
+                self.number()
+            }
+            Some(c) => Err(ParseRationalError::UnexpectedChar(self.pos, c)),
+        }
+    }
+
+    fn number(&mut self) -> Result<Rational64, ParseRationalError> {
+        let start = self.pos;
+        let overflow = ParseRationalError::Overflow(start);
+        let mut numer: i64 = 0;
+        let mut denom: i64 = 1;
+        let mut nb_digits = 0;
+        let mut after_separator = false;
+        while let Some(&c) = self.chars.get(self.pos) {
+            if c == '.' || c == ',' {
+                if after_separator {
+                    return Err(ParseRationalError::SeveralSeparators(
+                        self.pos,
+                    ));
+                }
+                after_separator = true;
+            } else if let Some(digit) = c.to_digit(10) {
+                numer = numer
+                    .checked_mul(10)
+                    .and_then(|numer| numer.checked_add(digit as i64))
+                    .ok_or_else(|| overflow.clone())?;
+                if after_separator {
+                    denom = denom
+                        .checked_mul(10)
+                        .ok_or_else(|| overflow.clone())?;
+                }
+                nb_digits += 1;
+            } else {
+                break;
+            }
+            self.pos += 1;
         }
-        Ok(integral_part
-            + Rational64::new(
-                decimal_part
-                    .parse()
-                    .map_err(|e| ParseRationalError::DenomError(e))?,
-                10_i64.pow(nb_decimals as u32),
-            ))
-    } else {
-        Ok(integral_part)
+        if nb_digits == 0 {
+            // A lone separator
+            return Err(ParseRationalError::UnexpectedChar(
+                start,
+                self.chars[start],
+            ));
+        }
+        Ok(Rational64::new(numer, denom))
     }
 }
 
 /// Convert a rational number to a decimal string representation. Rounding
 /// is performed to the closest decimal number with the required number of
-/// decimals.
+/// decimals, ties away from zero, and trailing zeros are trimmed. See the
+/// `decimal` module for other formats.
 pub fn rational_to_string(rat: Rational64, nb_max_decimals: u8) -> String {
+    format_decimal(
+        rat,
+        &DecimalFormat {
+            nb_decimals: nb_max_decimals,
+            ..Default::default()
+        },
+    )
+}
+
+/// Convert a rational number to a string representation from which
+/// `rational_from_str` gives back exactly the same number.
+///
+/// Decimal numbers are written as such, eg `12.5`, other numbers as a
+/// fraction, eg `1/3`.
+pub fn rational_to_exact_string(rat: Rational64) -> String {
     let rat = rat.reduced();
     if rat.is_integer() {
-        format!("{}", rat.to_integer())
-    } else {
-        let integral_part = rat.trunc();
-        let mut fract_part = rat.fract();
-        if fract_part < Rational64::new(0, 1) {
-            fract_part = -fract_part;
-        };
-        // The fractional part is a fraction m / n where 0 < m < n.
-        // We want to compute a decimal approximation, ie find two integers
-        // p and q such that p / 10^q < m / n and the difference is minimal,
-        // under the constraint that q <= nb_max_decimals.
-        let mut q = nb_max_decimals;
-        let mut p = (*fract_part.numer() as f64 * 10.0_f64.powi(q as i32)
-            / *fract_part.denom() as f64)
-            .round() as i64;
-        while p % 10 == 0 {
-            q -= 1;
-            p = p / 10;
+        return format!("{}", rat.to_integer());
+    }
+    match exact_decimals(rat) {
+        Some((scaled, nb_decimals)) => {
+            let sign = if scaled < 0 { "-" } else { "" };
+            let digits = format!(
+                "{:0width$}",
+                scaled.unsigned_abs(),
+                width = nb_decimals as usize + 1
+            );
+            let (integral, decimal) =
+                digits.split_at(digits.len() - nb_decimals as usize);
+            format!("{}{}.{}", sign, integral, decimal)
         }
-        let integral_part = integral_part.to_integer();
-        // Sign detection works correctly above except in one case: when
-        // the integral part is 0, the sign gets lost as there is no
-        // negative 0 on integers (contrary to floats). This little hack
-        // detects and fixes this edge case
-        let leading_minus = if integral_part == 0 && rat < 0.into() {
-            "-"
-        } else {
-            ""
-        };
-        format!(
-            "{leading_minus}{integral_part}.{decimal_part:0width$}",
-            leading_minus = leading_minus,
-            integral_part = integral_part,
-            decimal_part = p,
-            width = q as usize,
-        )
+        None => format!("{}/{}", rat.numer(), rat.denom()),
     }
 }
 
+/// If a reduced rational number is a decimal number, compute the number of
+/// decimals it needs and the corresponding scaled integer, ie the integer
+/// `p` such that the number is `p / 10^nb_decimals`.
+fn exact_decimals(rat: Rational64) -> Option<(i64, u32)> {
+    let mut denom = *rat.denom();
+    let mut twos = 0;
+    let mut fives = 0;
+    while denom % 2 == 0 {
+        denom /= 2;
+        twos += 1;
+    }
+    while denom % 5 == 0 {
+        denom /= 5;
+        fives += 1;
+    }
+    if denom != 1 {
+        return None;
+    }
+    let nb_decimals = std::cmp::max(twos, fives);
+    let factor = 10_i64.checked_pow(nb_decimals)? / rat.denom();
+    let scaled = (*rat.numer()).checked_mul(factor)?;
+    Some((scaled, nb_decimals))
+}
+
 #[cfg(test)]
 mod test {
-    use super::Rational64;
+    use super::{ParseRationalError, Rational64};
 
     #[test]
     fn rational_from_str() {
@@ -180,5 +378,138 @@ mod test {
             ),
             &"-0.05",
         );
+        // These used to loop forever, as they round to an integer
+        assert_eq!(
+            &super::rational_to_string(Rational64::new(1, 1000), 2),
+            "0"
+        );
+        assert_eq!(
+            &super::rational_to_string(Rational64::new(999, 1000), 2),
+            "1"
+        );
+        assert_eq!(
+            &super::rational_to_string(Rational64::new(-2999, 1000), 2),
+            "-3"
+        );
+    }
+
+    #[test]
+    fn rational_fractions() {
+        assert_eq!(
+            super::rational_from_str("1/3").unwrap(),
+            Rational64::new(1, 3),
+        );
+        assert_eq!(
+            super::rational_from_str(" -7 / 3").unwrap(),
+            Rational64::new(-7, 3),
+        );
+        assert_eq!(
+            super::rational_from_str("1/0"),
+            Err(ParseRationalError::DivisionByZero(1)),
+        );
+        assert_eq!(
+            super::rational_from_str("1/"),
+            Err(ParseRationalError::UnexpectedEnd(2)),
+        );
+    }
+
+    #[test]
+    fn rational_from_str_signs_and_separators() {
+        assert_eq!(
+            super::rational_from_str("-0.5").unwrap(),
+            Rational64::new(-1, 2),
+        );
+        assert_eq!(
+            super::rational_from_str("-1.25").unwrap(),
+            Rational64::new(-5, 4),
+        );
+        assert_eq!(
+            super::rational_from_str("12,50").unwrap(),
+            Rational64::new(25, 2),
+        );
+        assert_eq!(
+            super::rational_from_str(".5").unwrap(),
+            Rational64::new(1, 2),
+        );
+        assert_eq!(
+            super::rational_from_str("1.2.3"),
+            Err(ParseRationalError::SeveralSeparators(3)),
+        );
+        assert_eq!(
+            super::rational_from_str("1,234.5"),
+            Err(ParseRationalError::SeveralSeparators(5)),
+        );
+        assert_eq!(
+            super::rational_from_str("  "),
+            Err(ParseRationalError::EmptyString),
+        );
+        assert_eq!(
+            super::rational_from_str("12€"),
+            Err(ParseRationalError::UnexpectedChar(2, '€')),
+        );
+        assert_eq!(
+            super::rational_from_str("."),
+            Err(ParseRationalError::UnexpectedChar(0, '.')),
+        );
+    }
+
+    #[test]
+    fn rational_from_str_expressions() {
+        assert_eq!(
+            super::rational_from_str("12.5+3*2").unwrap(),
+            Rational64::new(37, 2),
+        );
+        assert_eq!(
+            super::rational_from_str("(10 - 4) / 4 * -2").unwrap(),
+            Rational64::new(-3, 1),
+        );
+        assert_eq!(
+            super::rational_from_str("1/3 + 1/6").unwrap(),
+            Rational64::new(1, 2),
+        );
+        assert_eq!(
+            super::rational_from_str("2 * (3 + 1"),
+            Err(ParseRationalError::UnexpectedEnd(10)),
+        );
+        assert_eq!(
+            super::rational_from_str("2 3"),
+            Err(ParseRationalError::UnexpectedChar(2, '3')),
+        );
+        assert_eq!(
+            super::rational_from_str("1 / (2 - 2)"),
+            Err(ParseRationalError::DivisionByZero(2)),
+        );
+        assert_eq!(
+            super::rational_from_str("99999999999999999999"),
+            Err(ParseRationalError::Overflow(0)),
+        );
+        assert_eq!(
+            super::rational_from_str("1 + 9999999999 * 9999999999"),
+            Err(ParseRationalError::Overflow(15)),
+        );
+    }
+
+    #[test]
+    fn rational_to_exact_string() {
+        for (rat, string) in &[
+            (Rational64::new(10, 1), "10"),
+            (Rational64::new(1, 3), "1/3"),
+            (Rational64::new(-7, 3), "-7/3"),
+            (Rational64::new(21, 2), "10.5"),
+            (Rational64::new(-21, 20), "-1.05"),
+            (Rational64::new(-1, 20), "-0.05"),
+            (Rational64::new(1, 1024), "0.0009765625"),
+            (Rational64::new(300523, 100000), "3.00523"),
+        ] {
+            assert_eq!(&super::rational_to_exact_string(*rat), string);
+            assert_eq!(super::rational_from_str(string).unwrap(), *rat);
+        }
+        // Too many decimals to fit in an i64
+        let tiny = Rational64::new(1, 1 << 62);
+        assert_eq!(
+            super::rational_from_str(&super::rational_to_exact_string(tiny))
+                .unwrap(),
+            tiny
+        );
     }
 }
@@ -0,0 +1,891 @@
+//! Reports of the CLI in machine-readable formats
+//!
+//! Balances, listings of transactions and settlements are gathered in
+//! serializable structures. Their json form follows the schema documented
+//! in the README: fields may be added, but `SCHEMA_VERSION` changes if
+//! existing fields are renamed, removed or change meaning. They can also be
+//! written as tables, in csv, aligned text or markdown.
+
+use std::collections::BTreeMap;
+
+use num_rational::Rational64;
+use num_traits::Zero;
+use serde::Serialize;
+
+use crate::accounts::{ParseError, ParsedAccounts, PurchaseId, User, UserId};
+use crate::combine::Combined;
+use crate::date::Date;
+use crate::decimal::allocate_rounding;
+use crate::money::{minor_units, Amount, Money, Numeric, DEFAULT_DECIMALS};
+use crate::settlement;
+use crate::split::split_to_string;
+
+/// Version of the json schema of `Report`
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How the CLI prints balances, listings and settlements
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Sentences, such as "Eska has a balance of: 15.00 EUR"
+    Text,
+    Json,
+    Csv,
+    /// Aligned columns
+    Table,
+    Markdown,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "table" => Ok(OutputFormat::Table),
+            "markdown" => Ok(OutputFormat::Markdown),
+            _ => Err(format!(
+                "Unknown format {}, use text, json, csv, table or markdown",
+                format
+            )),
+        }
+    }
+}
+
+/// An amount, both exact and rounded
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportAmount {
+    /// The exact value, as a decimal number if possible, else as a
+    /// fraction such as `1/3`
+    pub exact: String,
+    /// The value rounded to the precision of the report, such as `0.33`
+    pub rounded: String,
+}
+
+impl ReportAmount {
+    fn new<N: Numeric>(
+        exact: &N,
+        rounded: &Money,
+        precision: Option<u8>,
+    ) -> Self {
+        ReportAmount {
+            exact: exact.to_exact_string(),
+            rounded: rounded.format_value(precision),
+        }
+    }
+
+    /// An amount which is already rounded, such as a transfer
+    fn exact(money: &Money, precision: Option<u8>) -> Self {
+        Self::new(&money.value(), money, precision)
+    }
+}
+
+/// The balance of a user. Positive balances are owed money.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Balance {
+    pub user: String,
+    pub balance: ReportAmount,
+    /// With combined files, the balance of the user in each file
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subtotals: Vec<Subtotal>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Subtotal {
+    pub file: String,
+    pub balance: ReportAmount,
+}
+
+/// A transfer settling the balances
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SettlementTransfer {
+    pub from: String,
+    pub to: String,
+    pub amount: ReportAmount,
+}
+
+/// An amount paid or consumed by a user
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserAmount {
+    pub user: String,
+    pub amount: ReportAmount,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserShare {
+    pub user: String,
+    /// As in the accounts file: `2` for shares, `=12.5` for a fixed amount,
+    /// `25%` for a percentage
+    pub share: String,
+}
+
+/// A purchase. Its amounts are in its currency.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PurchaseEntry {
+    pub id: PurchaseId,
+    /// As YYYY-MM-DD
+    pub date: Option<String>,
+    pub descr: String,
+    pub category: Option<String>,
+    pub currency: Option<String>,
+    pub amount: ReportAmount,
+    pub payers: Vec<UserAmount>,
+    /// The non-zero shares, empty for itemized purchases
+    pub shares: Vec<UserShare>,
+    /// What each user consumed, empty for ignored purchases
+    pub costs: Vec<UserAmount>,
+    /// Whether the purchase is ignored in the balances, eg as its shares
+    /// sum to zero
+    pub ignored: bool,
+}
+
+/// A transfer, in the base currency
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransferEntry {
+    /// As YYYY-MM-DD
+    pub date: Option<String>,
+    pub descr: String,
+    pub from: String,
+    pub to: String,
+    pub amount: ReportAmount,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Transactions {
+    pub purchases: Vec<PurchaseEntry>,
+    pub transfers: Vec<TransferEntry>,
+}
+
+/// What is reported about a file, or about combined files
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileReport {
+    /// The path of the file, absent for combined files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub base_currency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transactions: Option<Transactions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balances: Option<Vec<Balance>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settlement: Option<Vec<SettlementTransfer>>,
+}
+
+/// Everything printed by one run of the CLI
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub version: u32,
+    pub files: Vec<FileReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined: Option<FileReport>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report {
+            version: SCHEMA_VERSION,
+            files: Vec::new(),
+            combined: None,
+        }
+    }
+}
+
+/// Number of decimals amounts are rounded to: `precision` if any, else the
+/// ones of their currency
+fn nb_decimals(currency: Option<&str>, precision: Option<u8>) -> u8 {
+    precision.unwrap_or_else(|| currency.map_or(DEFAULT_DECIMALS, minor_units))
+}
+
+/// Report balances, rounded such that they still add up to zero
+fn rounded_balances(
+    users: &[String],
+    balances: &[Amount],
+    currency: Option<&str>,
+    precision: Option<u8>,
+) -> Result<Vec<Balance>, ParseError> {
+    let rounded = allocate_rounding(balances, nb_decimals(currency, precision))
+        .ok_or_else(|| ParseError::Overflow("the balances".to_string()))?;
+    Ok(users
+        .iter()
+        .zip(balances.iter().zip(rounded))
+        .map(|(user, (balance, rounded))| Balance {
+            user: user.clone(),
+            balance: ReportAmount::new(
+                balance,
+                &Money::new(rounded, currency.map(String::from)),
+                precision,
+            ),
+            subtotals: Vec::new(),
+        })
+        .collect())
+}
+
+/// The balances of the accounts, at the end of `as_of` if any
+pub fn balances(
+    accounts: &ParsedAccounts,
+    as_of: Option<Date>,
+    precision: Option<u8>,
+) -> Result<Vec<Balance>, ParseError> {
+    let balances = match as_of {
+        Some(date) => accounts.user_balances_as_of(date)?,
+        None => accounts.user_balances()?,
+    };
+    let users: Vec<String> = accounts
+        .users()
+        .iter()
+        .map(|user| user.name.clone())
+        .collect();
+    rounded_balances(&users, &balances, accounts.base_currency(), precision)
+}
+
+fn settlement_transfers(
+    transfers: Vec<settlement::Transfer>,
+    users: &[String],
+    currency: Option<&str>,
+    precision: Option<u8>,
+) -> Vec<SettlementTransfer> {
+    transfers
+        .into_iter()
+        .map(|transfer| SettlementTransfer {
+            from: users[transfer.from].clone(),
+            to: users[transfer.to].clone(),
+            amount: ReportAmount::exact(
+                &Money::new(transfer.amount, currency.map(String::from)),
+                precision,
+            ),
+        })
+        .collect()
+}
+
+/// The transfers settling the rounded balances of the accounts, at the end
+/// of `as_of` if any
+pub fn settlement(
+    accounts: &ParsedAccounts,
+    as_of: Option<Date>,
+    precision: Option<u8>,
+) -> Result<Vec<SettlementTransfer>, ParseError> {
+    let transfers = match as_of {
+        Some(date) => accounts.rounded_settlement_as_of(date, precision)?,
+        None => accounts.rounded_settlement(precision)?,
+    };
+    let users: Vec<String> = accounts
+        .users()
+        .iter()
+        .map(|user| user.name.clone())
+        .collect();
+    Ok(settlement_transfers(
+        transfers,
+        &users,
+        accounts.base_currency(),
+        precision,
+    ))
+}
+
+/// The values of `amounts` in the order of the users
+fn in_users_order<'a, T>(
+    accounts: &'a ParsedAccounts,
+    amounts: &'a BTreeMap<UserId, T>,
+) -> Vec<(&'a User, &'a T)> {
+    accounts
+        .users()
+        .iter()
+        .filter_map(|user| Some((user, amounts.get(&user.id)?)))
+        .collect()
+}
+
+/// The purchases and transfers of the accounts
+pub fn transactions(
+    accounts: &ParsedAccounts,
+    precision: Option<u8>,
+) -> Result<Transactions, ParseError> {
+    let mut purchases = Vec::with_capacity(accounts.purchases().len());
+    for purchase in accounts.purchases() {
+        let currency = purchase.currency().map(String::from);
+        let money = |value: Rational64| Money::new(value, currency.clone());
+        let payers = in_users_order(accounts, purchase.payers())
+            .into_iter()
+            .map(|(user, paid)| UserAmount {
+                user: user.name.clone(),
+                amount: ReportAmount::exact(&money(*paid), precision),
+            })
+            .collect();
+        let shares = if purchase.is_itemized() {
+            Vec::new()
+        } else {
+            in_users_order(accounts, purchase.splits())
+                .into_iter()
+                .filter(|(_, split)| !split.is_zero())
+                .map(|(user, split)| UserShare {
+                    user: user.name.clone(),
+                    share: split_to_string(
+                        *split,
+                        precision.unwrap_or(DEFAULT_DECIMALS),
+                    ),
+                })
+                .collect()
+        };
+        let ignored = accounts.ignored_reason(purchase)?.is_some();
+        let costs = if ignored {
+            Vec::new()
+        } else {
+            let exact = purchase.per_user_costs()?;
+            let rounded = purchase.rounded_per_user_costs(nb_decimals(
+                purchase.currency(),
+                precision,
+            ))?;
+            in_users_order(accounts, &exact)
+                .into_iter()
+                .filter(|(_, cost)| !cost.is_zero())
+                .map(|(user, cost)| UserAmount {
+                    user: user.name.clone(),
+                    amount: ReportAmount::new(
+                        cost,
+                        &money(rounded[&user.id]),
+                        precision,
+                    ),
+                })
+                .collect()
+        };
+        purchases.push(PurchaseEntry {
+            id: purchase.id(),
+            date: purchase.date.map(|date| date.to_string()),
+            descr: purchase.descr.clone(),
+            category: purchase.category.clone(),
+            currency: currency.clone(),
+            amount: ReportAmount::exact(&purchase.amount, precision),
+            payers,
+            shares,
+            costs,
+            ignored,
+        });
+    }
+    let mut transfers = Vec::with_capacity(accounts.transfers().len());
+    for transfer in accounts.transfers() {
+        transfers.push(TransferEntry {
+            date: transfer.date.map(|date| date.to_string()),
+            descr: transfer.descr.clone(),
+            from: accounts.user_name(transfer.from)?.to_string(),
+            to: accounts.user_name(transfer.to)?.to_string(),
+            amount: ReportAmount::exact(
+                &Money::new(
+                    transfer.amount,
+                    accounts.base_currency().map(String::from),
+                ),
+                precision,
+            ),
+        });
+    }
+    Ok(Transactions {
+        purchases,
+        transfers,
+    })
+}
+
+/// The combined balances, with the balance in each file, named by `names`
+pub fn combined_balances(
+    combined: &Combined,
+    names: &[String],
+    precision: Option<u8>,
+) -> Result<Vec<Balance>, ParseError> {
+    let currency = combined.base_currency();
+    let mut balances = rounded_balances(
+        combined.users(),
+        &combined.balances()?,
+        currency,
+        precision,
+    )?;
+    for (name, subtotals) in names.iter().zip(combined.subtotals()) {
+        let subtotals =
+            rounded_balances(combined.users(), subtotals, currency, precision)?;
+        for (balance, subtotal) in balances.iter_mut().zip(subtotals) {
+            balance.subtotals.push(Subtotal {
+                file: name.clone(),
+                balance: subtotal.balance,
+            });
+        }
+    }
+    Ok(balances)
+}
+
+/// The transfers settling the rounded combined balances
+pub fn combined_settlement(
+    combined: &Combined,
+    precision: Option<u8>,
+) -> Result<Vec<SettlementTransfer>, ParseError> {
+    Ok(settlement_transfers(
+        combined.rounded_settlement(precision)?,
+        combined.users(),
+        combined.base_currency(),
+        precision,
+    ))
+}
+
+/// Rows of strings under a header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub header: Vec<String>,
+    /// Whether each column holds numbers, aligned to the right
+    pub numeric: Vec<bool>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new(columns: &[(&str, bool)]) -> Self {
+        Table {
+            header: columns.iter().map(|(name, _)| name.to_string()).collect(),
+            numeric: columns.iter().map(|(_, numeric)| *numeric).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a first column with the same value in every row
+    pub fn with_first_column(mut self, name: &str, value: &str) -> Self {
+        self.header.insert(0, name.to_string());
+        self.numeric.insert(0, false);
+        for row in &mut self.rows {
+            row.insert(0, value.to_string());
+        }
+        self
+    }
+
+    /// Stack tables into a single one. Columns with the same name are
+    /// merged, in the order they first appear, and each row has empty cells
+    /// in the columns of the other tables.
+    fn stack(tables: Vec<Table>) -> Self {
+        let mut stacked = Table::new(&[]);
+        for table in &tables {
+            for (name, numeric) in table.header.iter().zip(&table.numeric) {
+                if !stacked.header.contains(name) {
+                    stacked.header.push(name.clone());
+                    stacked.numeric.push(*numeric);
+                }
+            }
+        }
+        for table in tables {
+            // No Panic: every column of the table is in the stacked header
+            let columns: Vec<usize> = table
+                .header
+                .iter()
+                .map(|name| {
+                    stacked.header.iter().position(|n| n == name).unwrap()
+                })
+                .collect();
+            for row in table.rows {
+                let mut cells = vec![String::new(); stacked.header.len()];
+                for (column, cell) in columns.iter().zip(row) {
+                    cells[*column] = cell;
+                }
+                stacked.rows.push(cells);
+            }
+        }
+        stacked
+    }
+
+    /// Write the table as csv, with a header line
+    pub fn to_csv(&self) -> String {
+        let line = |cells: &[String]| {
+            let cells: Vec<String> =
+                cells.iter().map(|cell| csv_field(cell)).collect();
+            format!("{}\n", cells.join(","))
+        };
+        let mut csv = line(&self.header);
+        for row in &self.rows {
+            csv.push_str(&line(row));
+        }
+        csv
+    }
+
+    /// Write the table with aligned columns
+    pub fn to_text(&self) -> String {
+        let widths = self.widths();
+        let line = |cells: &[String]| {
+            let cells: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .zip(&self.numeric)
+                .map(|((cell, width), numeric)| {
+                    if *numeric {
+                        format!("{:>1$}", cell, width)
+                    } else {
+                        format!("{:1$}", cell, width)
+                    }
+                })
+                .collect();
+            format!("{}\n", cells.join("  ").trim_end())
+        };
+        let rule: Vec<String> =
+            widths.iter().map(|width| "-".repeat(*width)).collect();
+        let mut text = line(&self.header);
+        text.push_str(&line(&rule));
+        for row in &self.rows {
+            text.push_str(&line(row));
+        }
+        text
+    }
+
+    /// Write the table in markdown
+    pub fn to_markdown(&self) -> String {
+        let widths = self.widths();
+        let line = |cells: &[String]| {
+            let cells: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .zip(&self.numeric)
+                .map(|((cell, width), numeric)| {
+                    let cell = cell.replace('|', "\\|");
+                    if *numeric {
+                        format!("{:>1$}", cell, width)
+                    } else {
+                        format!("{:1$}", cell, width)
+                    }
+                })
+                .collect();
+            format!("| {} |\n", cells.join(" | "))
+        };
+        let rule: Vec<String> = widths
+            .iter()
+            .zip(&self.numeric)
+            .map(|(width, numeric)| {
+                if *numeric {
+                    format!("{}:", "-".repeat(width - 1))
+                } else {
+                    "-".repeat(*width)
+                }
+            })
+            .collect();
+        let mut markdown = line(&self.header);
+        markdown.push_str(&line(&rule));
+        for row in &self.rows {
+            markdown.push_str(&line(row));
+        }
+        markdown
+    }
+
+    /// Write the table in `format`, which cannot be json
+    pub fn write(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Csv => self.to_csv(),
+            OutputFormat::Markdown => self.to_markdown(),
+            OutputFormat::Text | OutputFormat::Table | OutputFormat::Json => {
+                self.to_text()
+            }
+        }
+    }
+
+    /// The width of each column, at least 3 for markdown rules
+    fn widths(&self) -> Vec<usize> {
+        self.header
+            .iter()
+            .enumerate()
+            .map(|(column, name)| {
+                self.rows
+                    .iter()
+                    .map(|row| row[column].chars().count())
+                    .chain(vec![name.chars().count(), 3])
+                    .max()
+                    .unwrap_or(3)
+            })
+            .collect()
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Join amounts of users, eg `Eska: 15.00, Shuba: 5.00`
+fn join_amounts(amounts: &[UserAmount]) -> String {
+    let amounts: Vec<String> = amounts
+        .iter()
+        .map(|amount| format!("{}: {}", amount.user, amount.amount.rounded))
+        .collect();
+    amounts.join(", ")
+}
+
+pub fn balances_table(balances: &[Balance]) -> Table {
+    let mut columns = vec![("user", false), ("balance", true), ("exact", true)];
+    let files: Vec<&str> = balances
+        .first()
+        .map(|balance| {
+            balance
+                .subtotals
+                .iter()
+                .map(|subtotal| subtotal.file.as_str())
+                .collect()
+        })
+        .unwrap_or_default();
+    columns.extend(files.iter().map(|file| (*file, true)));
+    let mut table = Table::new(&columns);
+    for balance in balances {
+        let mut row = vec![
+            balance.user.clone(),
+            balance.balance.rounded.clone(),
+            balance.balance.exact.clone(),
+        ];
+        row.extend(
+            balance
+                .subtotals
+                .iter()
+                .map(|subtotal| subtotal.balance.rounded.clone()),
+        );
+        table.rows.push(row);
+    }
+    table
+}
+
+pub fn settlement_table(transfers: &[SettlementTransfer]) -> Table {
+    let mut table =
+        Table::new(&[("from", false), ("to", false), ("amount", true)]);
+    for transfer in transfers {
+        table.rows.push(vec![
+            transfer.from.clone(),
+            transfer.to.clone(),
+            transfer.amount.rounded.clone(),
+        ]);
+    }
+    table
+}
+
+pub fn purchases_table(purchases: &[PurchaseEntry]) -> Table {
+    let mut table = Table::new(&[
+        ("id", true),
+        ("date", false),
+        ("descr", false),
+        ("amount", true),
+        ("currency", false),
+        ("payers", false),
+        ("shares", false),
+        ("costs", false),
+    ]);
+    for purchase in purchases {
+        let shares: Vec<String> = purchase
+            .shares
+            .iter()
+            .map(|share| format!("{}: {}", share.user, share.share))
+            .collect();
+        table.rows.push(vec![
+            purchase.id.to_string(),
+            purchase.date.clone().unwrap_or_default(),
+            purchase.descr.clone(),
+            purchase.amount.rounded.clone(),
+            purchase.currency.clone().unwrap_or_default(),
+            join_amounts(&purchase.payers),
+            shares.join(", "),
+            join_amounts(&purchase.costs),
+        ]);
+    }
+    table
+}
+
+pub fn transfers_table(transfers: &[TransferEntry]) -> Table {
+    let mut table = Table::new(&[
+        ("date", false),
+        ("descr", false),
+        ("from", false),
+        ("to", false),
+        ("amount", true),
+    ]);
+    for transfer in transfers {
+        table.rows.push(vec![
+            transfer.date.clone().unwrap_or_default(),
+            transfer.descr.clone(),
+            transfer.from.clone(),
+            transfer.to.clone(),
+            transfer.amount.rounded.clone(),
+        ]);
+    }
+    table
+}
+
+impl FileReport {
+    /// The tables of the report, with their titles
+    pub fn tables(&self) -> Vec<(&'static str, Table)> {
+        let mut tables = Vec::new();
+        if let Some(transactions) = &self.transactions {
+            tables
+                .push(("Purchases", purchases_table(&transactions.purchases)));
+            tables
+                .push(("Transfers", transfers_table(&transactions.transfers)));
+        }
+        if let Some(balances) = &self.balances {
+            tables.push(("Balances", balances_table(balances)));
+        }
+        if let Some(settlement) = &self.settlement {
+            tables.push(("Settlement", settlement_table(settlement)));
+        }
+        tables
+    }
+
+    /// The tables of the report stacked into a single one, whose first
+    /// columns are the file and the section of each row, such as
+    /// `balances`
+    pub fn stacked_table(&self) -> Table {
+        let file = self.file.as_deref().unwrap_or("combined");
+        let tables = self
+            .tables()
+            .into_iter()
+            .map(|(title, table)| {
+                table
+                    .with_first_column("section", &title.to_lowercase())
+                    .with_first_column("file", file)
+            })
+            .collect();
+        Table::stack(tables)
+    }
+
+    /// Write the tables of the report in `format`, which cannot be json.
+    /// In csv, they are written as a single table, the stacked table.
+    pub fn write(&self, format: OutputFormat) -> String {
+        if format == OutputFormat::Csv {
+            return self.stacked_table().to_csv();
+        }
+        let tables: Vec<String> = self
+            .tables()
+            .into_iter()
+            .map(|(title, table)| match format {
+                OutputFormat::Markdown => {
+                    format!("### {}\n\n{}", title, table.to_markdown())
+                }
+                _ => format!("{}:\n{}", title, table.write(format)),
+            })
+            .collect();
+        tables.join("\n")
+    }
+}
+
+impl Report {
+    /// Write the report in `format`, which cannot be text
+    pub fn write(&self, format: OutputFormat) -> String {
+        if format == OutputFormat::Json {
+            // No Panic: the report only has string keys
+            return serde_json::to_string_pretty(self).unwrap() + "\n";
+        }
+        if format == OutputFormat::Csv {
+            let tables = self
+                .files
+                .iter()
+                .chain(&self.combined)
+                .map(FileReport::stacked_table)
+                .collect();
+            return Table::stack(tables).to_csv();
+        }
+        let files: Vec<String> = self
+            .files
+            .iter()
+            .chain(&self.combined)
+            .map(|file| {
+                let name = file.file.as_deref().unwrap_or("the combined files");
+                match format {
+                    OutputFormat::Markdown => {
+                        format!("## {}\n\n{}", name, file.write(format))
+                    }
+                    _ => format!(
+                        "Processing accounts for {}:\n{}",
+                        name,
+                        file.write(format)
+                    ),
+                }
+            })
+            .collect();
+        files.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{balances, settlement, transactions, OutputFormat, Report};
+    use crate::accounts::ParsedAccounts;
+    use crate::report::FileReport;
+
+    #[test]
+    fn reports() {
+        let yaml = r#"
+base_currency: EUR
+users: [Eska, Shuba, Simon]
+purchases:
+  - descr: wine, red
+    date: 2021-03-01
+    who: Simon
+    amount: "10"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "1"
+      Simon: "1"
+"#;
+        let accounts =
+            ParsedAccounts::from_yaml_reader(yaml.as_bytes()).unwrap();
+        let report = Report {
+            files: vec![FileReport {
+                file: Some("input.yml".to_string()),
+                base_currency: Some("EUR".to_string()),
+                transactions: Some(transactions(&accounts, None).unwrap()),
+                balances: Some(balances(&accounts, None, None).unwrap()),
+                settlement: Some(settlement(&accounts, None, None).unwrap()),
+            }],
+            ..Report::default()
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["version"], 1);
+        assert_eq!(
+            json["files"][0]["balances"][0],
+            serde_json::json!({
+                "user": "Eska",
+                "balance": {"exact": "-10/3", "rounded": "-3.33"},
+            })
+        );
+        assert_eq!(
+            json["files"][0]["balances"][2]["balance"],
+            serde_json::json!({"exact": "20/3", "rounded": "6.66"})
+        );
+        assert_eq!(
+            json["files"][0]["settlement"][0],
+            serde_json::json!({
+                "from": "Eska",
+                "to": "Simon",
+                "amount": {"exact": "3.33", "rounded": "3.33"},
+            })
+        );
+        let purchase = &json["files"][0]["transactions"]["purchases"][0];
+        assert_eq!(purchase["id"], 1);
+        assert_eq!(purchase["date"], "2021-03-01");
+        assert_eq!(purchase["shares"][0]["share"], "1");
+        assert_eq!(purchase["costs"][1]["amount"]["exact"], "10/3");
+        assert_eq!(purchase["ignored"], false);
+
+        let file = &report.files[0];
+        let csv = file.write(OutputFormat::Csv);
+        assert_eq!(
+            csv,
+            "file,section,id,date,descr,amount,currency,payers,shares,costs,\
+             from,to,user,balance,exact\n\
+             input.yml,purchases,1,2021-03-01,\"wine, red\",10.00,EUR,\
+             Simon: 10.00,\"Eska: 1, Shuba: 1, Simon: 1\",\
+             \"Eska: 3.34, Shuba: 3.33, Simon: 3.33\",,,,,\n\
+             input.yml,balances,,,,,,,,,,,Eska,-3.33,-10/3\n\
+             input.yml,balances,,,,,,,,,,,Shuba,-3.33,-10/3\n\
+             input.yml,balances,,,,,,,,,,,Simon,6.66,20/3\n\
+             input.yml,settlement,,,,3.33,,,,,Eska,Simon,,,\n\
+             input.yml,settlement,,,,3.33,,,,,Shuba,Simon,,,\n"
+        );
+        assert_eq!(report.write(OutputFormat::Csv), csv);
+        assert!(file.write(OutputFormat::Table).contains(
+            "Settlement:\n\
+             from   to     amount\n\
+             -----  -----  ------\n\
+             Eska   Simon    3.33\n\
+             Shuba  Simon    3.33\n"
+        ));
+        assert!(file.write(OutputFormat::Markdown).contains(
+            "### Balances\n\
+             \n\
+             | user  | balance | exact |\n\
+             | ----- | ------: | ----: |\n\
+             | Eska  |   -3.33 | -10/3 |\n"
+        ));
+    }
+}
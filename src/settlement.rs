@@ -0,0 +1,235 @@
+//! Computation of the transfers settling a set of balances
+//!
+//! Given the balance of each user, we want to find a list of transfers
+//! between users such that, once all transfers are done, every balance is
+//! zero. Finding the minimal number of transfers amounts to partitioning
+//! the users into as many groups summing to zero as possible, as a group
+//! of `k` users can always be settled in `k - 1` transfers. This is solved
+//! exactly for small groups of users, and approximated by a greedy
+//! algorithm otherwise.
+
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedSub};
+
+/// Maximal number of users with a non-zero balance for which the optimal
+/// settlement is computed. The exact algorithm is exponential in this
+/// number.
+const MAX_EXACT_USERS: usize = 16;
+
+/// A transfer of money between two users
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    /// The user id of the user giving money
+    pub from: usize,
+    /// The user id of the user receiving money
+    pub to: usize,
+    pub amount: Rational64,
+}
+
+/// Compute a list of transfers zeroing all the passed balances.
+///
+/// A positive balance means the user is owed money, a negative one that the
+/// user owes money. The balances are expected to sum to zero. The result is
+/// deterministic: users are considered in the order of their ids when
+/// several choices are possible. Returns `None` if the computations
+/// overflow.
+pub fn settle(balances: &[Rational64]) -> Option<Vec<Transfer>> {
+    let zero = Rational64::new(0, 1);
+    let non_zero: Vec<usize> = balances
+        .iter()
+        .enumerate()
+        .filter(|(_, balance)| **balance != zero)
+        .map(|(uid, _)| uid)
+        .collect();
+    let groups = if non_zero.len() <= MAX_EXACT_USERS {
+        zero_sum_groups(&non_zero, balances)?
+    } else {
+        vec![non_zero]
+    };
+    let mut transfers = Vec::new();
+    for group in groups {
+        settle_group(&group, balances, &mut transfers)?;
+    }
+    Some(transfers)
+}
+
+/// Partition the users into the maximal number of groups whose balances
+/// sum to zero.
+///
+/// This is a dynamic programming over the subsets of users: the best
+/// partition of a subset is obtained by removing one of its users from the
+/// best partition of the subset without it, adding one group if the
+/// subset itself sums to zero. Removing users in the order found by the
+/// dynamic programming yields the groups as consecutive runs.
+fn zero_sum_groups(
+    uids: &[usize],
+    balances: &[Rational64],
+) -> Option<Vec<Vec<usize>>> {
+    let nb_users = uids.len();
+    if nb_users == 0 {
+        return Some(Vec::new());
+    }
+    let nb_subsets = 1_usize << nb_users;
+    let zero = Rational64::new(0, 1);
+    let mut sums = vec![zero; nb_subsets];
+    for subset in 1..nb_subsets {
+        let lowest = subset.trailing_zeros() as usize;
+        sums[subset] =
+            sums[subset & (subset - 1)].checked_add(&balances[uids[lowest]])?;
+    }
+    let mut nb_groups = vec![0_usize; nb_subsets];
+    for subset in 1..nb_subsets {
+        let best = (0..nb_users)
+            .filter(|i| subset & (1 << i) != 0)
+            .map(|i| nb_groups[subset & !(1 << i)])
+            .max()
+            .unwrap_or(0);
+        nb_groups[subset] = best + if sums[subset] == zero { 1 } else { 0 };
+    }
+
+    let mut groups = Vec::with_capacity(nb_groups[nb_subsets - 1]);
+    let mut group = Vec::new();
+    let mut subset = nb_subsets - 1;
+    while subset != 0 {
+        if sums[subset] == zero && !group.is_empty() {
+            groups.push(std::mem::take(&mut group));
+        }
+        let closes_group = if sums[subset] == zero { 1 } else { 0 };
+        // No Panic: by construction one of the users of the subset realizes
+        // the maximum.
+        let i = (0..nb_users)
+            .find(|i| {
+                subset & (1 << i) != 0
+                    && nb_groups[subset & !(1 << i)] + closes_group
+                        == nb_groups[subset]
+            })
+            .unwrap();
+        group.push(uids[i]);
+        subset &= !(1 << i);
+    }
+    groups.push(group);
+    for group in groups.iter_mut() {
+        group.sort_unstable();
+    }
+    groups.sort();
+    Some(groups)
+}
+
+/// Settle a group of users by repeatedly having the biggest debtor pay the
+/// biggest creditor. If the group sums to zero, this takes at most one
+/// transfer less than the number of users in the group. Returns `None` if
+/// the computations overflow.
+fn settle_group(
+    group: &[usize],
+    balances: &[Rational64],
+    transfers: &mut Vec<Transfer>,
+) -> Option<()> {
+    let zero = Rational64::new(0, 1);
+    let mut remaining: Vec<(usize, Rational64)> =
+        group.iter().map(|&uid| (uid, balances[uid])).collect();
+    loop {
+        let creditor = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, balance))| *balance > zero)
+            .max_by(|(_, (uid1, bal1)), (_, (uid2, bal2))| {
+                bal1.cmp(bal2).then(uid2.cmp(uid1))
+            })
+            .map(|(pos, _)| pos);
+        let debtor = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, balance))| *balance < zero)
+            .min_by(|(_, (uid1, bal1)), (_, (uid2, bal2))| {
+                bal1.cmp(bal2).then(uid1.cmp(uid2))
+            })
+            .map(|(pos, _)| pos);
+        let (creditor, debtor) = match (creditor, debtor) {
+            (Some(creditor), Some(debtor)) => (creditor, debtor),
+            _ => return Some(()),
+        };
+        let debt = zero.checked_sub(&remaining[debtor].1)?;
+        let amount = std::cmp::min(remaining[creditor].1, debt);
+        remaining[creditor].1 = remaining[creditor].1.checked_sub(&amount)?;
+        remaining[debtor].1 = remaining[debtor].1.checked_add(&amount)?;
+        transfers.push(Transfer {
+            from: remaining[debtor].0,
+            to: remaining[creditor].0,
+            amount,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{settle, Transfer};
+    use num_rational::Rational64;
+
+    fn apply(
+        balances: &[Rational64],
+        transfers: &[Transfer],
+    ) -> Vec<Rational64> {
+        let mut balances = balances.to_vec();
+        for transfer in transfers {
+            balances[transfer.from] += transfer.amount;
+            balances[transfer.to] -= transfer.amount;
+        }
+        balances
+    }
+
+    #[test]
+    fn settle_input_example() {
+        // Balances of Eska, Shuba and Simon in input.yml
+        let balances: Vec<Rational64> = vec![21.into(), (-24).into(), 3.into()];
+        assert_eq!(
+            settle(&balances),
+            Some(vec![
+                Transfer {
+                    from: 1,
+                    to: 0,
+                    amount: 21.into(),
+                },
+                Transfer {
+                    from: 1,
+                    to: 2,
+                    amount: 3.into(),
+                },
+            ]),
+        );
+    }
+
+    #[test]
+    fn settle_finds_zero_sum_groups() {
+        // Always paying the biggest creditor would need 4 transfers here,
+        // while grouping users 0, 3 & 4 and users 1 & 2 needs only 3.
+        let balances: Vec<Rational64> =
+            vec![6.into(), 4.into(), (-4).into(), (-3).into(), (-3).into()];
+        let transfers = settle(&balances).unwrap();
+        assert_eq!(transfers.len(), 3);
+        assert!(apply(&balances, &transfers)
+            .iter()
+            .all(|b| *b == Rational64::new(0, 1)));
+        assert_eq!(
+            transfers[0],
+            Transfer {
+                from: 3,
+                to: 0,
+                amount: 3.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn settle_nothing() {
+        assert_eq!(settle(&[]), Some(vec![]));
+        assert_eq!(settle(&[0.into(), 0.into()]), Some(vec![]));
+    }
+
+    #[test]
+    fn settle_overflow() {
+        // The balances sum to zero, but not the balances of some subsets
+        let big = Rational64::new(i64::MAX, 1);
+        let balances = vec![big, big, -big, -big];
+        assert_eq!(settle(&balances), None);
+    }
+}
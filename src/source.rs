@@ -0,0 +1,244 @@
+//! Locate the errors of a yaml accounting file in its source
+//!
+//! Deserialization errors come with their line and column, but errors found
+//! once the accounts are deserialized only say which purchase or transfer
+//! they are about. The source is then scanned again to find where that
+//! purchase or transfer is, and where the faulty value is within it.
+
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use crate::accounts::{ParseError, PurchaseLocation, SourceLocation};
+
+/// A line and a column, both starting from 1
+pub type Position = (usize, usize);
+
+/// Where a purchase or a transfer is in the source
+#[derive(Debug, Default)]
+struct Entry {
+    start: Position,
+    end: Position,
+    /// The keys and values of the entry, at any depth
+    scalars: Vec<(String, Position)>,
+}
+
+impl Entry {
+    /// The description of the entry, if it has one
+    fn descr(&self) -> Option<&str> {
+        self.scalars
+            .windows(2)
+            .find(|pair| pair[0].0 == "descr")
+            .map(|pair| pair[1].0.as_str())
+    }
+
+    /// Where an error found in the entry is: at the value it is about if
+    /// any, else at the start of the entry.
+    fn locate(&self, err: &ParseError) -> Position {
+        let value = match err {
+            ParseError::UnknownUser(name)
+            | ParseError::UnknownBeneficiary(name)
+            | ParseError::NegativeShares(name) => name.as_str(),
+            ParseError::UnknownField(path) => {
+                path.rsplit('.').next().unwrap_or_default()
+            }
+            _ => return self.start,
+        };
+        self.scalars
+            .iter()
+            .find(|(scalar, _)| scalar == value)
+            .map_or(self.start, |(_, position)| *position)
+    }
+}
+
+/// The positions of the purchases and transfers of a yaml file
+#[derive(Debug, Default)]
+pub struct YamlIndex {
+    purchases: Vec<Entry>,
+    transfers: Vec<Entry>,
+}
+
+impl YamlIndex {
+    /// Scan a yaml source. If it is not valid yaml, the index is empty.
+    pub fn new(yaml: &str) -> Self {
+        let mut scanner = Scanner::default();
+        let mut parser = Parser::new(yaml.chars());
+        match parser.load(&mut scanner, false) {
+            Ok(()) => scanner.index,
+            Err(_) => YamlIndex::default(),
+        }
+    }
+
+    /// Attach to an error where it occurred, and which purchase it is about
+    /// when it is known by its position only.
+    pub fn locate(&self, err: ParseError) -> ParseError {
+        if let ParseError::Several(errors) = err {
+            return ParseError::Several(
+                errors.into_iter().map(|err| self.locate(err)).collect(),
+            );
+        }
+        if let ParseError::At(location, inner) = err {
+            // Deserialization errors, located by the deserializer
+            let inner = match (location.position, *inner) {
+                (Some((line, _)), inner @ ParseError::YamlError(_)) => {
+                    self.in_purchase_at(line, inner)
+                }
+                (_, inner) => inner,
+            };
+            return ParseError::At(location, Box::new(inner));
+        }
+        let position = match &err {
+            ParseError::InPurchase(location, inner) => self
+                .purchases
+                .get(location.index)
+                .map(|entry| entry.locate(inner)),
+            ParseError::InTransfer(index, inner) => {
+                self.transfers.get(*index).map(|entry| entry.locate(inner))
+            }
+            _ => None,
+        };
+        match position {
+            Some(position) => ParseError::At(
+                SourceLocation {
+                    file: None,
+                    position: Some(position),
+                },
+                Box::new(err),
+            ),
+            None => err,
+        }
+    }
+
+    /// Attach an error to the purchase spanning the given line, if any
+    fn in_purchase_at(&self, line: usize, err: ParseError) -> ParseError {
+        let index = self
+            .purchases
+            .iter()
+            .position(|entry| entry.start.0 <= line && line <= entry.end.0);
+        match index {
+            Some(index) => ParseError::InPurchase(
+                PurchaseLocation {
+                    index,
+                    descr: self.purchases[index]
+                        .descr()
+                        .unwrap_or_default()
+                        .to_string(),
+                },
+                Box::new(err),
+            ),
+            None => err,
+        }
+    }
+}
+
+/// A node of the yaml document being read
+enum Frame {
+    /// A mapping, with the last key read, and whether the next node is a
+    /// key
+    Mapping {
+        key: Option<String>,
+        at_key: bool,
+    },
+    Sequence,
+}
+
+#[derive(Default)]
+struct Scanner {
+    index: YamlIndex,
+    stack: Vec<Frame>,
+    /// Whether a purchase (true) or a transfer (false) is being read
+    in_entry: Option<bool>,
+}
+
+impl Scanner {
+    /// If the node at the top of the stack is the list of purchases or
+    /// transfers, which one
+    fn entries_list(&self) -> Option<bool> {
+        match self.stack.as_slice() {
+            [Frame::Mapping {
+                key: Some(key),
+                at_key: false,
+            }, Frame::Sequence] => match key.as_str() {
+                "purchases" => Some(true),
+                "transfers" => Some(false),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn entry(&mut self, is_purchase: bool) -> Option<&mut Entry> {
+        if is_purchase {
+            self.index.purchases.last_mut()
+        } else {
+            self.index.transfers.last_mut()
+        }
+    }
+
+    fn node_start(&mut self, position: Position) {
+        if let Some(is_purchase) = self.entries_list() {
+            let entry = Entry {
+                start: position,
+                end: position,
+                scalars: Vec::new(),
+            };
+            if is_purchase {
+                self.index.purchases.push(entry);
+            } else {
+                self.index.transfers.push(entry);
+            }
+            self.in_entry = Some(is_purchase);
+        }
+    }
+
+    fn node_end(&mut self, scalar: Option<String>, position: Position) {
+        if let Some(is_purchase) = self.entries_list() {
+            if let Some(entry) = self.entry(is_purchase) {
+                entry.end = position;
+            }
+            self.in_entry = None;
+        }
+        if let Some(Frame::Mapping { key, at_key }) = self.stack.last_mut() {
+            if *at_key {
+                *key = scalar;
+            }
+            *at_key = !*at_key;
+        }
+    }
+}
+
+impl MarkedEventReceiver for Scanner {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        let position = (marker.line(), marker.col() + 1);
+        match event {
+            Event::Scalar(value, ..) => {
+                self.node_start(position);
+                if let Some(is_purchase) = self.in_entry {
+                    if let Some(entry) = self.entry(is_purchase) {
+                        entry.scalars.push((value.clone(), position));
+                    }
+                }
+                self.node_end(Some(value), position);
+            }
+            Event::Alias(_) => {
+                self.node_start(position);
+                self.node_end(None, position);
+            }
+            Event::MappingStart(_) => {
+                self.node_start(position);
+                self.stack.push(Frame::Mapping {
+                    key: None,
+                    at_key: true,
+                });
+            }
+            Event::SequenceStart(_) => {
+                self.node_start(position);
+                self.stack.push(Frame::Sequence);
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.node_end(None, position);
+            }
+            _ => (),
+        }
+    }
+}
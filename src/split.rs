@@ -0,0 +1,295 @@
+//! How the cost of a purchase is split between its beneficiaries
+//!
+//! Each beneficiary of a purchase can either owe a fixed amount, a
+//! percentage of the purchase amount, or a number of shares of what remains
+//! once fixed amounts and percentages are deducted.
+//!
+//! The string representation of a split is:
+//! - `<rational>` for shares, eg `2`,
+//! - `=<rational>` for a fixed amount, eg `=12.5`,
+//! - `<rational>%` for a percentage, eg `25%`.
+
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Zero};
+
+use crate::money::{Amount, Numeric};
+use crate::rational::ParseRationalError;
+use crate::rational::{
+    rational_from_str, rational_to_exact_string, rational_to_string,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+    /// A number of shares of the remainder of the purchase
+    Shares(Rational64),
+    /// A fixed amount
+    Fixed(Rational64),
+    /// A percentage of the purchase amount
+    Percent(Rational64),
+}
+
+/// Reasons why splits cannot be resolved into costs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitError {
+    /// Fixed amounts and percentages add up to more than the amount
+    ExceedsAmount,
+    /// Some amount remains once fixed amounts and percentages are deducted,
+    /// or nobody benefits from the purchase, but there are no shares to
+    /// split it.
+    SharesSumToZero,
+    /// The computations overflow
+    Overflow,
+}
+
+impl Default for Split {
+    fn default() -> Self {
+        Split::Shares(0.into())
+    }
+}
+
+impl Split {
+    /// Check whether this split makes its beneficiary owe nothing
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Split::Shares(val) | Split::Fixed(val) | Split::Percent(val) => {
+                *val == 0.into()
+            }
+        }
+    }
+}
+
+/// Deserialize a split from its string representation
+pub fn split_from_str(split_str: &str) -> Result<Split, ParseRationalError> {
+    let split_str = split_str.trim();
+    if let Some(fixed) = split_str.strip_prefix('=') {
+        Ok(Split::Fixed(rational_from_str(fixed)?))
+    } else if let Some(percent) = split_str.strip_suffix('%') {
+        Ok(Split::Percent(rational_from_str(percent)?))
+    } else {
+        Ok(Split::Shares(rational_from_str(split_str)?))
+    }
+}
+
+/// Convert a split to its string representation
+pub fn split_to_string(split: Split, nb_max_decimals: u8) -> String {
+    match split {
+        Split::Shares(val) => rational_to_string(val, nb_max_decimals),
+        Split::Fixed(val) => {
+            format!("={}", rational_to_string(val, nb_max_decimals))
+        }
+        Split::Percent(val) => {
+            format!("{}%", rational_to_string(val, nb_max_decimals))
+        }
+    }
+}
+
+/// Convert a split to a string representation from which `split_from_str`
+/// gives back exactly the same split
+pub fn split_to_exact_string(split: Split) -> String {
+    match split {
+        Split::Shares(val) => rational_to_exact_string(val),
+        Split::Fixed(val) => format!("={}", rational_to_exact_string(val)),
+        Split::Percent(val) => format!("{}%", rational_to_exact_string(val)),
+    }
+}
+
+/// Compute how much each beneficiary owes for a purchase of `amount`.
+///
+/// Fixed amounts and percentages are deducted first, the remainder is then
+/// split proportionally to the shares.
+pub fn resolve_splits(
+    amount: Rational64,
+    splits: &[Split],
+) -> Result<Vec<Amount>, SplitError> {
+    let zero = Amount::zero();
+    let mut remainder = Amount::from_rational(amount);
+    let amount = Amount::from_rational(amount);
+    let hundred = Amount::from_rational(100.into());
+    let percent_of = |percent: Rational64| {
+        amount
+            .checked_mul(&Amount::from_rational(percent))
+            .and_then(|value| value.checked_div(&hundred))
+            .ok_or(SplitError::Overflow)
+    };
+    let mut total_shares = Amount::zero();
+    for split in splits {
+        match *split {
+            Split::Shares(shares) => {
+                total_shares = total_shares
+                    .checked_add(&Amount::from_rational(shares))
+                    .ok_or(SplitError::Overflow)?
+            }
+            Split::Fixed(fixed) => {
+                remainder = remainder
+                    .checked_sub(&Amount::from_rational(fixed))
+                    .ok_or(SplitError::Overflow)?
+            }
+            Split::Percent(percent) => {
+                remainder = remainder
+                    .checked_sub(&percent_of(percent)?)
+                    .ok_or(SplitError::Overflow)?
+            }
+        }
+    }
+    // The fixed amounts and percentages of a negative amount, such as a
+    // refund, are negative too
+    let exceeds = if amount < zero {
+        remainder > zero
+    } else {
+        remainder < zero
+    };
+    if exceeds
+        && splits
+            .iter()
+            .any(|split| !matches!(split, Split::Shares(_)))
+    {
+        return Err(SplitError::ExceedsAmount);
+    }
+    if total_shares == zero
+        && (remainder != zero || splits.iter().all(Split::is_zero))
+    {
+        return Err(SplitError::SharesSumToZero);
+    }
+    splits
+        .iter()
+        .map(|split| match *split {
+            Split::Shares(shares) if shares == 0.into() => Ok(Amount::zero()),
+            Split::Shares(shares) => remainder
+                .checked_mul(&Amount::from_rational(shares))
+                .and_then(|cost| cost.checked_div(&total_shares))
+                .ok_or(SplitError::Overflow),
+            Split::Fixed(fixed) => Ok(Amount::from_rational(fixed)),
+            Split::Percent(percent) => percent_of(percent),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve_splits, Split, SplitError};
+    use crate::money::{Amount, Numeric};
+    use num_rational::Rational64;
+
+    fn amounts(values: &[i64]) -> Vec<Amount> {
+        values
+            .iter()
+            .map(|value| Amount::from_rational((*value).into()))
+            .collect()
+    }
+
+    #[test]
+    fn split_from_str() {
+        assert_eq!(
+            super::split_from_str("2").unwrap(),
+            Split::Shares(2.into())
+        );
+        assert_eq!(
+            super::split_from_str(" =12.5").unwrap(),
+            Split::Fixed(Rational64::new(25, 2))
+        );
+        assert_eq!(
+            super::split_from_str("25%").unwrap(),
+            Split::Percent(25.into())
+        );
+        assert!(super::split_from_str("=").is_err());
+    }
+
+    #[test]
+    fn split_to_string() {
+        for split_str in &["2", "=12.5", "25%"] {
+            let split = super::split_from_str(split_str).unwrap();
+            assert_eq!(&super::split_to_string(split, 2), split_str);
+        }
+    }
+
+    #[test]
+    fn split_to_exact_string() {
+        for split_str in &["1/3", "=12.5", "100/3%"] {
+            let split = super::split_from_str(split_str).unwrap();
+            assert_eq!(&super::split_to_exact_string(split), split_str);
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        // The first user owes exactly 12, the last one 20% and the rest is
+        // split equally between the others.
+        let splits = [
+            Split::Fixed(12.into()),
+            Split::Shares(1.into()),
+            Split::Shares(1.into()),
+            Split::Percent(20.into()),
+        ];
+        assert_eq!(
+            resolve_splits(50.into(), &splits),
+            Ok(amounts(&[12, 14, 14, 10])),
+        );
+        assert_eq!(
+            resolve_splits(10.into(), &splits),
+            Err(SplitError::ExceedsAmount),
+        );
+        assert_eq!(
+            resolve_splits(
+                12.into(),
+                &[Split::Fixed(12.into()), Split::Shares(0.into())]
+            ),
+            Ok(amounts(&[12, 0])),
+        );
+        assert_eq!(
+            resolve_splits(
+                13.into(),
+                &[Split::Fixed(12.into()), Split::Shares(0.into())]
+            ),
+            Err(SplitError::SharesSumToZero),
+        );
+        assert_eq!(
+            resolve_splits(12.into(), &[Split::Shares(0.into())]),
+            Err(SplitError::SharesSumToZero),
+        );
+    }
+
+    #[test]
+    fn resolve_negative_amount() {
+        // A refund is split as a purchase, with negative costs
+        assert_eq!(
+            resolve_splits(
+                (-10).into(),
+                &[Split::Shares(1.into()), Split::Shares(1.into())]
+            ),
+            Ok(amounts(&[-5, -5])),
+        );
+        let splits = [Split::Fixed((-4).into()), Split::Shares(1.into())];
+        assert_eq!(
+            resolve_splits((-10).into(), &splits),
+            Ok(amounts(&[-4, -6])),
+        );
+        assert_eq!(
+            resolve_splits((-3).into(), &splits),
+            Err(SplitError::ExceedsAmount),
+        );
+        assert_eq!(
+            resolve_splits(
+                (-10).into(),
+                &[Split::Percent(50.into()), Split::Shares(1.into())]
+            ),
+            Ok(amounts(&[-5, -5])),
+        );
+    }
+
+    #[test]
+    fn resolve_overflow() {
+        // The shares of each beneficiary are coprime, the costs have huge
+        // denominators
+        let splits = [
+            Split::Shares(Rational64::new(1, 2_147_483_647)),
+            Split::Shares(Rational64::new(1, 2_147_483_629)),
+            Split::Shares(Rational64::new(1, 2_147_483_587)),
+        ];
+        let costs = resolve_splits(Rational64::new(1, 3), &splits);
+        if cfg!(feature = "big-rational") {
+            assert!(costs.is_ok());
+        } else {
+            assert_eq!(costs, Err(SplitError::Overflow));
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Formats accounts files can be stored in
+//!
+//! Accounts can be written in yaml, json or toml. The format of a file is
+//! given by its extension, and guessed from its contents when the extension
+//! is not one of the known ones, so that the same file can be used by the
+//! CLI, the native GUI and the web GUI.
+
+use std::error::Error;
+use std::path::Path;
+
+use crate::accounts::{ParseError, ParseMode, ParsedAccounts};
+
+/// Format of an accounts file. Yaml is the default, as it is the easiest to
+/// write by hand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileFormat {
+    #[default]
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl FileFormat {
+    pub const ALL: [FileFormat; 3] =
+        [FileFormat::Yaml, FileFormat::Json, FileFormat::Toml];
+
+    /// The format given by the extension of `path`, if it is a known one
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "yml" | "yaml" => Some(FileFormat::Yaml),
+            "json" => Some(FileFormat::Json),
+            "toml" => Some(FileFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// Guess the format of `contents` from its first line which is neither
+    /// blank nor a comment. Json accounts are objects, toml ones start with
+    /// a `key = value` pair or a table header, and anything else is taken
+    /// as yaml.
+    pub fn detect(contents: &str) -> Self {
+        let first_line = contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'));
+        match first_line {
+            Some(line) if line.starts_with('{') => FileFormat::Json,
+            Some(line) if is_toml_line(line) => FileFormat::Toml,
+            _ => FileFormat::Yaml,
+        }
+    }
+
+    /// The format of the file at `path` with the given `contents`
+    pub fn of_file(path: &Path, contents: &str) -> Self {
+        Self::from_path(path).unwrap_or_else(|| Self::detect(contents))
+    }
+
+    /// The usual extension of files in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            FileFormat::Yaml => "yml",
+            FileFormat::Json => "json",
+            FileFormat::Toml => "toml",
+        }
+    }
+}
+
+fn is_toml_line(line: &str) -> bool {
+    if line.starts_with('[') {
+        return true;
+    }
+    match line.find('=') {
+        Some(index) => {
+            let key = line[..index].trim();
+            !key.is_empty()
+                && key.chars().all(|c| {
+                    c.is_ascii_alphanumeric()
+                        || c == '_'
+                        || c == '-'
+                        || c == '.'
+                        || c == '"'
+                })
+        }
+        None => false,
+    }
+}
+
+impl std::fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FileFormat::Yaml => write!(f, "yaml"),
+            FileFormat::Json => write!(f, "json"),
+            FileFormat::Toml => write!(f, "toml"),
+        }
+    }
+}
+
+impl std::str::FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yaml" | "yml" => Ok(FileFormat::Yaml),
+            "json" => Ok(FileFormat::Json),
+            "toml" => Ok(FileFormat::Toml),
+            _ => Err(format!(
+                "Unknown file format {}, expected yaml, json or toml",
+                s
+            )),
+        }
+    }
+}
+
+impl ParsedAccounts {
+    /// Deserialize accounts written in `format`, checked according to `mode`
+    pub fn from_str_as(
+        contents: &str,
+        format: FileFormat,
+        mode: ParseMode,
+    ) -> Result<Self, ParseError> {
+        match format {
+            FileFormat::Yaml => Self::from_yaml_str_with(contents, mode),
+            FileFormat::Json => Self::from_json_with(contents, mode),
+            FileFormat::Toml => Self::from_toml_with(contents, mode),
+        }
+    }
+
+    /// Serialize the accounts in `format`
+    pub fn to_string_as(
+        &self,
+        format: FileFormat,
+    ) -> Result<String, ParseError> {
+        let accounts = self.as_serializable();
+        match format {
+            FileFormat::Yaml => Ok(serde_yaml::to_string(&accounts)?),
+            FileFormat::Json => {
+                Ok(serde_json::to_string_pretty(&accounts)? + "\n")
+            }
+            FileFormat::Toml => {
+                // Going through a value puts the plain fields before the
+                // tables, as toml requires
+                let value = toml::Value::try_from(&accounts)?;
+                Ok(toml::to_string(&value)?)
+            }
+        }
+    }
+}
+
+/// Load the accounts of the file at `path`, in its format. Errors in the
+/// accounts are located in the file.
+pub fn load_file(
+    path: &Path,
+    mode: ParseMode,
+) -> Result<(ParsedAccounts, FileFormat), Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    let format = FileFormat::of_file(path, &contents);
+    let accounts = ParsedAccounts::from_str_as(&contents, format, mode)
+        .map_err(|err| err.in_file(&path.to_string_lossy()))?;
+    Ok((accounts, format))
+}
+
+/// Check that the accounts are valid, then replace the file at `path` with
+/// them, written in `format`. They are written to a temporary file next to
+/// it, which is then renamed, so that the file is never left half written.
+pub fn save_file(
+    path: &Path,
+    accounts: &ParsedAccounts,
+    format: FileFormat,
+    mode: ParseMode,
+) -> Result<(), Box<dyn Error>> {
+    let contents = accounts.to_string_as(format)?;
+    accounts.as_serializable().parse_with(mode)?;
+    let file_name = path.file_name().ok_or("Invalid file name")?;
+    let tmp_path =
+        path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+    std::fs::write(&tmp_path, contents)?;
+    if let Err(err) = std::fs::rename(&tmp_path, path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Apply `edit` to the accounts of the file at `path`, then save them back
+/// in the format of the file. The file is left untouched if the edit fails
+/// or makes the accounts invalid.
+pub fn edit_file<T>(
+    path: &Path,
+    mode: ParseMode,
+    edit: impl FnOnce(&mut ParsedAccounts) -> Result<T, ParseError>,
+) -> Result<T, Box<dyn Error>> {
+    let (mut accounts, format) = load_file(path, mode)?;
+    let result = edit(&mut accounts)?;
+    save_file(path, &accounts, format, mode)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{edit_file, FileFormat};
+    use crate::accounts::{ParseError, ParseMode, ParsedAccounts};
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            FileFormat::from_path(Path::new("trip.YAML")),
+            Some(FileFormat::Yaml)
+        );
+        assert_eq!(
+            FileFormat::from_path(Path::new("trip.toml")),
+            Some(FileFormat::Toml)
+        );
+        assert_eq!(FileFormat::from_path(Path::new("trip")), None);
+        assert_eq!(FileFormat::detect("  {\"users\": []}"), FileFormat::Json);
+        assert_eq!(
+            FileFormat::detect("# Trip\nbase_currency = \"EUR\""),
+            FileFormat::Toml
+        );
+        assert_eq!(
+            FileFormat::detect("[[purchases]]\ndescr = \"a=b\""),
+            FileFormat::Toml
+        );
+        assert_eq!(
+            FileFormat::detect("users: [Eska]\n# a = b"),
+            FileFormat::Yaml
+        );
+
+        let accounts = ParsedAccounts::from_yaml_reader(
+            r#"
+base_currency: EUR
+users: [Eska, Shuba]
+purchases:
+  - descr: train
+    date: 2021-03-04
+    who: Eska
+    amount: "30.5"
+    benef_to_shares:
+      Eska: "1"
+      Shuba: "2"
+transfers:
+  - from: Shuba
+    to: Eska
+    amount: "10"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        for &format in &FileFormat::ALL {
+            let saved = accounts.to_string_as(format).unwrap();
+            assert_eq!(FileFormat::detect(&saved), format);
+            assert_eq!(
+                ParsedAccounts::from_str_as(&saved, format, ParseMode::Strict)
+                    .unwrap(),
+                accounts
+            );
+        }
+
+        let err = ParsedAccounts::from_str_as(
+            "users = [\"Eska\"]\n\n[[purchases]]\ndescr = \"train\"\n\
+             amount = 3\n",
+            FileFormat::Toml,
+            ParseMode::Lenient,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err.root_cause(), ParseError::TomlError(_)),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.position().map(|(line, _)| line), Some(5));
+    }
+
+    #[test]
+    fn edit_files() {
+        let dir = std::env::temp_dir()
+            .join(format!("aaacs-edit-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("trip");
+        let json = r#"{"users": ["Eska", "Shuba"], "purchases": []}"#;
+        std::fs::write(&path, json).unwrap();
+
+        // The file is saved back in its format
+        let id = edit_file(&path, ParseMode::Strict, |accounts| {
+            accounts.add_user("Abel".to_string())?;
+            let id = accounts.add_purchase(
+                "bread".to_string(),
+                "Eska".to_string(),
+                3.into(),
+            )?;
+            accounts.set_purchase_user_share(
+                id,
+                "Eska".to_string(),
+                2.into(),
+            )?;
+            Ok(id)
+        })
+        .unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(FileFormat::detect(&saved), FileFormat::Json);
+        let accounts = ParsedAccounts::from_json(&saved).unwrap();
+        assert_eq!(accounts.user_id("Abel").unwrap().to_string(), "2");
+        assert_eq!(accounts.purchase(id).unwrap().descr, "bread");
+
+        // Failed edits, and edits making the accounts invalid, are not saved
+        let unknown_payer = edit_file(&path, ParseMode::Strict, |accounts| {
+            accounts.add_purchase(
+                "cheese".to_string(),
+                "Eskaa".to_string(),
+                5.into(),
+            )
+        });
+        assert!(unknown_payer.is_err());
+        let negative_share = edit_file(&path, ParseMode::Strict, |accounts| {
+            accounts.set_purchase_user_share(
+                id,
+                "Shuba".to_string(),
+                (-1).into(),
+            )
+        });
+        let err = negative_share.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ParseError>().map(ParseError::root_cause),
+            Some(&ParseError::NegativeShares("Shuba".to_string()))
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["trip"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}